@@ -0,0 +1,338 @@
+use chrono::{DateTime, Datelike, DurationRound, TimeDelta, TimeZone, Utc};
+use serde::Serialize;
+
+use crate::latency::LoadedLatency;
+use crate::results::SpeedResult;
+use crate::storage::ResultCsv;
+use crate::units::Unit;
+
+/// Median of the values, or zero if there are none.
+pub fn median(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let middle = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[middle - 1] + sorted[middle]) / 2.0
+    } else {
+        sorted[middle]
+    }
+}
+
+/// Difference between the largest and the smallest value.
+pub fn spread(values: &[f64]) -> f64 {
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if values.is_empty() {
+        0.0
+    } else {
+        max - min
+    }
+}
+
+/// Averages the speeds of the rows in `unit`, and finds the period they span.
+pub fn get_average(results: Vec<ResultCsv>, unit: Unit) -> Average {
+    let mut dl = 0.0;
+    let mut ul = 0.0;
+    let len = results.len();
+    let mut min_date = DateTime::<Utc>::MAX_UTC;
+    let mut max_date = DateTime::<Utc>::MIN_UTC;
+    for result in results.into_iter() {
+        dl += result.download_in(unit).unwrap_or_default();
+        ul += result.upload_in(unit).unwrap_or_default();
+        if result.date < min_date {
+            min_date = result.date;
+        }
+        if result.date > max_date {
+            max_date = result.date;
+        }
+    }
+    Average {
+        download: dl / len as f64,
+        upload: ul / len as f64,
+        period_in_hours: ((max_date - min_date).num_minutes() as f64 / 60.0).round() as i64,
+    }
+}
+
+/// Combines samples into a single result with their medians and spreads. The
+/// other fields, like the date, the server and `raw_file`, are the first
+/// sample's.
+pub fn aggregate_results(results: Vec<SpeedResult>) -> SpeedResult {
+    let downloads: Vec<f64> = results.iter().map(|result| result.download).collect();
+    let uploads: Vec<f64> = results.iter().map(|result| result.upload).collect();
+    let pings: Vec<f64> = results.iter().map(|result| result.ping).collect();
+    let jitters: Vec<f64> = results.iter().map(|result| result.jitter).collect();
+    let packet_losses: Vec<f64> = results
+        .iter()
+        .filter_map(|result| result.packet_loss)
+        .collect();
+    let samples = results.len();
+    let latencies: Vec<LoadedLatency> = results
+        .iter()
+        .filter_map(|result| result.loaded_latency.clone())
+        .collect();
+    let first = results
+        .into_iter()
+        .next()
+        .expect("There should be at least one result to aggregate.");
+    SpeedResult {
+        download: median(&downloads),
+        upload: median(&uploads),
+        ping: median(&pings),
+        jitter: median(&jitters),
+        packet_loss: if packet_losses.is_empty() {
+            None
+        } else {
+            Some(median(&packet_losses))
+        },
+        samples,
+        download_spread: spread(&downloads),
+        upload_spread: spread(&uploads),
+        ping_spread: spread(&pings),
+        loaded_latency: if latencies.is_empty() {
+            None
+        } else {
+            Some(LoadedLatency::combine(latencies))
+        },
+        ..first
+    }
+}
+
+/// Summary statistics of a set of values.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Stats {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+}
+
+impl Stats {
+    /// The statistics of bandwidths in `from` converted to `to`.
+    pub fn convert(self, from: Unit, to: Unit) -> Stats {
+        Stats {
+            min: from.convert(self.min, to),
+            max: from.convert(self.max, to),
+            mean: from.convert(self.mean, to),
+            median: from.convert(self.median, to),
+            ..self
+        }
+    }
+}
+
+/// Summary statistics of the values, or `None` if there are none.
+pub fn stats(values: &[f64]) -> Option<Stats> {
+    if values.is_empty() {
+        return None;
+    }
+    Some(Stats {
+        count: values.len(),
+        min: values.iter().copied().fold(f64::INFINITY, f64::min),
+        max: values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        mean: values.iter().sum::<f64>() / values.len() as f64,
+        median: median(values),
+    })
+}
+
+/// Periods measurements are grouped by, in UTC.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Period {
+    Hour,
+    Day,
+    Month,
+}
+
+impl Period {
+    /// The start of the period `date` is in.
+    pub fn start(self, date: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Period::Hour => date.duration_trunc(TimeDelta::hours(1)).unwrap_or(date),
+            Period::Day => date.duration_trunc(TimeDelta::days(1)).unwrap_or(date),
+            Period::Month => Utc
+                .with_ymd_and_hms(date.year(), date.month(), 1, 0, 0, 0)
+                .single()
+                .unwrap_or(date),
+        }
+    }
+}
+
+impl std::str::FromStr for Period {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hour" => Ok(Period::Hour),
+            "day" => Ok(Period::Day),
+            "month" => Ok(Period::Month),
+            _ => Err(format!("Unknown period '{}', use hour, day or month.", s)),
+        }
+    }
+}
+
+/// Average of a set of measurements, in the unit given to `get_average`.
+#[derive(PartialEq, Debug, Serialize)]
+pub struct Average {
+    pub upload: f64,
+    pub download: f64,
+    pub period_in_hours: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    mod calculate_average {
+        use chrono::prelude::*;
+        use chrono::Utc;
+        use pretty_assertions::assert_eq;
+
+        use super::super::*;
+        use crate::storage::ResultCsv;
+        use crate::units::Unit;
+        #[test]
+        fn average_calculated_with_single_item() {
+            assert_eq!(
+                Average {
+                    download: 100.0,
+                    upload: 200.0,
+                    period_in_hours: 0
+                },
+                get_average(
+                    vec![ResultCsv {
+                        date: Utc::now(),
+                        speeds_download: Some(100.0),
+                        speeds_upload: Some(200.0),
+                        ..Default::default()
+                    }],
+                    Unit::Mibps
+                )
+            );
+        }
+
+        #[test]
+        fn average_calculated_with_two_items() {
+            assert_eq!(
+                Average {
+                    download: 60.0,
+                    upload: 120.0,
+                    period_in_hours: 2
+                },
+                get_average(
+                    vec![
+                        ResultCsv {
+                            date: Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap(),
+                            speeds_download: Some(20.0),
+                            speeds_upload: Some(40.0),
+                            ..Default::default()
+                        },
+                        ResultCsv {
+                            date: Utc.with_ymd_and_hms(2021, 1, 1, 2, 0, 0).unwrap(),
+                            speeds_download: Some(100.0),
+                            speeds_upload: Some(200.0),
+                            ..Default::default()
+                        }
+                    ],
+                    Unit::Mibps
+                )
+            );
+        }
+
+        #[test]
+        fn average_approximate_hours() {
+            assert_eq!(
+                Average {
+                    download: 1.0,
+                    upload: 1.0,
+                    period_in_hours: 2
+                },
+                get_average(
+                    vec![
+                        ResultCsv {
+                            date: Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap(),
+                            speeds_download: Some(1.0),
+                            speeds_upload: Some(1.0),
+                            ..Default::default()
+                        },
+                        ResultCsv {
+                            date: Utc.with_ymd_and_hms(2021, 1, 1, 1, 59, 0).unwrap(),
+                            speeds_download: Some(1.0),
+                            speeds_upload: Some(1.0),
+                            ..Default::default()
+                        }
+                    ],
+                    Unit::Mibps
+                )
+            );
+        }
+    }
+
+    mod stats_and_periods {
+        use chrono::{TimeZone, Utc};
+        use pretty_assertions::assert_eq;
+
+        use super::super::*;
+
+        #[test]
+        fn stats_of_values() {
+            assert_eq!(
+                Some(Stats {
+                    count: 4,
+                    min: 1.0,
+                    max: 10.0,
+                    mean: 4.0,
+                    median: 2.5
+                }),
+                stats(&[10.0, 1.0, 3.0, 2.0])
+            );
+            assert_eq!(None, stats(&[]));
+        }
+
+        #[test]
+        fn start_of_periods() {
+            let date = Utc.with_ymd_and_hms(2021, 3, 14, 15, 9, 26).unwrap();
+            assert_eq!(
+                Utc.with_ymd_and_hms(2021, 3, 14, 15, 0, 0).unwrap(),
+                Period::Hour.start(date)
+            );
+            assert_eq!(
+                Utc.with_ymd_and_hms(2021, 3, 14, 0, 0, 0).unwrap(),
+                Period::Day.start(date)
+            );
+            assert_eq!(
+                Utc.with_ymd_and_hms(2021, 3, 1, 0, 0, 0).unwrap(),
+                Period::Month.start(date)
+            );
+        }
+    }
+
+    mod median_and_spread {
+        use super::super::*;
+        use pretty_assertions::assert_eq;
+
+        #[test]
+        fn median_of_odd_number_of_values_is_the_middle_value() {
+            assert_eq!(20.0, median(&[30.0, 10.0, 20.0]));
+        }
+
+        #[test]
+        fn median_of_even_number_of_values_is_the_mean_of_the_middle_values() {
+            assert_eq!(25.0, median(&[40.0, 10.0, 30.0, 20.0]));
+        }
+
+        #[test]
+        fn median_of_no_values_is_zero() {
+            assert_eq!(0.0, median(&[]));
+        }
+
+        #[test]
+        fn spread_is_difference_between_max_and_min() {
+            assert_eq!(30.0, spread(&[40.0, 10.0, 30.0, 20.0]));
+        }
+
+        #[test]
+        fn spread_of_single_value_is_zero() {
+            assert_eq!(0.0, spread(&[10.0]));
+        }
+    }
+}
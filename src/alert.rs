@@ -1,211 +1,236 @@
-use chrono::{DateTime, NaiveDateTime, Utc};
-use rev_lines::RevLines;
-use serde::{de, Deserialize, Deserializer};
-use std::fs::File;
-use std::io::prelude::*;
-use std::io::BufReader;
+use crate::aggregate::{get_average, Average};
+use std::path::Path;
 
-use crate::args::Alert;
+use serde::Serialize;
+
+use crate::config::{Alert, Expectation};
+use crate::error::Error;
 use crate::mail;
+use crate::storage::{data_dir, get_latest_results, read_latest_results, ResultCsv};
+use crate::units::Unit;
 
-pub fn alert(alert: Alert) -> Result<(), Option<String>> {
-    let results = match get_latest_results(alert.count)? {
+/// Checks the latest measurements and sends an e-mail if they are bellow expectation
+/// or if too many of them failed.
+pub fn alert(alert: Alert) -> Result<(), Error> {
+    let data_dir = data_dir()?;
+    if let Some(max_failures) = alert.failures {
+        let (checked, failed) =
+            latest_failures(&data_dir, alert.failures_window, alert.interface.as_deref())?;
+        if failed.len() >= max_failures as usize {
+            send_failures_email(&failed, checked, &alert)?;
+        }
+    }
+    if let Some(max_loaded_latency) = alert.max_loaded_latency {
+        let latest = get_latest_results(
+            &data_dir,
+            alert.count,
+            alert.interface.as_deref(),
+            |result| result.loaded_latency().is_some(),
+        )?;
+        if let Some(latest) = latest {
+            let average = average_loaded_latency(&latest);
+            if average > max_loaded_latency {
+                send_latency_email(average, max_loaded_latency, &latest, &alert)?;
+            }
+        }
+    }
+    let results = match get_latest_results(
+        &data_dir,
+        alert.count,
+        alert.interface.as_deref(),
+        |result| !result.failed(),
+    )? {
         Some(results) => results,
         None => {
-            println!("Not enough results to report yet.");
+            info!("Not enough results to report yet.");
             return Ok(());
         }
     };
-    let average = get_average(results);
+    let average = get_average(results, alert.units);
     if average_is_bellow(&average, &alert) {
         send_email(average, alert)?;
     }
     Ok(())
 }
 
-fn send_email(average: Average, alert: Alert) -> Result<(), String> {
+fn send_email(average: Average, alert: Alert) -> Result<(), Error> {
     let message_body = format!(
         "Latest bandwidth measurements found a discrepancy.\n\
-    Expected badwidth was {} mpbs for download and {} mbps for upload.\n\
-    Found {:.2} mbps for download and {:.2} mbps for upload, for the last ~{} hours ({} samples).",
+    Expected badwidth was {} {unit} for download and {} {unit} for upload.\n\
+    Found {:.2} {unit} for download and {:.2} {unit} for upload, for the last ~{} hours ({} samples).",
         alert.expected_download,
         alert.expected_upload,
         average.download,
         average.upload,
         average.period_in_hours,
-        alert.count
+        alert.count,
+        unit = alert.units.symbol()
     );
     mail::send_mail(
         alert.simulate,
-        alert.email,
+        &alert.email,
         "Bandwith bellow expectation",
         &message_body,
-        alert.smtp,
+        &alert.smtp,
     )?;
     Ok(())
 }
 
-fn average_is_bellow(average: &Average, alert: &Alert) -> bool {
-    average.upload < alert.expected_upload * (1.0 - alert.threshold as f64 / 100.0)
-        || average.download < alert.expected_download * (1.0 - alert.threshold as f64 / 100.0)
+/// Average latency under load of the rows, see `ResultCsv::loaded_latency`.
+pub fn average_loaded_latency(results: &[ResultCsv]) -> f64 {
+    let latencies: Vec<f64> = results
+        .iter()
+        .filter_map(ResultCsv::loaded_latency)
+        .collect();
+    latencies.iter().sum::<f64>() / latencies.len().max(1) as f64
 }
 
-fn get_average(results: Vec<ResultCsv>) -> Average {
-    let mut dl = 0.0;
-    let mut ul = 0.0;
-    let len = results.len();
-    let mut min_date = DateTime::<Utc>::MAX_UTC;
-    let mut max_date = DateTime::<Utc>::MIN_UTC;
-    for result in results.into_iter() {
-        dl += result.speeds_download;
-        ul += result.speeds_upload;
-        if result.date < min_date {
-            min_date = result.date;
-        }
-        if result.date > max_date {
-            max_date = result.date;
-        }
-    }
-    Average {
-        download: dl / len as f64,
-        upload: ul / len as f64,
-        period_in_hours: ((max_date - min_date).num_minutes() as f64 / 60.0).round() as i64,
-    }
+fn send_latency_email(
+    average: f64,
+    max_loaded_latency: f64,
+    results: &[ResultCsv],
+    alert: &Alert,
+) -> Result<(), Error> {
+    let measurements = results
+        .iter()
+        .map(|result| {
+            format!(
+                "{}: {:.1} ms idle, {:.1} ms under load, grade {}",
+                alert.timezone.format(&result.date, "%Y/%m/%d %H:%M:%S %:z"),
+                result.idle_latency.unwrap_or_default(),
+                result.loaded_latency().unwrap_or_default(),
+                result.bufferbloat_grade.as_deref().unwrap_or("n/a")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let message_body = format!(
+        "Latency under load averaged {:.1} ms in the latest {} measurements, above the maximum of {} ms.\n\n{}",
+        average,
+        results.len(),
+        max_loaded_latency,
+        measurements
+    );
+    mail::send_mail(
+        alert.simulate,
+        &alert.email,
+        "Latency under load above expectation",
+        &message_body,
+        &alert.smtp,
+    )?;
+    Ok(())
 }
 
-fn get_latest_results(count: u8) -> Result<Option<Vec<ResultCsv>>, String> {
-    let cwd = std::env::current_dir()
-        .map_err(|err| format!("Error when finding current working directory: {}", err))?;
-    let data_dir = cwd.join("data");
-    let file_path = data_dir.join("speed.csv");
-    let file = if file_path.exists() {
-        File::open(&file_path).map_err(|err| format!("Error when opening summary file: {}", err))?
-    } else {
-        return Ok(None);
-    };
-    let mut lines = BufReader::new(&file).lines();
-    let first_line = lines.next();
-    if lines.count() < count as usize {
-        return Ok(None);
-    }
-    let revlines = RevLines::new(&file);
-    let mut last_lines: Vec<String> = Result::from_iter(revlines.take(count as usize))
-        .map_err(|err| format!("Error when opening file: {}", err))?;
-    last_lines.splice(0..0, vec![first_line.unwrap().unwrap()]);
-    let text = last_lines.into_iter().fold(String::new(), |mut str, item| {
-        str.push_str(&item);
-        str.push('\n');
-        str
-    });
-    let mut rdr = csv::Reader::from_reader(text.as_bytes());
-    let results: Vec<ResultCsv> = rdr
-        .deserialize::<ResultCsv>()
-        .filter_map(|result| result.ok())
-        .collect();
-    if results.iter().len() != count as usize {
-        return Err("Error deserializing csv.".to_owned());
-    }
-    Ok(Some(results))
+/// How many measurements were checked for failures and the ones that failed:
+/// the latest `failures_window`, or all of them while there are fewer, so that
+/// failures are also alerted on before the window fills up.
+fn latest_failures(
+    data_dir: &Path,
+    failures_window: u8,
+    interface: Option<&str>,
+) -> Result<(usize, Vec<ResultCsv>), Error> {
+    let latest = read_latest_results(data_dir, failures_window, interface, |_| true)?;
+    let checked = latest.len();
+    Ok((
+        checked,
+        latest.into_iter().filter(ResultCsv::failed).collect(),
+    ))
+}
+
+fn send_failures_email(failed: &[ResultCsv], checked: usize, alert: &Alert) -> Result<(), Error> {
+    let failures = failed
+        .iter()
+        .map(|result| {
+            format!(
+                "{}: {}{} - {}",
+                alert.timezone.format(&result.date, "%Y/%m/%d %H:%M:%S %:z"),
+                result
+                    .error_category
+                    .map_or("unknown".to_owned(), |category| category.to_string()),
+                result.fault.map_or("".to_owned(), |fault| format!(
+                    " (likely cause: {})",
+                    fault.description()
+                )),
+                result.error_message.as_deref().unwrap_or_default()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let message_body = format!(
+        "{} of the latest {} bandwidth measurements failed.\n\n{}",
+        failed.len(),
+        checked,
+        failures
+    );
+    mail::send_mail(
+        alert.simulate,
+        &alert.email,
+        "Bandwith measurements failing",
+        &message_body,
+        &alert.smtp,
+    )?;
+    Ok(())
 }
 
-fn date_time_from_str<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let s: String = Deserialize::deserialize(deserializer)?;
-    let ndt = NaiveDateTime::parse_from_str(&s, "%Y/%m/%d %H:%M:%S").map_err(de::Error::custom)?;
-    Ok(DateTime::from_naive_utc_and_offset(ndt, Utc))
+/// Whether the average is bellow the expected bandwidth, considering the threshold.
+pub fn average_is_bellow(average: &Average, alert: &Alert) -> bool {
+    is_bellow_expectation(
+        average,
+        &Expectation {
+            download: alert.expected_download,
+            upload: alert.expected_upload,
+            threshold: alert.threshold,
+        },
+    )
 }
 
-#[derive(Debug, Deserialize)]
-#[serde()]
-struct ResultCsv {
-    #[serde(deserialize_with = "date_time_from_str")]
-    date: DateTime<Utc>,
-    speeds_download: f64,
-    speeds_upload: f64,
+fn is_bellow_expectation(average: &Average, expectation: &Expectation) -> bool {
+    average.upload < expectation.minimum_upload()
+        || average.download < expectation.minimum_download()
 }
 
-#[derive(PartialEq, Debug)]
-struct Average {
-    upload: f64,
-    download: f64,
-    period_in_hours: i64,
+/// What `alert` finds in the latest measurements, without sending anything.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct AlertState {
+    /// Average of the latest `count` successful measurements, `None` if there
+    /// are not enough of them yet.
+    pub average: Option<Average>,
+    /// Whether the average is bellow expectation, `None` without an average or
+    /// an expectation.
+    pub below_expectation: Option<bool>,
+    /// How many of the latest `failures_window` measurements failed, of all of
+    /// them while there are fewer, `None` if there are none yet.
+    pub failed: Option<usize>,
+}
+
+/// Reads the latest measurements like `alert` does, with the average in `unit`.
+pub fn alert_state(
+    data_dir: &Path,
+    interface: Option<&str>,
+    expectation: Option<&Expectation>,
+    count: u8,
+    failures_window: u8,
+    unit: Unit,
+) -> Result<AlertState, Error> {
+    let (checked, failed) = latest_failures(data_dir, failures_window, interface)?;
+    let failed = (checked > 0).then_some(failed.len());
+    let average = get_latest_results(data_dir, count, interface, |result| !result.failed())?
+        .map(|results| get_average(results, unit));
+    let below_expectation = match (&average, expectation) {
+        (Some(average), Some(expectation)) => Some(is_bellow_expectation(average, expectation)),
+        _ => None,
+    };
+    Ok(AlertState {
+        average,
+        below_expectation,
+        failed,
+    })
 }
 
 #[cfg(test)]
 mod tests {
-    mod calculate_average {
-        use chrono::prelude::*;
-        use chrono::Utc;
-        use pretty_assertions::assert_eq;
-
-        use super::super::*;
-        #[test]
-        fn average_calculated_with_single_item() {
-            assert_eq!(
-                Average {
-                    download: 100.0,
-                    upload: 200.0,
-                    period_in_hours: 0
-                },
-                get_average(vec![ResultCsv {
-                    date: Utc::now(),
-                    speeds_download: 100.0,
-                    speeds_upload: 200.0,
-                }])
-            );
-        }
-
-        #[test]
-        fn average_calculated_with_two_items() {
-            assert_eq!(
-                Average {
-                    download: 60.0,
-                    upload: 120.0,
-                    period_in_hours: 2
-                },
-                get_average(vec![
-                    ResultCsv {
-                        date: Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap(),
-                        speeds_download: 20.0,
-                        speeds_upload: 40.0,
-                    },
-                    ResultCsv {
-                        date: Utc.with_ymd_and_hms(2021, 1, 1, 2, 0, 0).unwrap(),
-                        speeds_download: 100.0,
-                        speeds_upload: 200.0,
-                    }
-                ])
-            );
-        }
-
-        #[test]
-        fn average_approximate_hours() {
-            assert_eq!(
-                Average {
-                    download: 1.0,
-                    upload: 1.0,
-                    period_in_hours: 2
-                },
-                get_average(vec![
-                    ResultCsv {
-                        date: Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap(),
-                        speeds_download: 1.0,
-                        speeds_upload: 1.0,
-                    },
-                    ResultCsv {
-                        date: Utc.with_ymd_and_hms(2021, 1, 1, 1, 59, 0).unwrap(),
-                        speeds_download: 1.0,
-                        speeds_upload: 1.0,
-                    }
-                ])
-            );
-        }
-    }
-
     mod check_average {
-        use crate::args::Smtp;
+        use crate::config::Smtp;
 
         use super::super::*;
         #[test]
@@ -263,6 +288,8 @@ mod tests {
                 threshold,
                 expected_download: download,
                 expected_upload: upload,
+                units: crate::units::Unit::Mbps,
+                timezone: crate::timezone::Zone::Utc,
                 email: "".to_owned(),
                 smtp: Smtp {
                     email: "".to_owned(),
@@ -270,6 +297,10 @@ mod tests {
                     port: 0,
                     credentials: None,
                 },
+                interface: None,
+                failures: None,
+                failures_window: 8,
+                max_loaded_latency: None,
             }
         }
     }
@@ -0,0 +1,521 @@
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::aggregate::Period;
+use crate::alert::alert_state;
+use crate::config::Serve;
+use crate::error::Error;
+use crate::output::round;
+use crate::rollup::{self, Rollup};
+use crate::server::{Request, Response};
+use crate::storage::{format_date, get_latest_results, read_results, ResultCsv};
+use crate::units::STORED_UNIT;
+
+const DEFAULT_PER_PAGE: usize = 100;
+const MAX_PER_PAGE: usize = 1000;
+
+/// Dates from the `from` and `to` query parameters.
+type Range = (Option<DateTime<Utc>>, Option<DateTime<Utc>>);
+
+/// A row of the summary file as returned by the API, with bandwidths in the
+/// unit set by `serve --units`.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ResultJson {
+    pub date: String,
+    pub status: &'static str,
+    pub download: Option<f64>,
+    pub upload: Option<f64>,
+    pub bandwidth_unit: &'static str,
+    pub ping: Option<f64>,
+    pub jitter: Option<f64>,
+    pub latency_unit: &'static str,
+    pub packet_loss: Option<f64>,
+    pub server_id: Option<u32>,
+    pub server_host: Option<String>,
+    pub server_location: Option<String>,
+    pub server_country: Option<String>,
+    pub isp: Option<String>,
+    pub client_ip: Option<String>,
+    pub interface: Option<String>,
+    pub samples: Option<usize>,
+    pub error_category: Option<String>,
+    pub error_message: Option<String>,
+    /// Path of the JSON written by Ookla's CLI, for successful measurements.
+    pub raw: Option<String>,
+}
+
+impl ResultJson {
+    pub fn new(row: &ResultCsv, serve: &Serve) -> ResultJson {
+        ResultJson {
+            date: format_date(&row.date),
+            status: if row.failed() { "failed" } else { "ok" },
+            download: row.download_in(serve.units).map(|value| round(value, 2)),
+            upload: row.upload_in(serve.units).map(|value| round(value, 2)),
+            bandwidth_unit: serve.units.symbol(),
+            ping: row.ping,
+            jitter: row.jitter,
+            latency_unit: "ms",
+            packet_loss: row.packet_loss,
+            server_id: row.server_id,
+            server_host: row.server_host.clone(),
+            server_location: row.server_location.clone(),
+            server_country: row.server_country.clone(),
+            isp: row.client_isp.clone(),
+            client_ip: row.client_ip.clone(),
+            interface: row.interface.clone(),
+            samples: row.samples,
+            error_category: row.error_category.map(|category| category.to_string()),
+            error_message: row.error_message.clone(),
+            raw: (!row.failed()).then(|| format!("/raw/{}", row.raw_file_name())),
+        }
+    }
+}
+
+/// Answers the requests to the JSON API, or returns `None` if the path is not
+/// one of its endpoints.
+pub fn route(request: &Request, serve: &Serve, data_dir: &Path) -> Option<Result<Response, Error>> {
+    let response = match request.path.as_str() {
+        "/results" => results(request, serve, data_dir),
+        "/results/latest" => latest_result(serve, data_dir),
+        "/stats" => period_stats(request, serve, data_dir),
+        "/alerts/state" => state(serve, data_dir),
+        _ => return None,
+    };
+    Some(response)
+}
+
+/// `GET /results?from=&to=&page=&per_page=`, the measurements in the range,
+/// from the oldest to the newest.
+fn results(request: &Request, serve: &Serve, data_dir: &Path) -> Result<Response, Error> {
+    let (from, to) = match parse_range(request) {
+        Ok(range) => range,
+        Err(response) => return Ok(response),
+    };
+    let rows = rows_in_range(serve, data_dir, from, to)?;
+    let page = match query_number(request, "page", 1) {
+        Ok(page) if page > 0 => page,
+        _ => return Ok(bad_request("page has to be a number greater than 0.")),
+    };
+    let per_page = match query_number(request, "per_page", DEFAULT_PER_PAGE) {
+        Ok(per_page) if per_page > 0 && per_page <= MAX_PER_PAGE => per_page,
+        _ => {
+            return Ok(bad_request(&format!(
+                "per_page has to be a number between 1 and {}.",
+                MAX_PER_PAGE
+            )))
+        }
+    };
+    let results: Vec<ResultJson> = rows
+        .iter()
+        .skip((page - 1).saturating_mul(per_page))
+        .take(per_page)
+        .map(|row| ResultJson::new(row, serve))
+        .collect();
+    Ok(Response::json(
+        json!({
+            "page": page,
+            "per_page": per_page,
+            "total": rows.len(),
+            "pages": rows.len().div_ceil(per_page),
+            "results": results,
+        })
+        .to_string(),
+    ))
+}
+
+/// `GET /results/latest`, the newest measurement, successful or not.
+fn latest_result(serve: &Serve, data_dir: &Path) -> Result<Response, Error> {
+    match get_latest_results(data_dir, 1, serve.interface.as_deref(), |_| true)? {
+        Some(rows) => Ok(Response::json(to_json(&ResultJson::new(&rows[0], serve))?)),
+        None => Ok(json_error(404, "There are no measurements yet.")),
+    }
+}
+
+/// `GET /stats?period=hour|day|month&from=&to=`, statistics of the measurements
+/// grouped by period, in UTC. They come from the rollup files, unless only the
+/// measurements of an interface are served.
+fn period_stats(request: &Request, serve: &Serve, data_dir: &Path) -> Result<Response, Error> {
+    let period_name = request.query.get("period").map_or("day", String::as_str);
+    let period = match period_name.parse::<Period>() {
+        Ok(period) => period,
+        Err(message) => return Ok(bad_request(&message)),
+    };
+    let (from, to) = match parse_range(request) {
+        Ok(range) => range,
+        Err(response) => return Ok(response),
+    };
+    let rollups = match serve.interface {
+        None => rollup::read(data_dir, period)?
+            .into_iter()
+            .map(|rollup| rollup.convert(STORED_UNIT, serve.units))
+            .collect(),
+        Some(_) => {
+            let rows = rows_in_range(serve, data_dir, None, None)?;
+            rollup::compute(&rows.iter().collect::<Vec<_>>(), period, serve.units)
+        }
+    };
+    let stats: Vec<Rollup> = rollups
+        .into_iter()
+        .filter(|rollup| {
+            from.is_none_or(|from| rollup.start >= period.start(from))
+                && to.is_none_or(|to| rollup.start < to)
+        })
+        .collect();
+    Ok(Response::json(
+        json!({
+            "period": period_name,
+            "bandwidth_unit": serve.units.symbol(),
+            "latency_unit": "ms",
+            "stats": stats,
+        })
+        .to_string(),
+    ))
+}
+
+/// `GET /alerts/state`, what `alert` would find with the same expectation,
+/// `--count` and `--failures-window`.
+fn state(serve: &Serve, data_dir: &Path) -> Result<Response, Error> {
+    let state = alert_state(
+        data_dir,
+        serve.interface.as_deref(),
+        serve.expectation.as_ref(),
+        serve.count,
+        serve.failures_window,
+        serve.units,
+    )?;
+    let expectation = serve.expectation.as_ref().map(|expectation| {
+        json!({
+            "download": expectation.download,
+            "upload": expectation.upload,
+            "threshold": expectation.threshold,
+            "minimum_download": expectation.minimum_download(),
+            "minimum_upload": expectation.minimum_upload(),
+        })
+    });
+    Ok(Response::json(
+        json!({
+            "bandwidth_unit": serve.units.symbol(),
+            "expectation": expectation,
+            "count": serve.count,
+            "average": state.average,
+            "below_expectation": state.below_expectation,
+            "failures_window": serve.failures_window,
+            "failed": state.failed,
+        })
+        .to_string(),
+    ))
+}
+
+/// The `from` and `to` query parameters, or the response to a request with
+/// invalid ones.
+fn parse_range(request: &Request) -> Result<Range, Response> {
+    let mut range = [None, None];
+    for (bound, name) in range.iter_mut().zip(["from", "to"]) {
+        if let Some(value) = request.query.get(name) {
+            match DateTime::parse_from_rfc3339(value) {
+                Ok(date) => *bound = Some(date.with_timezone(&Utc)),
+                Err(_) => {
+                    return Err(bad_request(&format!(
+                        "{} has to be a date like 2021-01-31T00:00:00Z.",
+                        name
+                    )))
+                }
+            }
+        }
+    }
+    let [from, to] = range;
+    Ok((from, to))
+}
+
+/// The rows of the interface being served from `from` (inclusive) to `to`.
+fn rows_in_range(
+    serve: &Serve,
+    data_dir: &Path,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Result<Vec<ResultCsv>, Error> {
+    Ok(read_results(data_dir)?
+        .into_iter()
+        .filter(|row| {
+            (serve.interface.is_none() || row.interface == serve.interface)
+                && from.is_none_or(|from| row.date >= from)
+                && to.is_none_or(|to| row.date < to)
+        })
+        .collect())
+}
+
+fn query_number(request: &Request, name: &str, default: usize) -> Result<usize, ()> {
+    match request.query.get(name) {
+        Some(value) => value.parse::<usize>().map_err(|_| ()),
+        None => Ok(default),
+    }
+}
+
+fn to_json(value: &impl Serialize) -> Result<String, Error> {
+    serde_json::to_string(value).map_err(|err| Error::storage("Could not serialize results", err))
+}
+
+fn bad_request(message: &str) -> Response {
+    json_error(400, message)
+}
+
+fn json_error(status: u16, message: &str) -> Response {
+    Response {
+        status,
+        ..Response::json(json!({ "error": message }).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Expectation;
+    use crate::test_support::create_data_dir_with_summary;
+    use crate::units::Unit;
+    use pretty_assertions::assert_eq;
+    use serde_json::Value;
+    use std::collections::HashMap;
+    use std::fs;
+
+    const SUMMARY: &str = "\
+date,ping,speeds_download,speeds_upload,client_ip,client_isp,server_host,server_location,server_country,server_id,interface,samples,status,error_category,error_message,speeds_unit,jitter,packet_loss
+2021/01/01 10:00:00,5.5,100,50,1.2.3.4,Some ISP,speed.example.com,Lisbon,Portugal,1234,eth0,1,ok,,,mbps,0.5,0
+2021/01/01 22:00:00,6.5,80,30,1.2.3.4,Some ISP,speed.example.com,Lisbon,Portugal,1234,eth0,1,ok,,,mbps,0.7,0
+2021/01/02 10:00:00,,,,,,,,,,eth0,,failed,timeout,Timed out after 120s,,,
+2021/01/02 11:00:00,4.0,90,40,1.2.3.4,Some ISP,speed.example.com,Lisbon,Portugal,1234,wlan0,1,ok,,,mbps,0.2,0
+";
+
+    fn serve() -> Serve {
+        Serve {
+            address: "127.0.0.1:0".to_owned(),
+            units: Unit::Mbps,
+            interface: Some("eth0".to_owned()),
+            expectation: Some(Expectation {
+                download: 100.0,
+                upload: 50.0,
+                threshold: 20,
+            }),
+            count: 2,
+            failures_window: 3,
+        }
+    }
+
+    fn get(data_dir: &Path, target: &str) -> (u16, Value) {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let request = Request {
+            method: "GET".to_owned(),
+            path: path.to_owned(),
+            query: url::form_urlencoded::parse(query.as_bytes())
+                .into_owned()
+                .collect::<HashMap<_, _>>(),
+        };
+        let response = route(&request, &serve(), data_dir).unwrap().unwrap();
+        assert_eq!("application/json", response.content_type);
+        (
+            response.status,
+            serde_json::from_slice(&response.body).unwrap(),
+        )
+    }
+
+    #[test]
+    fn results_are_paginated_and_filtered_by_date() {
+        let data_dir = create_data_dir_with_summary("api-results", SUMMARY);
+        assert_eq!(
+            (
+                200,
+                json!({
+                    "page": 1,
+                    "per_page": 2,
+                    "total": 3,
+                    "pages": 2,
+                    "results": [
+                        {
+                            "date": "2021-01-01T10:00:00Z",
+                            "status": "ok",
+                            "download": 100.0,
+                            "upload": 50.0,
+                            "bandwidth_unit": "Mbps",
+                            "ping": 5.5,
+                            "jitter": 0.5,
+                            "latency_unit": "ms",
+                            "packet_loss": 0.0,
+                            "server_id": 1234,
+                            "server_host": "speed.example.com",
+                            "server_location": "Lisbon",
+                            "server_country": "Portugal",
+                            "isp": "Some ISP",
+                            "client_ip": "1.2.3.4",
+                            "interface": "eth0",
+                            "samples": 1,
+                            "error_category": null,
+                            "error_message": null,
+                            "raw": "/raw/20210101100000.json"
+                        },
+                        {
+                            "date": "2021-01-01T22:00:00Z",
+                            "status": "ok",
+                            "download": 80.0,
+                            "upload": 30.0,
+                            "bandwidth_unit": "Mbps",
+                            "ping": 6.5,
+                            "jitter": 0.7,
+                            "latency_unit": "ms",
+                            "packet_loss": 0.0,
+                            "server_id": 1234,
+                            "server_host": "speed.example.com",
+                            "server_location": "Lisbon",
+                            "server_country": "Portugal",
+                            "isp": "Some ISP",
+                            "client_ip": "1.2.3.4",
+                            "interface": "eth0",
+                            "samples": 1,
+                            "error_category": null,
+                            "error_message": null,
+                            "raw": "/raw/20210101220000.json"
+                        }
+                    ]
+                })
+            ),
+            get(&data_dir, "/results?per_page=2")
+        );
+        let (_, page) = get(&data_dir, "/results?per_page=2&page=2");
+        assert_eq!("failed", page["results"][0]["status"]);
+        assert_eq!("timeout", page["results"][0]["error_category"]);
+        assert_eq!(Value::Null, page["results"][0]["raw"]);
+        let (_, range) = get(
+            &data_dir,
+            "/results?from=2021-01-01T12:00:00%2B01:00&to=2021-01-02T10:00:00Z",
+        );
+        assert_eq!(1, range["total"]);
+        assert_eq!("2021-01-01T22:00:00Z", range["results"][0]["date"]);
+        assert_eq!(400, get(&data_dir, "/results?from=yesterday").0);
+        assert_eq!(400, get(&data_dir, "/results?per_page=0").0);
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn latest_result_is_the_newest_of_the_interface() {
+        let data_dir = create_data_dir_with_summary("api-latest", SUMMARY);
+        let (status, latest) = get(&data_dir, "/results/latest");
+        assert_eq!(200, status);
+        assert_eq!("2021-01-02T10:00:00Z", latest["date"]);
+        assert_eq!("Timed out after 120s", latest["error_message"]);
+        fs::remove_file(data_dir.join("speed.csv")).unwrap();
+        assert_eq!(
+            (404, json!({ "error": "There are no measurements yet." })),
+            get(&data_dir, "/results/latest")
+        );
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn stats_are_grouped_by_period() {
+        let data_dir = create_data_dir_with_summary("api-stats", SUMMARY);
+        assert_eq!(
+            (
+                200,
+                json!({
+                    "period": "day",
+                    "bandwidth_unit": "Mbps",
+                    "latency_unit": "ms",
+                    "stats": [
+                        {
+                            "start": "2021-01-01T00:00:00Z",
+                            "measurements": 2,
+                            "failed": 0,
+                            "download": {"count": 2, "min": 80.0, "max": 100.0, "mean": 90.0, "median": 90.0},
+                            "upload": {"count": 2, "min": 30.0, "max": 50.0, "mean": 40.0, "median": 40.0},
+                            "ping": {"count": 2, "min": 5.5, "max": 6.5, "mean": 6.0, "median": 6.0}
+                        },
+                        {
+                            "start": "2021-01-02T00:00:00Z",
+                            "measurements": 1,
+                            "failed": 1,
+                            "download": null,
+                            "upload": null,
+                            "ping": null
+                        }
+                    ]
+                })
+            ),
+            get(&data_dir, "/stats?period=day")
+        );
+        let (_, months) = get(&data_dir, "/stats?period=month");
+        assert_eq!(3, months["stats"][0]["measurements"]);
+        assert_eq!(400, get(&data_dir, "/stats?period=year").0);
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn stats_of_every_interface_come_from_rollups() {
+        let data_dir = create_data_dir_with_summary("api-rollups", SUMMARY);
+        rollup::rebuild(&data_dir).unwrap();
+        let request = Request {
+            method: "GET".to_owned(),
+            path: "/stats".to_owned(),
+            query: HashMap::from([
+                ("period".to_owned(), "day".to_owned()),
+                ("from".to_owned(), "2021-01-02T12:00:00Z".to_owned()),
+            ]),
+        };
+        let serve = Serve {
+            interface: None,
+            units: Unit::Mibps,
+            ..serve()
+        };
+        let response = route(&request, &serve, &data_dir).unwrap().unwrap();
+        let stats: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!("Mibps", stats["bandwidth_unit"]);
+        assert_eq!(1, stats["stats"].as_array().unwrap().len());
+        assert_eq!("2021-01-02T00:00:00Z", stats["stats"][0]["start"]);
+        assert_eq!(2, stats["stats"][0]["measurements"]);
+        assert_eq!(1, stats["stats"][0]["failed"]);
+        assert_eq!(
+            Unit::Mbps.convert(90.0, Unit::Mibps),
+            stats["stats"][0]["download"]["median"]
+        );
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn alert_state_matches_alert() {
+        let data_dir = create_data_dir_with_summary("api-state", SUMMARY);
+        assert_eq!(
+            (
+                200,
+                json!({
+                    "bandwidth_unit": "Mbps",
+                    "expectation": {
+                        "download": 100.0,
+                        "upload": 50.0,
+                        "threshold": 20,
+                        "minimum_download": 80.0,
+                        "minimum_upload": 40.0
+                    },
+                    "count": 2,
+                    "average": {"download": 90.0, "upload": 40.0, "period_in_hours": 12},
+                    "below_expectation": false,
+                    "failures_window": 3,
+                    "failed": 1
+                })
+            ),
+            get(&data_dir, "/alerts/state")
+        );
+        let serve = Serve {
+            failures_window: 100,
+            ..serve()
+        };
+        let request = Request {
+            method: "GET".to_owned(),
+            path: "/alerts/state".to_owned(),
+            query: HashMap::new(),
+        };
+        let response = route(&request, &serve, &data_dir).unwrap().unwrap();
+        let state: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(1, state["failed"], "fewer measurements than the window");
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+}
@@ -0,0 +1,67 @@
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io;
+
+/// A file to be added to a bundle.
+pub struct Entry {
+    pub name: String,
+    /// Modification time, in seconds since the Unix epoch.
+    pub mtime: u64,
+    pub contents: Vec<u8>,
+}
+
+/// The entries as a gzip compressed tar archive, which can be opened with the
+/// usual tools.
+pub fn tar_gz(entries: &[Entry]) -> io::Result<Vec<u8>> {
+    let mut builder = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+    for entry in entries {
+        let mut header = tar::Header::new_ustar();
+        header.set_size(entry.contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(entry.mtime);
+        builder.append_data(&mut header, &entry.name, entry.contents.as_slice())?;
+    }
+    builder.into_inner()?.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use pretty_assertions::assert_eq;
+    use std::io::Read;
+
+    #[test]
+    fn writes_a_tar_gz_archive() {
+        let json = crate::run::SIMULATED_RESULT.repeat(40);
+        let entries = [
+            Entry {
+                name: "20210101100000.json".to_owned(),
+                mtime: 1_609_495_200,
+                contents: json.clone().into_bytes(),
+            },
+            Entry {
+                name: "20210101110000.json".to_owned(),
+                mtime: 1_609_498_800,
+                contents: vec![],
+            },
+        ];
+        let compressed = tar_gz(&entries).unwrap();
+        assert!(compressed.len() < json.len() / 4);
+        let mut archive = tar::Archive::new(GzDecoder::new(compressed.as_slice()));
+        let mut read = vec![];
+        for file in archive.entries().unwrap() {
+            let mut file = file.unwrap();
+            let name = file.path().unwrap().to_string_lossy().into_owned();
+            let mtime = file.header().mtime().unwrap();
+            let mut contents = vec![];
+            file.read_to_end(&mut contents).unwrap();
+            read.push((name, mtime, contents));
+        }
+        let expected: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.name.clone(), entry.mtime, entry.contents.clone()))
+            .collect();
+        assert_eq!(expected, read);
+    }
+}
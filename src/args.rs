@@ -1,15 +1,35 @@
-use clap::{App, AppSettings, Arg, SubCommand};
+use clap::{App, AppSettings, Arg, ArgGroup, SubCommand};
+use trackspeedtest::config::{
+    parse_date_filter, parse_period, Aggregation, Alert, ChartStyle, Check, Credentials,
+    EmailOptions, Expectation, Export, ExportFormat, History, Import, ImportFormat, Influx,
+    InfluxTarget, Mqtt, OutputFormat, Probe, Prune, Retention, Run, Serve, Smtp,
+};
+use trackspeedtest::logging::LogFormat;
+use trackspeedtest::probe::{Target, DEFAULT_TARGETS};
+use trackspeedtest::timezone::Zone;
+use trackspeedtest::units::Unit;
 
 #[derive(Debug)]
 pub struct Args {
-    pub verbose: bool,
+    pub verbosity: u64,
+    pub log_format: LogFormat,
+    pub log_file: bool,
     pub command: Option<Command>,
 }
 
+// parsed once, so the size of the variants does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum Command {
     Run(Run),
     Alert(Alert),
+    Export(Export),
+    Serve(Serve),
+    History(History),
+    Prune(Prune),
+    Check(Check),
+    Import(Import),
+    Probe(Probe),
 }
 
 impl Args {
@@ -23,7 +43,12 @@ impl Args {
     {
         let args = Args::get_args_app().get_matches_from_safe(args)?;
         Ok(Args {
-            verbose: args.occurrences_of("v") > 0,
+            verbosity: args.occurrences_of("v"),
+            log_format: match args.value_of("log format") {
+                Some("json") => LogFormat::Json,
+                _ => LogFormat::Text,
+            },
+            log_file: args.is_present("log file"),
             command: Args::get_config_from_cl(args),
         })
     }
@@ -40,7 +65,22 @@ impl Args {
                     .long("verbose")
                     .global(true)
                     .multiple(true)
-                    .help("Sets the level of verbosity"),
+                    .help("Sets the level of verbosity, repeat it for more detail (-v info, -vv debug, -vvv trace)"),
+            )
+            .arg(
+                Arg::with_name("log format")
+                    .long("log-format")
+                    .global(true)
+                    .takes_value(true)
+                    .possible_values(&["text", "json"])
+                    .default_value("text")
+                    .help("Format of the log lines written to stderr"),
+            )
+            .arg(
+                Arg::with_name("log file")
+                    .long("log-file")
+                    .global(true)
+                    .help("Also appends the log to trackspeedtest.log in the data directory"),
             )
             .subcommand(
                 SubCommand::with_name("alert")
@@ -83,7 +123,7 @@ impl Args {
                             .takes_value(true)
                             .index(4)
                             .required(true)
-                            .help("Expected upload bandwidth, in mbps (e.g. 123.45)")
+                            .help("Expected upload bandwidth, in the unit set by --units (e.g. 123.45)")
                             .validator(|v| {
                                 if v.parse::<f64>().is_err() {
                                     return Err("Upload bandwidth is not in the correct format.".to_owned());
@@ -97,7 +137,7 @@ impl Args {
                             .takes_value(true)
                             .index(5)
                             .required(true)
-                            .help("Expected download bandwidth, in mbps (e.g. 123.45)")
+                            .help("Expected download bandwidth, in the unit set by --units (e.g. 123.45)")
                             .validator(|v| {
                                 if v.parse::<f64>().is_err() {
                                     return Err("Download bandwidth is not in the correct format.".to_owned());
@@ -111,6 +151,23 @@ impl Args {
                             .long("simulate")
                             .help("Should write email to stdout instead of sending e-mail"),
                     )
+                    .arg(
+                        Arg::with_name("units")
+                            .long("units")
+                            .takes_value(true)
+                            .possible_values(&["mbps", "mibps"])
+                            .default_value("mibps")
+                            .help("Unit of the expected bandwidths and of the e-mail message: mibps (mebibits, the unit of earlier versions) or mbps (megabits) per second"),
+                    )
+                    .arg(
+                        Arg::with_name("timezone")
+                            .long("timezone")
+                            .takes_value(true)
+                            .allow_hyphen_values(true)
+                            .default_value("utc")
+                            .validator(|zone| zone.parse::<Zone>().map(|_| ()))
+                            .help("Time zone of the dates in the e-mail message: utc, local (the zone of the system, set by TZ), an offset like +02:00 or a name like Europe/Paris"),
+                    )
                     .arg(
                         Arg::with_name("threshold")
                             .short("t")
@@ -163,6 +220,48 @@ impl Args {
                             .long("hours")
                             .default_value("24")
                             .help("Last hours to use as average"),
+                    )
+                    .arg(
+                        Arg::with_name("interface")
+                            .short("i")
+                            .long("interface")
+                            .takes_value(true)
+                            .help("Only use measurements taken through this network interface"),
+                    )
+                    .arg(
+                        Arg::with_name("failures")
+                            .long("failures")
+                            .takes_value(true)
+                            .help("Also sends an e-mail message if at least this many of the latest measurements failed")
+                            .validator(|v| match v.parse::<u8>() {
+                                Ok(failures) if failures > 0 => Ok(()),
+                                _ => Err("Failures has to be a number between 1 and 255.".to_owned()),
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("failures window")
+                            .long("failures-window")
+                            .takes_value(true)
+                            .default_value("8")
+                            .help("How many of the latest measurements are checked for failures")
+                            .validator(|v| {
+                                if v.parse::<u8>().is_err() {
+                                    return Err("Failures window is not in the correct format.".to_owned());
+                                }
+                                Ok(())
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("max loaded latency")
+                            .long("max-loaded-latency")
+                            .takes_value(true)
+                            .help("Sends an e-mail when the latency under load of the latest --count measurements taken with run --latency-target averages above this many milliseconds")
+                            .validator(|v| {
+                                if v.parse::<f64>().is_err() {
+                                    return Err("Maximum loaded latency is not in the correct format.".to_owned());
+                                }
+                                Ok(())
+                            }),
                     ),
             )
             .subcommand(
@@ -174,10 +273,131 @@ impl Args {
                             .long("simulate")
                             .help("Should simulate instead of running speed test"),
                     )
+                    .arg(
+                        Arg::with_name("interface")
+                            .short("i")
+                            .long("interface")
+                            .takes_value(true)
+                            .help("Network interface to bind the measurement to (e.g. eth1)"),
+                    )
+                    .arg(
+                        Arg::with_name("source ip")
+                            .long("source-ip")
+                            .takes_value(true)
+                            .help("Source IP address to bind the measurement to")
+                            .validator(|v| {
+                                if v.parse::<std::net::IpAddr>().is_err() {
+                                    return Err("Source IP is not a valid IP address.".to_owned());
+                                }
+                                Ok(())
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("repeat")
+                            .short("r")
+                            .long("repeat")
+                            .takes_value(true)
+                            .default_value("1")
+                            .help("How many measurements to take in this run")
+                            .validator(|v| match v.parse::<u8>() {
+                                Ok(repeat) if repeat > 0 => Ok(()),
+                                _ => Err("Repeat has to be a number between 1 and 255.".to_owned()),
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("different servers")
+                            .long("different-servers")
+                            .help("Take each of the repeated measurements against a different server"),
+                    )
+                    .arg(
+                        Arg::with_name("aggregate")
+                            .short("a")
+                            .long("aggregate")
+                            .takes_value(true)
+                            .possible_values(&["samples", "median"])
+                            .default_value("samples")
+                            .help("Write each sample to the summary file, or a single row with the median of the samples and their spread (max - min)"),
+                    )
+                    .arg(
+                        Arg::with_name("retries")
+                            .long("retries")
+                            .takes_value(true)
+                            .default_value("0")
+                            .help("How many times to retry a failed measurement before giving up")
+                            .validator(|v| {
+                                if v.parse::<u8>().is_err() {
+                                    return Err("Retries is not in the correct format.".to_owned());
+                                }
+                                Ok(())
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("retry delay")
+                            .long("retry-delay")
+                            .takes_value(true)
+                            .default_value("30")
+                            .help("Seconds to wait before the first retry, doubled on every following retry")
+                            .validator(|v| {
+                                if v.parse::<u64>().is_err() {
+                                    return Err("Retry delay is not in the correct format.".to_owned());
+                                }
+                                Ok(())
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("retry other server")
+                            .long("retry-other-server")
+                            .help("Retry failed measurements against a different server"),
+                    )
+                    .arg(
+                        Arg::with_name("timeout")
+                            .long("timeout")
+                            .takes_value(true)
+                            .default_value("300")
+                            .help("Seconds to wait for a measurement before killing it, 0 waits forever")
+                            .validator(|v| {
+                                if v.parse::<u64>().is_err() {
+                                    return Err("Timeout is not in the correct format.".to_owned());
+                                }
+                                Ok(())
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("latency target")
+                            .long("latency-target")
+                            .takes_value(true)
+                            .value_name("HOST:PORT")
+                            .help("Measures the latency to this host (port 443 if left out) while idle and during the download and upload, to grade bufferbloat (e.g. 1.1.1.1:443)"),
+                    )
                     .arg(
                         Arg::with_name("show_results")
                             .long("show-results")
-                            .help("Sends results to stdout, one result per line: download, upload, ping"),
+                            .help("Sends results to stdout, in the format set by --output"),
+                    )
+                    .arg(
+                        Arg::with_name("output")
+                            .long("output")
+                            .takes_value(true)
+                            .possible_values(&["plain", "text", "table", "csv", "json"])
+                            .default_value("plain")
+                            .help("Format of the results shown with --show-results"),
+                    )
+                    .arg(
+                        Arg::with_name("units")
+                            .long("units")
+                            .takes_value(true)
+                            .possible_values(&["mbps", "mibps"])
+                            .default_value("mbps")
+                            .help("Unit of the bandwidths shown with --show-results in the text, table, csv and json formats: mbps (megabits) or mibps (mebibits) per second. The plain format is always in bytes per second"),
+                    )
+                    .arg(
+                        Arg::with_name("timezone")
+                            .long("timezone")
+                            .takes_value(true)
+                            .allow_hyphen_values(true)
+                            .default_value("utc")
+                            .validator(|zone| zone.parse::<Zone>().map(|_| ()))
+                            .help("Time zone of the timestamps shown with --show-results: utc, local (the zone of the system, set by TZ), an offset like +02:00 or a name like Europe/Paris"),
                     )
                     .arg(
                         Arg::with_name("sender email")
@@ -226,135 +446,1447 @@ impl Args {
                             .requires("username")
                             .takes_value(true)
                             .help("SMTP server password for authentication"),
+                    )
+                    .arg(
+                        Arg::with_name("influx url")
+                            .long("influx-url")
+                            .takes_value(true)
+                            .requires("influx target")
+                            .help("Base URL of an InfluxDB server to write every result to (e.g. http://localhost:8086)")
+                            .validator(|url| {
+                                if url::Url::parse(&url).is_err() {
+                                    return Err("InfluxDB URL is not in the correct format.".to_owned());
+                                }
+                                Ok(())
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("influx db")
+                            .long("influx-db")
+                            .takes_value(true)
+                            .requires("influx url")
+                            .help("InfluxDB 1.x database to write to"),
+                    )
+                    .arg(
+                        Arg::with_name("influx username")
+                            .long("influx-username")
+                            .takes_value(true)
+                            .requires_all(&["influx db", "influx password"])
+                            .help("InfluxDB 1.x username"),
+                    )
+                    .arg(
+                        Arg::with_name("influx password")
+                            .long("influx-password")
+                            .takes_value(true)
+                            .requires("influx username")
+                            .help("InfluxDB 1.x password"),
+                    )
+                    .arg(
+                        Arg::with_name("influx bucket")
+                            .long("influx-bucket")
+                            .takes_value(true)
+                            .requires_all(&["influx url", "influx org", "influx token"])
+                            .help("InfluxDB 2.x bucket to write to"),
+                    )
+                    .arg(
+                        Arg::with_name("influx org")
+                            .long("influx-org")
+                            .takes_value(true)
+                            .requires("influx bucket")
+                            .help("InfluxDB 2.x organization"),
+                    )
+                    .arg(
+                        Arg::with_name("influx token")
+                            .long("influx-token")
+                            .takes_value(true)
+                            .requires("influx bucket")
+                            .help("InfluxDB 2.x API token"),
+                    )
+                    .group(
+                        ArgGroup::with_name("influx target")
+                            .args(&["influx db", "influx bucket"]),
+                    )
+                    .arg(
+                        Arg::with_name("mqtt broker")
+                            .long("mqtt")
+                            .takes_value(true)
+                            .help("MQTT broker to publish the results to, use server or server:port (defaults to port 1883)")
+                            .validator(|server_and_port| {
+                                let parts: Vec<&str> = server_and_port.split(':').collect();
+                                if parts.len() > 2 || parts[0].is_empty() {
+                                    return Err("Not valid server".to_owned());
+                                }
+                                if parts.len() == 2 && parts[1].parse::<u16>().is_err() {
+                                    return Err("Port is not in the correct format.".to_owned());
+                                }
+                                Ok(())
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("mqtt username")
+                            .long("mqtt-username")
+                            .takes_value(true)
+                            .requires_all(&["mqtt broker", "mqtt password"])
+                            .help("MQTT broker username"),
+                    )
+                    .arg(
+                        Arg::with_name("mqtt password")
+                            .long("mqtt-password")
+                            .takes_value(true)
+                            .requires("mqtt username")
+                            .help("MQTT broker password"),
+                    )
+                    .arg(
+                        Arg::with_name("mqtt topic")
+                            .long("mqtt-topic")
+                            .takes_value(true)
+                            .default_value("trackspeedtest")
+                            .help("Prefix of the MQTT state topic"),
+                    )
+                    .arg(
+                        Arg::with_name("mqtt discovery prefix")
+                            .long("mqtt-discovery-prefix")
+                            .takes_value(true)
+                            .default_value("homeassistant")
+                            .help("Prefix of the Home Assistant discovery topics"),
+                    )
+                    .arg(
+                        Arg::with_name("host")
+                            .long("host")
+                            .takes_value(true)
+                            .help("Name of this machine, used as the host tag in InfluxDB and to identify it in MQTT, defaults to the host name"),
+                    )
+                    .arg(
+                        Arg::with_name("keep raw days")
+                            .long("keep-raw-days")
+                            .takes_value(true)
+                            .help("After each run, move raw results (the JSON files) older than this many days to monthly bundles in data/archive")
+                            .validator(|v| {
+                                if v.parse::<u32>().is_err() {
+                                    return Err("Days to keep raw results is not in the correct format.".to_owned());
+                                }
+                                Ok(())
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("no archive")
+                            .long("no-archive")
+                            .requires("keep raw days")
+                            .help("Delete old raw results instead of archiving them in monthly bundles"),
+                    )
+                    .arg(
+                        Arg::with_name("delete archives after")
+                            .long("delete-archives-after")
+                            .takes_value(true)
+                            .help("Months to keep the monthly bundles after the end of their month")
+                            .validator(|v| {
+                                if v.parse::<u32>().is_err() {
+                                    return Err("Months to keep archives is not in the correct format.".to_owned());
+                                }
+                                Ok(())
+                            }),
+                    )
+                    .group(
+                        ArgGroup::with_name("retention")
+                            .args(&["keep raw days", "delete archives after"])
+                            .multiple(true),
                     ),
             )
-    }
-
-    fn get_smtp_from_cl(args: &clap::ArgMatches) -> Option<Smtp> {
-        let smtp = if let Some(server_and_port) = args.value_of("smtp server") {
-            let parts: Vec<&str> = server_and_port.split(':').collect();
-            let server = parts[0];
-            let port = parts[1].parse::<u16>().ok()?;
-            let credentials = if let (Some(username), Some(password)) =
-                (args.value_of("username"), args.value_of("password"))
-            {
-                Some(Credentials {
-                    username: username.to_owned(),
-                    password: password.to_owned(),
-                })
-            } else {
-                None
-            };
-            let email = args.value_of("sender email")?;
-            Some(Smtp {
-                email: email.to_owned(),
-                server: server.to_owned(),
-                port,
-                credentials,
-            })
-        } else {
-            None
-        };
-        smtp
-    }
-
-    fn get_config_from_cl(args: clap::ArgMatches) -> Option<Command> {
-        match args.subcommand() {
-            ("run", Some(run_args)) => Some(Command::Run(Run {
-                simulate: run_args.is_present("simulate"),
-                email_options: EmailOptions::new_from_args(run_args),
-                show_results: run_args.is_present("show_results"),
-            })),
-            ("alert", Some(alert_args)) => Some(Command::Alert(Alert {
-                simulate: alert_args.is_present("simulate"),
-                email: alert_args.value_of("email").unwrap().to_owned(),
-                expected_download: alert_args
-                    .value_of("download")
-                    .unwrap()
-                    .parse::<f64>()
-                    .unwrap(),
-                expected_upload: alert_args
-                    .value_of("upload")
-                    .unwrap()
-                    .parse::<f64>()
-                    .unwrap(),
-                threshold: alert_args
-                    .value_of("threshold")
-                    .unwrap()
-                    .parse::<u8>()
-                    .unwrap(),
-                count: alert_args.value_of("count").unwrap().parse::<u8>().unwrap(),
-                smtp: Args::get_smtp_from_cl(alert_args).unwrap(),
-            })),
-            _ => None,
-        }
-    }
-}
-
-#[derive(Debug)]
-pub struct Run {
-    pub simulate: bool,
-    pub email_options: Option<EmailOptions>,
-    pub show_results: bool,
-}
-
-#[derive(Debug)]
-pub struct Alert {
-    pub simulate: bool,
-    pub email: String,
-    pub expected_download: f64,
-    pub expected_upload: f64,
-    pub threshold: u8,
-    pub count: u8,
-    pub smtp: Smtp,
-}
-
-#[derive(Debug)]
-pub struct Smtp {
-    pub server: String,
-    pub email: String,
-    pub port: u16,
-    pub credentials: Option<Credentials>,
-}
-
-#[derive(Debug)]
-pub struct Credentials {
-    pub username: String,
-    pub password: String,
-}
-
-#[derive(Debug)]
-pub struct EmailOptions {
-    pub email: String,
-    pub smtp: Smtp,
-}
-impl EmailOptions {
-    pub fn new_from_args(args: &clap::ArgMatches) -> Option<EmailOptions> {
-        if let (Some(email), Some(smtp)) = (
-            args.value_of("email").map(|str| str.to_owned()),
-            Args::get_smtp_from_cl(args),
-        ) {
-            Some(EmailOptions { email, smtp })
-        } else {
-            None
-        }
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn args_run_simulated() {
-        let run = match Args::new_from(["trackspeedtest", "run", "--simulate"].iter())
-            .unwrap()
-            .command
-            .unwrap()
-        {
-            Command::Alert(_) => panic!("Should not be alert"),
+            .subcommand(
+                SubCommand::with_name("prune")
+                    .about("Archives or deletes old raw results")
+                    .arg(
+                        Arg::with_name("keep raw days")
+                            .long("keep-raw-days")
+                            .takes_value(true)
+                            .help("Move raw results (the JSON files) older than this many days to monthly bundles in data/archive")
+                            .validator(|v| {
+                                if v.parse::<u32>().is_err() {
+                                    return Err("Days to keep raw results is not in the correct format.".to_owned());
+                                }
+                                Ok(())
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("no archive")
+                            .long("no-archive")
+                            .requires("keep raw days")
+                            .help("Delete old raw results instead of archiving them in monthly bundles"),
+                    )
+                    .arg(
+                        Arg::with_name("delete archives after")
+                            .long("delete-archives-after")
+                            .takes_value(true)
+                            .help("Months to keep the monthly bundles after the end of their month")
+                            .validator(|v| {
+                                if v.parse::<u32>().is_err() {
+                                    return Err("Months to keep archives is not in the correct format.".to_owned());
+                                }
+                                Ok(())
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("dry run")
+                            .long("dry-run")
+                            .help("Only list what would be archived or deleted"),
+                    )
+                    .group(
+                        ArgGroup::with_name("retention")
+                            .args(&["keep raw days", "delete archives after"])
+                            .multiple(true)
+                            .required(true),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("import")
+                    .about("Imports the history of the Node.js tool or of speedtest-cli into the summary file")
+                    .arg(
+                        Arg::with_name("files")
+                            .index(1)
+                            .multiple(true)
+                            .required(true)
+                            .help("Files to import: .json files or a speed.csv of the Node.js tool, or speedtest-cli --csv output"),
+                    )
+                    .arg(
+                        Arg::with_name("format")
+                            .long("format")
+                            .takes_value(true)
+                            .possible_values(&["node-json", "node-csv", "speedtest-cli"])
+                            .help("Format of the files, detected from each file by default"),
+                    )
+                    .arg(
+                        Arg::with_name("dry run")
+                            .long("dry-run")
+                            .help("Only report how many measurements would be imported"),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("check")
+                    .alias("fsck")
+                    .about("Validates the summary file, reporting malformed rows")
+                    .arg(
+                        Arg::with_name("repair")
+                            .long("repair")
+                            .help("Move malformed rows to data/speed.bad.csv, drop an incomplete last line and upgrade the header"),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("probe")
+                    .about("Runs quick connectivity probes, writing them to data/probes.csv")
+                    .arg(
+                        Arg::with_name("target")
+                            .short("t")
+                            .long("target")
+                            .takes_value(true)
+                            .multiple(true)
+                            .number_of_values(1)
+                            .validator(|target| target.parse::<Target>().map(|_| ()))
+                            .help("Target to probe, repeat for several: gateway[:PORT] (the default gateway, port 53), tcp:HOST:PORT, dns:NAME or an http(s) URL; defaults to gateway, tcp:1.1.1.1:443, tcp:8.8.8.8:53 and dns:www.speedtest.net"),
+                    )
+                    .arg(
+                        Arg::with_name("attempts")
+                            .long("attempts")
+                            .takes_value(true)
+                            .default_value("3")
+                            .help("How many times each target is probed in a round")
+                            .validator(|v| match v.parse::<u8>() {
+                                Ok(attempts) if attempts > 0 => Ok(()),
+                                _ => Err("Attempts has to be a number between 1 and 255.".to_owned()),
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("timeout")
+                            .long("timeout")
+                            .takes_value(true)
+                            .default_value("2")
+                            .help("Seconds each attempt waits for an answer")
+                            .validator(|v| match v.parse::<u64>() {
+                                Ok(timeout) if timeout > 0 => Ok(()),
+                                _ => Err("Timeout is not in the correct format.".to_owned()),
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("interval")
+                            .long("interval")
+                            .takes_value(true)
+                            .help("Keep probing, starting a round every this many seconds, instead of running a single round")
+                            .validator(|v| match v.parse::<u64>() {
+                                Ok(interval) if interval > 0 => Ok(()),
+                                _ => Err("Interval is not in the correct format.".to_owned()),
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("max outage")
+                            .long("max-outage")
+                            .takes_value(true)
+                            .help("Alert, once connectivity is back, about outages (no target past the gateway answering) that lasted at least this many seconds")
+                            .validator(|v| {
+                                if v.parse::<u32>().is_err() {
+                                    return Err("Maximum outage is not in the correct format.".to_owned());
+                                }
+                                Ok(())
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("max packet loss")
+                            .long("max-packet-loss")
+                            .takes_value(true)
+                            .help("Alert when the average packet loss of the latest rounds goes above this percentage")
+                            .validator(|v| match v.parse::<f64>() {
+                                Ok(loss) if (0.0..100.0).contains(&loss) => Ok(()),
+                                _ => Err("Maximum packet loss has to be a percentage bellow 100.".to_owned()),
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("loss window")
+                            .long("loss-window")
+                            .takes_value(true)
+                            .default_value("10")
+                            .help("How many rounds the packet loss is averaged over")
+                            .validator(|v| match v.parse::<usize>() {
+                                Ok(window) if window > 0 => Ok(()),
+                                _ => Err("Loss window is not in the correct format.".to_owned()),
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("timezone")
+                            .long("timezone")
+                            .takes_value(true)
+                            .allow_hyphen_values(true)
+                            .default_value("utc")
+                            .validator(|zone| zone.parse::<Zone>().map(|_| ()))
+                            .help("Time zone of the dates in the alert messages: utc, local (the zone of the system, set by TZ), an offset like +02:00 or a name like Europe/Paris"),
+                    )
+                    .arg(
+                        Arg::with_name("simulate")
+                            .short("s")
+                            .long("simulate")
+                            .help("Should write the alert messages to stdout instead of sending them"),
+                    )
+                    .arg(
+                        Arg::with_name("sender email")
+                            .short("e")
+                            .long("sender")
+                            .takes_value(true)
+                            .requires_all(&["email", "smtp server"])
+                            .help("E-mail address to send the alert message from"),
+                    )
+                    .arg(
+                        Arg::with_name("email")
+                            .long("to")
+                            .takes_value(true)
+                            .requires_all(&["sender email", "smtp server"])
+                            .help("E-mail address to send the alert message to"),
+                    )
+                    .arg(
+                        Arg::with_name("smtp server")
+                            .long("smtp")
+                            .takes_value(true)
+                            .requires_all(&["sender email", "email"])
+                            .help("SMTP server and port to use, use server:port")
+                            .validator(|server_and_port| {
+                                let parts: Vec<&str> = server_and_port.split(':').collect();
+                                if parts.len() != 2 {
+                                    return Err("Not valid server".to_owned());
+                                }
+                                if parts[1].parse::<u16>().is_err() {
+                                    return Err("Port is not in the correct format.".to_owned());
+                                }
+                                Ok(())
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("username")
+                            .short("u")
+                            .long("username")
+                            .requires("password")
+                            .takes_value(true)
+                            .help("SMTP server user for authentication"),
+                    )
+                    .arg(
+                        Arg::with_name("password")
+                            .short("p")
+                            .long("password")
+                            .requires("username")
+                            .takes_value(true)
+                            .help("SMTP server password for authentication"),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("export")
+                    .about("Writes the history of measurements to stdout, in another format")
+                    .arg(
+                        Arg::with_name("format")
+                            .long("format")
+                            .takes_value(true)
+                            .possible_values(&["influx", "ndjson", "csv", "parquet"])
+                            .required(true)
+                            .help("Format to export to, influx is InfluxDB line protocol and ndjson a JSON object per line"),
+                    )
+                    .arg(
+                        Arg::with_name("from")
+                            .long("from")
+                            .takes_value(true)
+                            .help("Only export measurements taken from this date on, like 2021-01-31 or 2021-01-31T10:00:00Z")
+                            .validator(|date| parse_date_filter(&date).map(|_| ())),
+                    )
+                    .arg(
+                        Arg::with_name("to")
+                            .long("to")
+                            .takes_value(true)
+                            .help("Only export measurements taken before this date")
+                            .validator(|date| parse_date_filter(&date).map(|_| ())),
+                    )
+                    .arg(
+                        Arg::with_name("interface")
+                            .short("i")
+                            .long("interface")
+                            .takes_value(true)
+                            .help("Only export measurements taken through this network interface"),
+                    )
+                    .arg(
+                        Arg::with_name("host")
+                            .long("host")
+                            .takes_value(true)
+                            .help("Value of the host tag, defaults to the host name"),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("serve")
+                    .about("Serves a web UI with charts of the measurements")
+                    .arg(
+                        Arg::with_name("listen")
+                            .short("l")
+                            .long("listen")
+                            .takes_value(true)
+                            .default_value("127.0.0.1:8080")
+                            .help("Address and port to listen on, use 0.0.0.0:8080 to accept connections from other machines")
+                            .validator(|address| {
+                                if address.parse::<std::net::SocketAddr>().is_err() {
+                                    return Err("Address is not in the correct format, use ip:port.".to_owned());
+                                }
+                                Ok(())
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("download")
+                            .long("download")
+                            .takes_value(true)
+                            .requires("upload")
+                            .help("Expected download bandwidth, drawn as a reference line, in the unit set by --units")
+                            .validator(|v| {
+                                if v.parse::<f64>().is_err() {
+                                    return Err("Download bandwidth is not in the correct format.".to_owned());
+                                }
+                                Ok(())
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("upload")
+                            .long("upload")
+                            .takes_value(true)
+                            .requires("download")
+                            .help("Expected upload bandwidth, drawn as a reference line, in the unit set by --units")
+                            .validator(|v| {
+                                if v.parse::<f64>().is_err() {
+                                    return Err("Upload bandwidth is not in the correct format.".to_owned());
+                                }
+                                Ok(())
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("threshold")
+                            .short("t")
+                            .long("threshold")
+                            .takes_value(true)
+                            .default_value("20")
+                            .help("Threshold percentage, measurements bellow the expected bandwidth by more than this are highlighted")
+                            .validator(|v| {
+                                if v.parse::<u8>().is_err() {
+                                    return Err("Threshold is not in the correct format.".to_owned());
+                                }
+                                Ok(())
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("units")
+                            .long("units")
+                            .takes_value(true)
+                            .possible_values(&["mbps", "mibps"])
+                            .default_value("mbps")
+                            .help("Unit of the bandwidths shown: mbps (megabits) or mibps (mebibits) per second"),
+                    )
+                    .arg(
+                        Arg::with_name("interface")
+                            .short("i")
+                            .long("interface")
+                            .takes_value(true)
+                            .help("Only show measurements taken through this network interface"),
+                    )
+                    .arg(
+                        Arg::with_name("count")
+                            .short("c")
+                            .long("count")
+                            .takes_value(true)
+                            .default_value("8")
+                            .help("How many measurements make up the average reported by /alerts/state")
+                            .validator(|v| {
+                                if v.parse::<u8>().is_err() {
+                                    return Err("Measurement count is not in the correct format.".to_owned());
+                                }
+                                Ok(())
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("failures window")
+                            .long("failures-window")
+                            .takes_value(true)
+                            .default_value("8")
+                            .help("How many of the latest measurements /alerts/state checks for failures")
+                            .validator(|v| {
+                                if v.parse::<u8>().is_err() {
+                                    return Err("Failures window is not in the correct format.".to_owned());
+                                }
+                                Ok(())
+                            }),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("history")
+                    .about("Shows charts of the latest measurements in the terminal")
+                    .arg(
+                        Arg::with_name("last")
+                            .long("last")
+                            .takes_value(true)
+                            .default_value("7d")
+                            .help("How far back to chart, in hours, days or weeks (e.g. 24h, 7d, 2w)")
+                            .validator(|v| parse_period(&v).map(|_| ())),
+                    )
+                    .arg(
+                        Arg::with_name("style")
+                            .long("style")
+                            .takes_value(true)
+                            .possible_values(&["chart", "sparkline"])
+                            .default_value("chart")
+                            .help("Draw line charts or one line sparklines"),
+                    )
+                    .arg(
+                        Arg::with_name("width")
+                            .long("width")
+                            .takes_value(true)
+                            .help("Columns the charts have to fit in (defaults to $COLUMNS or 80)")
+                            .validator(|v| match v.parse::<usize>() {
+                                Ok(width) if width > 0 => Ok(()),
+                                _ => Err("Width has to be a number greater than 0.".to_owned()),
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("download")
+                            .long("download")
+                            .takes_value(true)
+                            .requires("upload")
+                            .help("Expected download bandwidth, drawn as a reference line, in the unit set by --units")
+                            .validator(|v| {
+                                if v.parse::<f64>().is_err() {
+                                    return Err("Download bandwidth is not in the correct format.".to_owned());
+                                }
+                                Ok(())
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("upload")
+                            .long("upload")
+                            .takes_value(true)
+                            .requires("download")
+                            .help("Expected upload bandwidth, drawn as a reference line, in the unit set by --units")
+                            .validator(|v| {
+                                if v.parse::<f64>().is_err() {
+                                    return Err("Upload bandwidth is not in the correct format.".to_owned());
+                                }
+                                Ok(())
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("threshold")
+                            .short("t")
+                            .long("threshold")
+                            .takes_value(true)
+                            .default_value("20")
+                            .help("Threshold percentage, measurements bellow the expected bandwidth by more than this are highlighted")
+                            .validator(|v| {
+                                if v.parse::<u8>().is_err() {
+                                    return Err("Threshold is not in the correct format.".to_owned());
+                                }
+                                Ok(())
+                            }),
+                    )
+                    .arg(
+                        Arg::with_name("units")
+                            .long("units")
+                            .takes_value(true)
+                            .possible_values(&["mbps", "mibps"])
+                            .default_value("mbps")
+                            .help("Unit of the bandwidths shown: mbps (megabits) or mibps (mebibits) per second"),
+                    )
+                    .arg(
+                        Arg::with_name("timezone")
+                            .long("timezone")
+                            .takes_value(true)
+                            .allow_hyphen_values(true)
+                            .default_value("utc")
+                            .validator(|zone| zone.parse::<Zone>().map(|_| ()))
+                            .help("Time zone of the dates shown: utc, local (the zone of the system, set by TZ), an offset like +02:00 or a name like Europe/Paris"),
+                    )
+                    .arg(
+                        Arg::with_name("interface")
+                            .short("i")
+                            .long("interface")
+                            .takes_value(true)
+                            .help("Only show measurements taken through this network interface"),
+                    ),
+            )
+    }
+
+    fn get_expectation_from_cl(args: &clap::ArgMatches) -> Option<Expectation> {
+        Some(Expectation {
+            download: args.value_of("download")?.parse::<f64>().ok()?,
+            upload: args.value_of("upload")?.parse::<f64>().ok()?,
+            threshold: args.value_of("threshold")?.parse::<u8>().ok()?,
+        })
+    }
+
+    fn get_retention_from_cl(args: &clap::ArgMatches) -> Option<Retention> {
+        if !args.is_present("retention") {
+            return None;
+        }
+        let parse = |name| args.value_of(name).map(|v| v.parse::<u32>().unwrap());
+        Some(Retention {
+            keep_raw_days: parse("keep raw days"),
+            archive: !args.is_present("no archive"),
+            delete_archives_after_months: parse("delete archives after"),
+        })
+    }
+
+    fn get_influx_from_cl(args: &clap::ArgMatches) -> Option<Influx> {
+        let url = args.value_of("influx url")?.to_owned();
+        let target = if let Some(bucket) = args.value_of("influx bucket") {
+            InfluxTarget::V2 {
+                org: args.value_of("influx org")?.to_owned(),
+                bucket: bucket.to_owned(),
+                token: args.value_of("influx token")?.to_owned(),
+            }
+        } else {
+            InfluxTarget::V1 {
+                database: args.value_of("influx db")?.to_owned(),
+                credentials: if let (Some(username), Some(password)) = (
+                    args.value_of("influx username"),
+                    args.value_of("influx password"),
+                ) {
+                    Some(Credentials {
+                        username: username.to_owned(),
+                        password: password.to_owned(),
+                    })
+                } else {
+                    None
+                },
+            }
+        };
+        Some(Influx {
+            url,
+            target,
+            host: Args::get_host_from_cl(args),
+        })
+    }
+
+    fn get_mqtt_from_cl(args: &clap::ArgMatches) -> Option<Mqtt> {
+        let mut parts = args.value_of("mqtt broker")?.split(':');
+        let host = parts.next()?.to_owned();
+        let port = parts.next().map_or(Some(1883), |port| port.parse().ok())?;
+        let credentials = if let (Some(username), Some(password)) = (
+            args.value_of("mqtt username"),
+            args.value_of("mqtt password"),
+        ) {
+            Some(Credentials {
+                username: username.to_owned(),
+                password: password.to_owned(),
+            })
+        } else {
+            None
+        };
+        // topic levels and Home Assistant ids only accept some characters
+        let node_id = Args::get_host_from_cl(args)
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        Some(Mqtt {
+            host,
+            port,
+            credentials,
+            topic: args.value_of("mqtt topic")?.to_owned(),
+            discovery_prefix: args.value_of("mqtt discovery prefix")?.to_owned(),
+            node_id,
+        })
+    }
+
+    fn get_host_from_cl(args: &clap::ArgMatches) -> String {
+        args.value_of("host").map_or_else(
+            || {
+                hostname::get()
+                    .map(|host| host.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            },
+            |host| host.to_owned(),
+        )
+    }
+
+    fn get_smtp_from_cl(args: &clap::ArgMatches) -> Option<Smtp> {
+        let smtp = if let Some(server_and_port) = args.value_of("smtp server") {
+            let parts: Vec<&str> = server_and_port.split(':').collect();
+            let server = parts[0];
+            let port = parts[1].parse::<u16>().ok()?;
+            let credentials = if let (Some(username), Some(password)) =
+                (args.value_of("username"), args.value_of("password"))
+            {
+                Some(Credentials {
+                    username: username.to_owned(),
+                    password: password.to_owned(),
+                })
+            } else {
+                None
+            };
+            let email = args.value_of("sender email")?;
+            Some(Smtp {
+                email: email.to_owned(),
+                server: server.to_owned(),
+                port,
+                credentials,
+            })
+        } else {
+            None
+        };
+        smtp
+    }
+
+    fn get_email_options_from_cl(args: &clap::ArgMatches) -> Option<EmailOptions> {
+        if let (Some(email), Some(smtp)) = (
+            args.value_of("email").map(|str| str.to_owned()),
+            Args::get_smtp_from_cl(args),
+        ) {
+            Some(EmailOptions { email, smtp })
+        } else {
+            None
+        }
+    }
+
+    fn get_config_from_cl(args: clap::ArgMatches) -> Option<Command> {
+        match args.subcommand() {
+            ("run", Some(run_args)) => Some(Command::Run(Run {
+                simulate: run_args.is_present("simulate"),
+                email_options: Args::get_email_options_from_cl(run_args),
+                show_results: if run_args.is_present("show_results") {
+                    Some(match run_args.value_of("output") {
+                        Some("json") => OutputFormat::Json,
+                        Some("csv") => OutputFormat::Csv,
+                        Some("table") => OutputFormat::Table,
+                        Some("text") => OutputFormat::Text,
+                        _ => OutputFormat::Plain,
+                    })
+                } else {
+                    None
+                },
+                units: run_args.value_of("units").unwrap().parse::<Unit>().unwrap(),
+                timezone: run_args
+                    .value_of("timezone")
+                    .unwrap()
+                    .parse::<Zone>()
+                    .unwrap(),
+                interface: run_args.value_of("interface").map(|str| str.to_owned()),
+                source_ip: run_args.value_of("source ip").map(|str| str.to_owned()),
+                repeat: run_args.value_of("repeat").unwrap().parse::<u8>().unwrap(),
+                different_servers: run_args.is_present("different servers"),
+                aggregation: match run_args.value_of("aggregate") {
+                    Some("median") => Aggregation::Median,
+                    _ => Aggregation::Samples,
+                },
+                retries: run_args.value_of("retries").unwrap().parse::<u8>().unwrap(),
+                retry_delay: std::time::Duration::from_secs(
+                    run_args
+                        .value_of("retry delay")
+                        .unwrap()
+                        .parse::<u64>()
+                        .unwrap(),
+                ),
+                retry_other_server: run_args.is_present("retry other server"),
+                timeout: match run_args
+                    .value_of("timeout")
+                    .unwrap()
+                    .parse::<u64>()
+                    .unwrap()
+                {
+                    0 => None,
+                    timeout => Some(std::time::Duration::from_secs(timeout)),
+                },
+                latency_target: run_args
+                    .value_of("latency target")
+                    .map(|str| str.to_owned()),
+                influx: Args::get_influx_from_cl(run_args),
+                mqtt: Args::get_mqtt_from_cl(run_args),
+                retention: Args::get_retention_from_cl(run_args),
+            })),
+            ("export", Some(export_args)) => Some(Command::Export(Export {
+                format: match export_args.value_of("format") {
+                    Some("ndjson") => ExportFormat::Ndjson,
+                    Some("csv") => ExportFormat::Csv,
+                    Some("parquet") => ExportFormat::Parquet,
+                    _ => ExportFormat::Influx,
+                },
+                interface: export_args.value_of("interface").map(|str| str.to_owned()),
+                host: Args::get_host_from_cl(export_args),
+                from: export_args
+                    .value_of("from")
+                    .map(|date| parse_date_filter(date).unwrap()),
+                to: export_args
+                    .value_of("to")
+                    .map(|date| parse_date_filter(date).unwrap()),
+            })),
+            ("serve", Some(serve_args)) => Some(Command::Serve(Serve {
+                address: serve_args.value_of("listen").unwrap().to_owned(),
+                units: serve_args
+                    .value_of("units")
+                    .unwrap()
+                    .parse::<Unit>()
+                    .unwrap(),
+                interface: serve_args.value_of("interface").map(|str| str.to_owned()),
+                expectation: Args::get_expectation_from_cl(serve_args),
+                count: serve_args.value_of("count").unwrap().parse::<u8>().unwrap(),
+                failures_window: serve_args
+                    .value_of("failures window")
+                    .unwrap()
+                    .parse::<u8>()
+                    .unwrap(),
+            })),
+            ("prune", Some(prune_args)) => Some(Command::Prune(Prune {
+                retention: Args::get_retention_from_cl(prune_args).unwrap(),
+                dry_run: prune_args.is_present("dry run"),
+            })),
+            ("import", Some(import_args)) => Some(Command::Import(Import {
+                files: import_args
+                    .values_of("files")
+                    .unwrap()
+                    .map(std::path::PathBuf::from)
+                    .collect(),
+                format: match import_args.value_of("format") {
+                    Some("node-json") => Some(ImportFormat::NodeJson),
+                    Some("node-csv") => Some(ImportFormat::NodeCsv),
+                    Some("speedtest-cli") => Some(ImportFormat::SpeedtestCli),
+                    _ => None,
+                },
+                dry_run: import_args.is_present("dry run"),
+            })),
+            ("check", Some(check_args)) => Some(Command::Check(Check {
+                repair: check_args.is_present("repair"),
+            })),
+            ("probe", Some(probe_args)) => Some(Command::Probe(Probe {
+                targets: match probe_args.values_of("target") {
+                    Some(targets) => targets.map(|target| target.parse().unwrap()).collect(),
+                    None => DEFAULT_TARGETS
+                        .iter()
+                        .map(|target| target.parse().unwrap())
+                        .collect(),
+                },
+                attempts: probe_args
+                    .value_of("attempts")
+                    .unwrap()
+                    .parse::<u8>()
+                    .unwrap(),
+                timeout: std::time::Duration::from_secs(
+                    probe_args
+                        .value_of("timeout")
+                        .unwrap()
+                        .parse::<u64>()
+                        .unwrap(),
+                ),
+                interval: probe_args.value_of("interval").map(|interval| {
+                    std::time::Duration::from_secs(interval.parse::<u64>().unwrap())
+                }),
+                max_outage: probe_args.value_of("max outage").map(|seconds| {
+                    chrono::Duration::seconds(seconds.parse::<u32>().unwrap() as i64)
+                }),
+                max_packet_loss: probe_args
+                    .value_of("max packet loss")
+                    .map(|loss| loss.parse::<f64>().unwrap()),
+                loss_window: probe_args
+                    .value_of("loss window")
+                    .unwrap()
+                    .parse::<usize>()
+                    .unwrap(),
+                simulate: probe_args.is_present("simulate"),
+                email_options: Args::get_email_options_from_cl(probe_args),
+                timezone: probe_args
+                    .value_of("timezone")
+                    .unwrap()
+                    .parse::<Zone>()
+                    .unwrap(),
+            })),
+            ("history", Some(history_args)) => Some(Command::History(History {
+                last: parse_period(history_args.value_of("last").unwrap()).unwrap(),
+                style: match history_args.value_of("style") {
+                    Some("sparkline") => ChartStyle::Sparkline,
+                    _ => ChartStyle::Chart,
+                },
+                width: history_args
+                    .value_of("width")
+                    .map(str::to_owned)
+                    .or_else(|| std::env::var("COLUMNS").ok())
+                    .and_then(|width| width.parse::<usize>().ok())
+                    .unwrap_or(80),
+                units: history_args
+                    .value_of("units")
+                    .unwrap()
+                    .parse::<Unit>()
+                    .unwrap(),
+                timezone: history_args
+                    .value_of("timezone")
+                    .unwrap()
+                    .parse::<Zone>()
+                    .unwrap(),
+                interface: history_args.value_of("interface").map(|str| str.to_owned()),
+                expectation: Args::get_expectation_from_cl(history_args),
+            })),
+            ("alert", Some(alert_args)) => Some(Command::Alert(Alert {
+                simulate: alert_args.is_present("simulate"),
+                email: alert_args.value_of("email").unwrap().to_owned(),
+                expected_download: alert_args
+                    .value_of("download")
+                    .unwrap()
+                    .parse::<f64>()
+                    .unwrap(),
+                expected_upload: alert_args
+                    .value_of("upload")
+                    .unwrap()
+                    .parse::<f64>()
+                    .unwrap(),
+                units: alert_args
+                    .value_of("units")
+                    .unwrap()
+                    .parse::<Unit>()
+                    .unwrap(),
+                timezone: alert_args
+                    .value_of("timezone")
+                    .unwrap()
+                    .parse::<Zone>()
+                    .unwrap(),
+                threshold: alert_args
+                    .value_of("threshold")
+                    .unwrap()
+                    .parse::<u8>()
+                    .unwrap(),
+                count: alert_args.value_of("count").unwrap().parse::<u8>().unwrap(),
+                smtp: Args::get_smtp_from_cl(alert_args).unwrap(),
+                interface: alert_args.value_of("interface").map(|str| str.to_owned()),
+                failures: alert_args
+                    .value_of("failures")
+                    .map(|failures| failures.parse::<u8>().unwrap()),
+                failures_window: alert_args
+                    .value_of("failures window")
+                    .unwrap()
+                    .parse::<u8>()
+                    .unwrap(),
+                max_loaded_latency: alert_args
+                    .value_of("max loaded latency")
+                    .map(|latency| latency.parse::<f64>().unwrap()),
+            })),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn args_run_simulated() {
+        let run = match Args::new_from(["trackspeedtest", "run", "--simulate"].iter())
+            .unwrap()
+            .command
+            .unwrap()
+        {
             Command::Run(run) => run,
+            _ => panic!("Should be run"),
         };
         assert!(run.simulate);
     }
+
+    #[test]
+    fn args_run_shows_results_in_the_chosen_format() {
+        let get_run = |args: &[&str]| match Args::new_from(args.iter()).unwrap().command.unwrap() {
+            Command::Run(run) => run,
+            _ => panic!("Should be run"),
+        };
+        assert_eq!(None, get_run(&["trackspeedtest", "run"]).show_results);
+        assert_eq!(
+            Some(OutputFormat::Plain),
+            get_run(&["trackspeedtest", "run", "--show-results"]).show_results
+        );
+        assert_eq!(
+            Some(OutputFormat::Text),
+            get_run(&[
+                "trackspeedtest",
+                "run",
+                "--show-results",
+                "--output",
+                "text"
+            ])
+            .show_results
+        );
+        assert_eq!(
+            Some(OutputFormat::Json),
+            get_run(&[
+                "trackspeedtest",
+                "run",
+                "--show-results",
+                "--output",
+                "json"
+            ])
+            .show_results
+        );
+        assert_eq!(Zone::Utc, get_run(&["trackspeedtest", "run"]).timezone);
+        assert_eq!(
+            "-03:00".parse::<Zone>().unwrap(),
+            get_run(&["trackspeedtest", "run", "--timezone", "-03:00"]).timezone
+        );
+        assert_eq!(
+            "Europe/Paris".parse::<Zone>().unwrap(),
+            get_run(&["trackspeedtest", "run", "--timezone", "Europe/Paris"]).timezone
+        );
+        assert!(Args::new_from(["trackspeedtest", "run", "--timezone", "Mars"].iter()).is_err());
+    }
+
+    #[test]
+    fn args_import_with_format() {
+        let import = match Args::new_from(
+            [
+                "trackspeedtest",
+                "import",
+                "--format",
+                "speedtest-cli",
+                "a.csv",
+                "b.csv",
+            ]
+            .iter(),
+        )
+        .unwrap()
+        .command
+        .unwrap()
+        {
+            Command::Import(import) => import,
+            _ => panic!("Should be import"),
+        };
+        assert_eq!(
+            vec![
+                std::path::PathBuf::from("a.csv"),
+                std::path::PathBuf::from("b.csv")
+            ],
+            import.files
+        );
+        assert_eq!(Some(ImportFormat::SpeedtestCli), import.format);
+        assert!(!import.dry_run);
+        assert!(Args::new_from(["trackspeedtest", "import"].iter()).is_err());
+    }
+
+    #[test]
+    fn args_export_with_date_range() {
+        let export = match Args::new_from(
+            [
+                "trackspeedtest",
+                "export",
+                "--format",
+                "parquet",
+                "--from",
+                "2021-01-01",
+                "--to",
+                "2021-02-01T12:00:00-03:00",
+            ]
+            .iter(),
+        )
+        .unwrap()
+        .command
+        .unwrap()
+        {
+            Command::Export(export) => export,
+            _ => panic!("Should be export"),
+        };
+        assert_eq!(ExportFormat::Parquet, export.format);
+        assert_eq!(
+            Some("2021-01-01T00:00:00+00:00".to_owned()),
+            export.from.map(|date| date.to_rfc3339())
+        );
+        assert_eq!(
+            Some("2021-02-01T15:00:00+00:00".to_owned()),
+            export.to.map(|date| date.to_rfc3339())
+        );
+        assert!(Args::new_from(
+            [
+                "trackspeedtest",
+                "export",
+                "--format",
+                "csv",
+                "--from",
+                "01/01/2021"
+            ]
+            .iter()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn args_history_with_expectation() {
+        let history = match Args::new_from(
+            [
+                "trackspeedtest",
+                "history",
+                "--last",
+                "24h",
+                "--style",
+                "sparkline",
+                "--width",
+                "100",
+                "--download",
+                "300",
+                "--upload",
+                "100",
+            ]
+            .iter(),
+        )
+        .unwrap()
+        .command
+        .unwrap()
+        {
+            Command::History(history) => history,
+            _ => panic!("Should be history"),
+        };
+        assert_eq!(chrono::Duration::hours(24), history.last);
+        assert_eq!(ChartStyle::Sparkline, history.style);
+        assert_eq!(100, history.width);
+        assert_eq!(
+            Some(240.0),
+            history.expectation.map(|e| e.minimum_download())
+        );
+        assert!(Args::new_from(["trackspeedtest", "history", "--last", "0d"].iter()).is_err());
+    }
+
+    #[test]
+    fn args_retention_for_run_and_prune() {
+        let run = match Args::new_from(
+            [
+                "trackspeedtest",
+                "run",
+                "--keep-raw-days",
+                "30",
+                "--no-archive",
+            ]
+            .iter(),
+        )
+        .unwrap()
+        .command
+        .unwrap()
+        {
+            Command::Run(run) => run,
+            _ => panic!("Should be run"),
+        };
+        assert_eq!(
+            Some(Retention {
+                keep_raw_days: Some(30),
+                archive: false,
+                delete_archives_after_months: None,
+            }),
+            run.retention
+        );
+        let prune = match Args::new_from(
+            [
+                "trackspeedtest",
+                "prune",
+                "--delete-archives-after",
+                "12",
+                "--dry-run",
+            ]
+            .iter(),
+        )
+        .unwrap()
+        .command
+        .unwrap()
+        {
+            Command::Prune(prune) => prune,
+            _ => panic!("Should be prune"),
+        };
+        assert!(prune.dry_run);
+        assert_eq!(None, prune.retention.keep_raw_days);
+        assert_eq!(Some(12), prune.retention.delete_archives_after_months);
+        assert!(Args::new_from(["trackspeedtest", "prune"].iter()).is_err());
+        assert!(Args::new_from(["trackspeedtest", "run", "--no-archive"].iter()).is_err());
+    }
+
+    #[test]
+    fn args_logging_options() {
+        let args = Args::new_from(["trackspeedtest", "run", "-vv", "--log-format", "json"].iter())
+            .unwrap();
+        assert_eq!(2, args.verbosity);
+        assert_eq!(LogFormat::Json, args.log_format);
+        assert!(!args.log_file);
+        let args = Args::new_from(["trackspeedtest", "--log-file", "run"].iter()).unwrap();
+        assert_eq!(0, args.verbosity);
+        assert_eq!(LogFormat::Text, args.log_format);
+        assert!(args.log_file);
+    }
+
+    #[test]
+    fn args_run_publishes_to_mqtt() {
+        let run = match Args::new_from(
+            [
+                "trackspeedtest",
+                "run",
+                "--mqtt",
+                "broker",
+                "--host",
+                "my host",
+            ]
+            .iter(),
+        )
+        .unwrap()
+        .command
+        .unwrap()
+        {
+            Command::Run(run) => run,
+            _ => panic!("Should be run"),
+        };
+        let mqtt = run.mqtt.unwrap();
+        assert_eq!(("broker", 1883), (mqtt.host.as_str(), mqtt.port));
+        assert_eq!("my_host", mqtt.node_id);
+        assert_eq!("homeassistant", mqtt.discovery_prefix);
+        assert!(run.influx.is_none());
+    }
+
+    #[test]
+    fn args_run_publishes_to_influxdb() {
+        let get_influx = |args: &[&str]| match Args::new_from(args.iter()).unwrap().command.unwrap()
+        {
+            Command::Run(run) => run.influx.unwrap(),
+            _ => panic!("Should be run"),
+        };
+        let influx = get_influx(&[
+            "trackspeedtest",
+            "run",
+            "--influx-url",
+            "http://localhost:8086",
+            "--influx-db",
+            "speed",
+            "--influx-username",
+            "user",
+            "--influx-password",
+            "pass",
+        ]);
+        assert_eq!("http://localhost:8086", influx.url);
+        assert_eq!(
+            InfluxTarget::V1 {
+                database: "speed".to_owned(),
+                credentials: Some(Credentials {
+                    username: "user".to_owned(),
+                    password: "pass".to_owned()
+                })
+            },
+            influx.target
+        );
+        let influx = get_influx(&[
+            "trackspeedtest",
+            "run",
+            "--influx-url",
+            "http://localhost:8086",
+            "--influx-bucket",
+            "speed",
+            "--influx-org",
+            "org",
+            "--influx-token",
+            "secret",
+        ]);
+        assert_eq!(
+            InfluxTarget::V2 {
+                org: "org".to_owned(),
+                bucket: "speed".to_owned(),
+                token: "secret".to_owned()
+            },
+            influx.target
+        );
+        for args in [
+            &["--influx-url", "http://localhost:8086"][..],
+            &["--influx-db", "speed"],
+            &[
+                "--influx-url",
+                "u",
+                "--influx-db",
+                "speed",
+                "--influx-username",
+                "user",
+            ],
+            &[
+                "--influx-url",
+                "u",
+                "--influx-bucket",
+                "speed",
+                "--influx-org",
+                "org",
+            ],
+        ] {
+            let args = ["trackspeedtest", "run"].iter().chain(args);
+            assert!(Args::new_from(args).is_err());
+        }
+    }
+
+    #[test]
+    fn args_run_bound_to_interface_and_source_ip() {
+        let run = match Args::new_from(
+            [
+                "trackspeedtest",
+                "run",
+                "--interface",
+                "eth1",
+                "--source-ip",
+                "192.168.2.10",
+            ]
+            .iter(),
+        )
+        .unwrap()
+        .command
+        .unwrap()
+        {
+            Command::Run(run) => run,
+            _ => panic!("Should be run"),
+        };
+        assert_eq!(Some("eth1".to_owned()), run.interface);
+        assert_eq!(Some("192.168.2.10".to_owned()), run.source_ip);
+    }
+
+    #[test]
+    fn args_run_measures_loaded_latency() {
+        let get_run = |args: &[&str]| match Args::new_from(args.iter()).unwrap().command.unwrap() {
+            Command::Run(run) => run,
+            _ => panic!("Should be run"),
+        };
+        assert_eq!(None, get_run(&["trackspeedtest", "run"]).latency_target);
+        assert_eq!(
+            Some("1.1.1.1:443".to_owned()),
+            get_run(&["trackspeedtest", "run", "--latency-target", "1.1.1.1:443"]).latency_target
+        );
+    }
+
+    #[test]
+    fn args_probe_with_targets_and_alerts() {
+        let get_probe = |args: &[&str]| match Args::new_from(args.iter()).unwrap().command.unwrap()
+        {
+            Command::Probe(probe) => probe,
+            _ => panic!("Should be probe"),
+        };
+        let probe = get_probe(&["trackspeedtest", "probe"]);
+        assert_eq!(DEFAULT_TARGETS.len(), probe.targets.len());
+        assert_eq!(3, probe.attempts);
+        assert_eq!(None, probe.interval);
+        assert_eq!(None, probe.max_outage);
+        let probe = get_probe(&[
+            "trackspeedtest",
+            "probe",
+            "--target",
+            "tcp:127.0.0.1:80",
+            "-t",
+            "dns:localhost",
+            "--interval",
+            "30",
+            "--max-outage",
+            "120",
+            "--max-packet-loss",
+            "5",
+        ]);
+        assert_eq!(
+            vec![
+                Target::Tcp("127.0.0.1:80".to_owned()),
+                Target::Dns("localhost".to_owned())
+            ],
+            probe.targets
+        );
+        assert_eq!(Some(std::time::Duration::from_secs(30)), probe.interval);
+        assert_eq!(Some(chrono::Duration::minutes(2)), probe.max_outage);
+        assert_eq!(Some(5.0), probe.max_packet_loss);
+        assert!(Args::new_from(["trackspeedtest", "probe", "-t", "icmp:1.1.1.1"].iter()).is_err());
+    }
+
+    #[test]
+    fn args_run_repeated_with_median() {
+        let run = match Args::new_from(
+            [
+                "trackspeedtest",
+                "run",
+                "--repeat",
+                "3",
+                "--different-servers",
+                "--aggregate",
+                "median",
+            ]
+            .iter(),
+        )
+        .unwrap()
+        .command
+        .unwrap()
+        {
+            Command::Run(run) => run,
+            _ => panic!("Should be run"),
+        };
+        assert_eq!(3, run.repeat);
+        assert!(run.different_servers);
+        assert_eq!(Aggregation::Median, run.aggregation);
+    }
+
+    #[test]
+    fn args_alert_compares_in_mibps_unless_told_otherwise() {
+        let get_alert = |units: &[&str]| {
+            let args = [
+                "trackspeedtest",
+                "alert",
+                "from@example.com",
+                "to@example.com",
+                "smtp.example.com:587",
+                "50",
+                "100",
+            ]
+            .iter()
+            .chain(units);
+            match Args::new_from(args).unwrap().command.unwrap() {
+                Command::Alert(alert) => alert,
+                _ => panic!("Should be alert"),
+            }
+        };
+        let alert = get_alert(&[]);
+        assert_eq!(Unit::Mibps, alert.units);
+        assert_eq!(
+            (100.0, 50.0),
+            (alert.expected_download, alert.expected_upload)
+        );
+        assert_eq!(Unit::Mbps, get_alert(&["--units", "mbps"]).units);
+    }
+
+    #[test]
+    fn args_run_rejects_zero_repeat() {
+        assert!(Args::new_from(["trackspeedtest", "run", "--repeat", "0"].iter()).is_err());
+    }
+
+    #[test]
+    fn args_run_rejects_invalid_source_ip() {
+        assert!(
+            Args::new_from(["trackspeedtest", "run", "--source-ip", "not an ip"].iter()).is_err()
+        );
+    }
 }
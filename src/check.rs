@@ -0,0 +1,267 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::path::Path;
+
+use crate::config::Check;
+use crate::error::Error;
+use crate::rollup;
+use crate::storage::{
+    data_dir, lock_summary_file, parse_summary, upgrade_summary_file, write_atomically, BadRow,
+    SUMMARY_HEADER,
+};
+
+/// File the rows removed by a repair are moved to, inside the data directory.
+pub const BAD_ROWS_FILE: &str = "speed.bad.csv";
+
+/// A problem found in the summary file.
+#[derive(Debug, PartialEq)]
+pub enum Problem {
+    /// The header has the columns of an older version. It is also upgraded by
+    /// the next run.
+    OutdatedHeader,
+    /// The last line has no line break, it was cut by an interrupted write.
+    PartialLine(u64),
+    BadRow(BadRow),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::OutdatedHeader => write!(f, "The header has the columns of an older version"),
+            Problem::PartialLine(line) => write!(f, "Line {} is incomplete", line),
+            Problem::BadRow(bad_row) => write!(f, "Malformed row at {}", bad_row),
+        }
+    }
+}
+
+/// What `check_summary_file` found.
+#[derive(Debug, PartialEq)]
+pub struct Report {
+    /// Rows that could be read.
+    pub rows: usize,
+    pub problems: Vec<Problem>,
+    pub repaired: bool,
+}
+
+/// Validates the summary file and prints the problems found, repairing them
+/// when asked to. Fails when there are problems left.
+pub fn check(check: Check) -> Result<(), Error> {
+    let data_dir = data_dir()?;
+    let report = match check_summary_file(&data_dir, check.repair)? {
+        Some(report) => report,
+        None => {
+            println!("There is no summary file yet.");
+            return Ok(());
+        }
+    };
+    println!("Checked {} row(s).", report.rows);
+    for problem in report.problems.iter() {
+        println!("{}.", problem);
+    }
+    if report.problems.is_empty() {
+        println!("No problems found.");
+    } else if report.repaired {
+        println!(
+            "Repaired, malformed rows were moved to {}.",
+            data_dir.join(BAD_ROWS_FILE).display()
+        );
+    } else {
+        return Err(Error::Storage {
+            message: format!(
+                "Found {} problem(s) in the summary file, use --repair to fix them.",
+                report.problems.len()
+            ),
+            source: None,
+        });
+    }
+    Ok(())
+}
+
+/// Looks for problems in the summary file, see `Problem`. A repair truncates a
+/// partial last line, moves malformed rows to `BAD_ROWS_FILE`, upgrades the
+/// header and rebuilds the rollups. The repaired file replaces the summary file
+/// while locked, see `lock_summary_file`, so runs waiting for the lock append to
+/// the repaired file.
+/// Returns `None` when there is no summary file.
+pub fn check_summary_file(data_dir: &Path, repair: bool) -> Result<Option<Report>, Error> {
+    let file_path = data_dir.join("speed.csv");
+    if !file_path.exists() {
+        return Ok(None);
+    }
+    let lock = if repair {
+        Some(lock_summary_file(data_dir)?)
+    } else {
+        None
+    };
+    let mut file = File::open(&file_path)
+        .map_err(|err| Error::storage("Error when opening summary file", err))?;
+    file.lock_shared()
+        .map_err(|err| Error::storage("Error when locking summary file", err))?;
+    let mut contents = vec![];
+    file.read_to_end(&mut contents)
+        .map_err(|err| Error::storage("Error when reading summary file", err))?;
+    let complete = contents
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(0, |last| last + 1);
+    let summary = parse_summary(&contents[..complete])?;
+    let mut problems = vec![];
+    if !summary
+        .header
+        .iter()
+        .eq(SUMMARY_HEADER.iter().map(|column| column.as_bytes()))
+    {
+        problems.push(Problem::OutdatedHeader);
+    }
+    let mut report = Report {
+        rows: summary.rows.len(),
+        problems,
+        repaired: false,
+    };
+    if complete < contents.len() {
+        let line = contents.iter().filter(|byte| **byte == b'\n').count() as u64 + 1;
+        report.problems.push(Problem::PartialLine(line));
+    }
+    if !repair || report.problems.is_empty() && summary.bad_rows.is_empty() {
+        report
+            .problems
+            .extend(summary.bad_rows.into_iter().map(Problem::BadRow));
+        return Ok(Some(report));
+    }
+    if !summary.bad_rows.is_empty() {
+        let records: Vec<&csv::ByteRecord> = summary
+            .bad_rows
+            .iter()
+            .map(|bad_row| &bad_row.record)
+            .collect();
+        append_bad_rows(&data_dir.join(BAD_ROWS_FILE), &summary.header, &records)?;
+    }
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for record in
+        std::iter::once(&summary.header).chain(summary.rows.iter().map(|(record, _)| record))
+    {
+        writer
+            .write_byte_record(record)
+            .map_err(|err| Error::storage("Error when repairing summary file", err))?;
+    }
+    let repaired = writer
+        .into_inner()
+        .map_err(|err| Error::storage("Error when repairing summary file", err))?;
+    write_atomically(&file_path, &repaired)
+        .map_err(|err| Error::storage("Error when writing repaired summary file", err))?;
+    upgrade_summary_file(&file_path)?;
+    drop(lock);
+    rollup::rebuild(data_dir)?;
+    report
+        .problems
+        .extend(summary.bad_rows.into_iter().map(Problem::BadRow));
+    report.repaired = true;
+    Ok(Some(report))
+}
+
+/// Appends the records to the file of bad rows, which starts with the header
+/// of the summary file they came from.
+fn append_bad_rows(
+    path: &Path,
+    header: &csv::ByteRecord,
+    records: &[&csv::ByteRecord],
+) -> Result<(), Error> {
+    let new = !path.exists();
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| Error::storage("Error when opening file of malformed rows", err))?;
+    let mut writer = csv::WriterBuilder::new().flexible(true).from_writer(file);
+    if new {
+        writer
+            .write_byte_record(header)
+            .map_err(|err| Error::storage("Error when writing malformed rows", err))?;
+    }
+    for record in records {
+        writer
+            .write_byte_record(record)
+            .map_err(|err| Error::storage("Error when writing malformed rows", err))?;
+    }
+    writer
+        .flush()
+        .map_err(|err| Error::storage("Error when writing malformed rows", err))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::create_data_dir;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+
+    #[test]
+    fn finds_and_repairs_malformed_rows() {
+        let data_dir = create_data_dir("check-repair");
+        let header = SUMMARY_HEADER.join(",");
+        let row = |date: &str| format!("{},5,100,50,,,,,,,,,,1,,,,,,ok,,,mbps,,,,,,,,,,", date);
+        fs::write(
+            data_dir.join("speed.csv"),
+            format!(
+                "{}\n{}\n{}\n{},extra\n{}\n2021/01/01 04:",
+                header,
+                row("2021/01/01 00:00:00"),
+                row("yesterday"),
+                row("2021/01/01 02:00:00"),
+                row("2021/01/01 03:00:00"),
+            ),
+        )
+        .unwrap();
+        let report = check_summary_file(&data_dir, false).unwrap().unwrap();
+        assert_eq!(2, report.rows);
+        assert_eq!(
+            vec![
+                "Line 6 is incomplete".to_owned(),
+                "Malformed row at line 3: invalid date 'yesterday': input contains invalid characters".to_owned(),
+                "Malformed row at line 4: has 34 fields, the header has 33".to_owned(),
+            ],
+            report
+                .problems
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        );
+        assert!(!report.repaired);
+        assert!(
+            check_summary_file(&data_dir, true)
+                .unwrap()
+                .unwrap()
+                .repaired
+        );
+        assert_eq!(
+            format!(
+                "{}\n{}\n{}\n",
+                header,
+                row("2021/01/01 00:00:00"),
+                row("2021/01/01 03:00:00")
+            ),
+            fs::read_to_string(data_dir.join("speed.csv")).unwrap()
+        );
+        assert_eq!(
+            format!(
+                "{}\n{}\n{},extra\n",
+                header,
+                row("yesterday"),
+                row("2021/01/01 02:00:00")
+            ),
+            fs::read_to_string(data_dir.join(BAD_ROWS_FILE)).unwrap()
+        );
+        assert!(data_dir.join(rollup::ROLLUP_DIR).join("daily.csv").exists());
+        assert_eq!(
+            Report {
+                rows: 2,
+                problems: vec![],
+                repaired: false
+            },
+            check_summary_file(&data_dir, false).unwrap().unwrap()
+        );
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+}
@@ -0,0 +1,399 @@
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::probe::Target;
+use crate::timezone::Zone;
+use crate::units::Unit;
+
+/// Options for `run::run`.
+#[derive(Debug)]
+pub struct Run {
+    pub simulate: bool,
+    pub email_options: Option<EmailOptions>,
+    /// Prints the results to stdout in this format.
+    pub show_results: Option<OutputFormat>,
+    /// Unit of the bandwidths shown with `show_results`.
+    pub units: Unit,
+    /// Zone of the timestamps shown with `show_results`.
+    pub timezone: Zone,
+    pub interface: Option<String>,
+    pub source_ip: Option<String>,
+    pub repeat: u8,
+    pub different_servers: bool,
+    pub aggregation: Aggregation,
+    pub retries: u8,
+    pub retry_delay: std::time::Duration,
+    pub retry_other_server: bool,
+    pub timeout: Option<std::time::Duration>,
+    /// Host and port the latency is sampled to while idle and under load.
+    pub latency_target: Option<String>,
+    /// Sends every result to InfluxDB.
+    pub influx: Option<Influx>,
+    /// Publishes the latest result to an MQTT broker.
+    pub mqtt: Option<Mqtt>,
+    /// Prunes the raw results after each run.
+    pub retention: Option<Retention>,
+}
+
+/// What is written to the summary file when there is more than one measurement.
+#[derive(Debug, PartialEq)]
+pub enum Aggregation {
+    Samples,
+    /// A single row with the medians of the samples, see
+    /// `aggregate::aggregate_results`. The row takes the date, server and raw
+    /// file of the first sample, so it links to the raw JSON of that sample
+    /// only; the files of the others stay in the data directory, named after
+    /// their own dates.
+    Median,
+}
+
+/// How `run` prints its results, see `output::format_results`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// The download, upload and ping of each result, a number per line, as
+    /// printed by earlier versions.
+    Plain,
+    Json,
+    Csv,
+    Table,
+    Text,
+}
+
+/// Options for `alert::alert`.
+#[derive(Debug)]
+pub struct Alert {
+    pub simulate: bool,
+    pub email: String,
+    pub expected_download: f64,
+    pub expected_upload: f64,
+    /// Unit of the expected bandwidths and of the e-mail message.
+    pub units: Unit,
+    /// Zone of the dates in the e-mail message.
+    pub timezone: Zone,
+    pub threshold: u8,
+    pub count: u8,
+    pub smtp: Smtp,
+    pub interface: Option<String>,
+    pub failures: Option<u8>,
+    pub failures_window: u8,
+    /// Maximum average latency under load, in milliseconds.
+    pub max_loaded_latency: Option<f64>,
+}
+
+/// SMTP server used to send e-mail messages.
+#[derive(Debug)]
+pub struct Smtp {
+    pub server: String,
+    pub email: String,
+    pub port: u16,
+    pub credentials: Option<Credentials>,
+}
+
+#[derive(Derivative, PartialEq)]
+#[derivative(Debug)]
+pub struct Credentials {
+    pub username: String,
+    #[derivative(Debug(format_with = "redact"))]
+    pub password: String,
+}
+
+/// Hides a secret in debug output, as the options are logged.
+fn redact(_: &String, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "***")
+}
+
+/// An InfluxDB server, see `influx::push`.
+#[derive(Debug)]
+pub struct Influx {
+    /// Base URL of the server, like `http://localhost:8086`.
+    pub url: String,
+    pub target: InfluxTarget,
+    /// Value of the `host` tag.
+    pub host: String,
+}
+
+/// Where the points are written to, depending on the version of InfluxDB.
+#[derive(Derivative, PartialEq)]
+#[derivative(Debug)]
+pub enum InfluxTarget {
+    /// InfluxDB 1.x, written to `/write`.
+    V1 {
+        database: String,
+        credentials: Option<Credentials>,
+    },
+    /// InfluxDB 2.x, written to `/api/v2/write`.
+    V2 {
+        org: String,
+        bucket: String,
+        #[derivative(Debug(format_with = "redact"))]
+        token: String,
+    },
+}
+
+/// An MQTT broker, see `mqtt::publish`.
+#[derive(Debug)]
+pub struct Mqtt {
+    pub host: String,
+    pub port: u16,
+    pub credentials: Option<Credentials>,
+    /// Prefix of the state topic, `<topic>/<node_id>/state`.
+    pub topic: String,
+    /// Prefix of the Home Assistant discovery topics, usually `homeassistant`.
+    pub discovery_prefix: String,
+    /// Identifies this machine in topics and in Home Assistant.
+    pub node_id: String,
+}
+
+/// Options for `export::export`.
+#[derive(Debug)]
+pub struct Export {
+    pub format: ExportFormat,
+    pub interface: Option<String>,
+    /// Value of the `host` tag, for formats that have one.
+    pub host: String,
+    /// Only measurements taken from this date on are exported.
+    pub from: Option<DateTime<Utc>>,
+    /// Only measurements taken before this date are exported.
+    pub to: Option<DateTime<Utc>>,
+}
+
+/// Formats the summary file can be exported to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    /// InfluxDB line protocol, one line per successful measurement.
+    Influx,
+    /// A JSON object per line, with the columns of `export::COLUMNS`.
+    Ndjson,
+    /// CSV with a header, with the columns of `export::COLUMNS`.
+    Csv,
+    /// A Parquet file, with the columns of `export::COLUMNS`.
+    Parquet,
+}
+
+/// Options for `server::serve`.
+#[derive(Debug)]
+pub struct Serve {
+    /// Address and port to listen on, like `127.0.0.1:8080`.
+    pub address: String,
+    /// Unit of the bandwidths shown.
+    pub units: Unit,
+    pub interface: Option<String>,
+    pub expectation: Option<Expectation>,
+    /// How many measurements make up the average of `/alerts/state`, like `alert --count`.
+    pub count: u8,
+    /// How many measurements are checked for failures, like `alert --failures-window`.
+    pub failures_window: u8,
+}
+
+/// Options for `prune::prune`.
+#[derive(Debug)]
+pub struct Prune {
+    pub retention: Retention,
+    /// Only lists what would be archived or deleted.
+    pub dry_run: bool,
+}
+
+/// Options for `import::import`.
+#[derive(Debug)]
+pub struct Import {
+    pub files: Vec<PathBuf>,
+    /// Format of the files, detected from each of them when `None`.
+    pub format: Option<ImportFormat>,
+    /// Only reports what would be imported.
+    pub dry_run: bool,
+}
+
+/// Formats of history that can be imported into the summary file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportFormat {
+    /// The .json files of the Node.js tool, one measurement (or an array of them) per file.
+    NodeJson,
+    /// A summary file of the Node.js tool or of this one, with a `date` column.
+    NodeCsv,
+    /// The output of `speedtest-cli --csv`, with or without `--csv-header`.
+    SpeedtestCli,
+}
+
+/// Options for `check::check`.
+#[derive(Debug)]
+pub struct Check {
+    /// Fixes the problems found instead of only listing them.
+    pub repair: bool,
+}
+
+/// Options for `probe::probe`.
+#[derive(Debug)]
+pub struct Probe {
+    pub targets: Vec<Target>,
+    /// How many times each target is probed in a round.
+    pub attempts: u8,
+    /// How long each attempt waits for an answer.
+    pub timeout: std::time::Duration,
+    /// Time between the start of two rounds, a single round is run when `None`.
+    pub interval: Option<std::time::Duration>,
+    /// Shortest outage that is alerted, once connectivity is back.
+    pub max_outage: Option<chrono::Duration>,
+    /// Maximum average packet loss, in percent.
+    pub max_packet_loss: Option<f64>,
+    /// How many rounds the packet loss is averaged over.
+    pub loss_window: usize,
+    pub simulate: bool,
+    pub email_options: Option<EmailOptions>,
+    /// Zone of the dates in the e-mail messages.
+    pub timezone: Zone,
+}
+
+/// How long the raw results (the JSON of each measurement) are kept, see
+/// `prune::plan`. The summary file is never pruned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Retention {
+    /// Days the raw results are kept in the data directory, `None` to keep them.
+    pub keep_raw_days: Option<u32>,
+    /// Whether raw results are moved to monthly bundles in `archive/` instead
+    /// of deleted.
+    pub archive: bool,
+    /// Months the bundles are kept after the end of their month, `None` to keep them.
+    pub delete_archives_after_months: Option<u32>,
+}
+
+/// Options for `history::history`.
+#[derive(Debug)]
+pub struct History {
+    /// How far back the charts go.
+    pub last: chrono::Duration,
+    pub style: ChartStyle,
+    /// Columns of the terminal the charts have to fit in.
+    pub width: usize,
+    /// Unit of the bandwidths shown.
+    pub units: Unit,
+    /// Zone of the dates shown.
+    pub timezone: Zone,
+    pub interface: Option<String>,
+    pub expectation: Option<Expectation>,
+}
+
+/// How `history` draws each series.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChartStyle {
+    /// A line chart several rows high, with labelled axes.
+    Chart,
+    /// A single row of block characters.
+    Sparkline,
+}
+
+/// Expected bandwidths, in the unit they are shown in, with the same meaning as
+/// the ones given to `alert`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expectation {
+    pub download: f64,
+    pub upload: f64,
+    /// Percentage the bandwidths can be bellow the expected ones.
+    pub threshold: u8,
+}
+
+impl Expectation {
+    /// The lowest download that is not bellow expectation.
+    pub fn minimum_download(&self) -> f64 {
+        self.download * (1.0 - self.threshold as f64 / 100.0)
+    }
+
+    /// The lowest upload that is not bellow expectation.
+    pub fn minimum_upload(&self) -> f64 {
+        self.upload * (1.0 - self.threshold as f64 / 100.0)
+    }
+}
+
+/// Parses a period like `24h`, `7d` or `2w`.
+pub fn parse_period(text: &str) -> Result<chrono::Duration, String> {
+    let invalid = || {
+        format!(
+            "Invalid period '{}', use a number followed by h, d or w (like 7d).",
+            text
+        )
+    };
+    let (count, unit) = match text.char_indices().last() {
+        Some((index, _)) => text.split_at(index),
+        None => return Err(invalid()),
+    };
+    let count = count.parse::<i64>().map_err(|_| invalid())?;
+    if count <= 0 {
+        return Err(invalid());
+    }
+    match unit {
+        "h" => chrono::Duration::try_hours(count),
+        "d" => chrono::Duration::try_days(count),
+        "w" => chrono::Duration::try_weeks(count),
+        _ => None,
+    }
+    .ok_or_else(invalid)
+}
+
+/// Parses a date of a command line filter, like `2021-01-31`, at midnight UTC,
+/// or `2021-01-31T10:00:00Z`. Rows of the summary file are parsed by
+/// `storage::parse_date`.
+pub fn parse_date_filter(text: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(text)
+        .map(|date| date.with_timezone(&Utc))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(text, "%Y-%m-%d")
+                .map(|date| date.and_time(NaiveTime::MIN).and_utc())
+        })
+        .map_err(|_| {
+            format!(
+                "Invalid date '{}', use a date like 2021-01-31 or 2021-01-31T10:00:00Z.",
+                text
+            )
+        })
+}
+
+/// Where to send e-mail messages to, and through which server.
+#[derive(Debug)]
+pub struct EmailOptions {
+    pub email: String,
+    pub smtp: Smtp,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn hides_secrets_in_debug_output() {
+        let credentials = Credentials {
+            username: "user".to_owned(),
+            password: "secret".to_owned(),
+        };
+        let target = InfluxTarget::V2 {
+            org: "org".to_owned(),
+            bucket: "bucket".to_owned(),
+            token: "secret".to_owned(),
+        };
+        let debug = format!("{:?} {:?}", credentials, target);
+        assert!(!debug.contains("secret"), "{}", debug);
+        assert!(debug.contains("user"), "{}", debug);
+    }
+
+    #[test]
+    fn parses_periods() {
+        assert_eq!(Ok(chrono::Duration::hours(24)), parse_period("24h"));
+        assert_eq!(Ok(chrono::Duration::days(7)), parse_period("7d"));
+        assert_eq!(Ok(chrono::Duration::weeks(2)), parse_period("2w"));
+        for invalid in [
+            "",
+            "d",
+            "7",
+            "0d",
+            "-1d",
+            "7m",
+            "7 d",
+            "7é",
+            "9999999999999999d",
+            "9999999999999999h",
+            "9999999999999999w",
+        ] {
+            assert!(parse_period(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,356 @@
+use chrono::{DateTime, Utc};
+use std::path::Path;
+
+use crate::aggregate::Stats;
+use crate::config::{parse_period, Serve};
+use crate::error::Error;
+use crate::rollup::{self, Rollup};
+use crate::storage::{read_latest_results, read_results, read_results_since, ResultCsv};
+
+/// Ranges that can be selected, with their labels.
+const RANGES: [(&str, &str); 6] = [
+    ("24h", "24 hours"),
+    ("7d", "7 days"),
+    ("30d", "30 days"),
+    ("13w", "3 months"),
+    ("52w", "1 year"),
+    ("all", "All"),
+];
+const DEFAULT_RANGE: &str = "7d";
+const RECENT_RUNS: u8 = 20;
+
+const WIDTH: f64 = 900.0;
+const HEIGHT: f64 = 220.0;
+const LEFT: f64 = 50.0;
+const RIGHT: f64 = 10.0;
+const TOP: f64 = 10.0;
+const BOTTOM: f64 = 25.0;
+
+/// A chart of one of the values of the measurements.
+struct Chart<'a> {
+    title: &'a str,
+    unit: &'a str,
+    points: Vec<(DateTime<Utc>, f64)>,
+    /// Drawn as a reference line.
+    expected: Option<f64>,
+    /// Points bellow this value are highlighted.
+    minimum: Option<f64>,
+}
+
+/// Renders the dashboard page with the measurements in `range` (like `7d` or
+/// `all`), a point per day for ranges of at least `rollup::LONG_RANGE`.
+pub fn render(serve: &Serve, data_dir: &Path, range: Option<&str>) -> Result<String, Error> {
+    let now = Utc::now();
+    let range = match range {
+        Some(range) if range == "all" || parse_period(range).is_ok() => range,
+        _ => DEFAULT_RANGE,
+    };
+    let from = parse_period(range)
+        .ok()
+        .and_then(|period| now.checked_sub_signed(period));
+    // long ranges of every interface are charted from the daily rollups, so
+    // that the whole summary file is not read
+    let long =
+        serve.interface.is_none() && from.is_none_or(|from| now - from >= rollup::LONG_RANGE);
+    let (measurements, failures, recent) = if long {
+        let rollups = rollup::read_daily(data_dir, from, serve.units)?;
+        let failures: Vec<(DateTime<Utc>, String)> = rollups
+            .iter()
+            .filter(|rollup| rollup.failed > 0)
+            .map(|rollup| (rollup.start, format!("{} failed", rollup.failed)))
+            .collect();
+        let recent = read_latest_results(data_dir, RECENT_RUNS, None, |row| {
+            from.is_none_or(|from| row.date >= from)
+        })?;
+        (rollups, failures, recent)
+    } else {
+        let rows: Vec<ResultCsv> = match from {
+            Some(from) => read_results_since(data_dir, from)?,
+            None => read_results(data_dir)?,
+        }
+        .into_iter()
+        .filter(|row| {
+            serve.interface.is_none() || row.interface.as_deref() == serve.interface.as_deref()
+        })
+        .collect();
+        let failures: Vec<(DateTime<Utc>, String)> = rows
+            .iter()
+            .filter(|row| row.failed())
+            .map(|row| {
+                (
+                    row.date,
+                    row.error_category
+                        .map_or("failed".to_owned(), |category| category.to_string()),
+                )
+            })
+            .collect();
+        let measurements: Vec<Rollup> = rows
+            .iter()
+            .map(|row| rollup::from_row(row, serve.units))
+            .collect();
+        let recent = rows.into_iter().rev().take(RECENT_RUNS as usize).collect();
+        (measurements, failures, recent)
+    };
+    let start = from.unwrap_or_else(|| measurements.first().map_or(now, |rollup| rollup.start));
+    let points = |stats: fn(&Rollup) -> Option<&Stats>| {
+        measurements
+            .iter()
+            .filter_map(|rollup| Some((rollup.start, stats(rollup)?.mean)))
+            .collect()
+    };
+    let unit = serve.units.symbol();
+    let charts = [
+        Chart {
+            title: "Download",
+            unit,
+            points: points(|rollup| rollup.download.as_ref()),
+            expected: serve
+                .expectation
+                .as_ref()
+                .map(|expectation| expectation.download),
+            minimum: serve
+                .expectation
+                .as_ref()
+                .map(|expectation| expectation.minimum_download()),
+        },
+        Chart {
+            title: "Upload",
+            unit,
+            points: points(|rollup| rollup.upload.as_ref()),
+            expected: serve
+                .expectation
+                .as_ref()
+                .map(|expectation| expectation.upload),
+            minimum: serve
+                .expectation
+                .as_ref()
+                .map(|expectation| expectation.minimum_upload()),
+        },
+        Chart {
+            title: "Ping",
+            unit: "ms",
+            points: points(|rollup| rollup.ping.as_ref()),
+            expected: None,
+            minimum: None,
+        },
+    ];
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>Speed test</title>\n<style>\n\
+         body { font-family: sans-serif; margin: 1em 2em; color: #222; }\n\
+         nav a { margin-right: 1em; }\n\
+         nav a.selected { font-weight: bold; }\n\
+         svg { display: block; margin-bottom: 1.5em; }\n\
+         table { border-collapse: collapse; }\n\
+         th, td { padding: 0.2em 0.8em; text-align: left; border-bottom: 1px solid #ddd; }\n\
+         tr.failed { color: #c00; }\n\
+         </style>\n</head>\n<body>\n<h1>Speed test</h1>\n<nav>",
+    );
+    for (value, label) in RANGES {
+        html.push_str(&format!(
+            "<a href=\"/?range={}\"{}>{}</a>",
+            value,
+            if value == range {
+                " class=\"selected\""
+            } else {
+                ""
+            },
+            label
+        ));
+    }
+    html.push_str("</nav>\n");
+    for chart in charts.iter() {
+        html.push_str(&format!("<h2>{} ({})</h2>\n", chart.title, chart.unit));
+        html.push_str(&render_chart(chart, &failures, start, now));
+    }
+    html.push_str(
+        "<h2>Recent runs</h2>\n<table>\n<tr><th>Date</th><th>Status</th><th>Download</th><th>Upload</th><th>Ping</th><th>Server</th><th>Details</th></tr>\n",
+    );
+    for row in recent.iter() {
+        let number =
+            |value: Option<f64>| value.map_or(String::new(), |value| format!("{:.2}", value));
+        let details = if row.failed() {
+            escape(row.error_message.as_deref().unwrap_or_default())
+        } else {
+            format!("<a href=\"/raw/{}\">json</a>", row.raw_file_name())
+        };
+        html.push_str(&format!(
+            "<tr{}><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            if row.failed() { " class=\"failed\"" } else { "" },
+            row.date.format("%Y-%m-%d %H:%M:%S"),
+            if row.failed() {
+                escape(&format!(
+                    "failed ({})",
+                    row.error_category
+                        .map_or("unknown".to_owned(), |category| category.to_string())
+                ))
+            } else {
+                "ok".to_owned()
+            },
+            number(row.download_in(serve.units)),
+            number(row.upload_in(serve.units)),
+            number(row.ping),
+            escape(row.server_host.as_deref().unwrap_or_default()),
+            details
+        ));
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    Ok(html)
+}
+
+/// Renders a line chart as SVG, with a tooltip on each point and failures as red
+/// vertical lines.
+fn render_chart(
+    chart: &Chart,
+    failures: &[(DateTime<Utc>, String)],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> String {
+    let max = chart
+        .points
+        .iter()
+        .map(|(_, value)| *value)
+        .chain(chart.expected)
+        .fold(0.0, f64::max)
+        .max(1.0)
+        * 1.1;
+    let span = (to - from).num_seconds().max(1) as f64;
+    let x = |date: &DateTime<Utc>| {
+        LEFT + (*date - from).num_seconds() as f64 / span * (WIDTH - LEFT - RIGHT)
+    };
+    let y = |value: f64| TOP + (1.0 - value / max) * (HEIGHT - TOP - BOTTOM);
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n\
+         <line x1=\"{l}\" y1=\"{b}\" x2=\"{r}\" y2=\"{b}\" stroke=\"#999\"/>\n\
+         <line x1=\"{l}\" y1=\"{t}\" x2=\"{l}\" y2=\"{b}\" stroke=\"#999\"/>\n\
+         <text x=\"{tx}\" y=\"{t2}\" font-size=\"11\" text-anchor=\"end\">{max:.0}</text>\n\
+         <text x=\"{tx}\" y=\"{b}\" font-size=\"11\" text-anchor=\"end\">0</text>\n\
+         <text x=\"{l}\" y=\"{h2}\" font-size=\"11\">{from}</text>\n\
+         <text x=\"{r}\" y=\"{h2}\" font-size=\"11\" text-anchor=\"end\">{to}</text>\n",
+        w = WIDTH,
+        h = HEIGHT,
+        l = LEFT,
+        r = WIDTH - RIGHT,
+        t = TOP,
+        b = HEIGHT - BOTTOM,
+        tx = LEFT - 5.0,
+        t2 = TOP + 10.0,
+        h2 = HEIGHT - 5.0,
+        max = max,
+        from = from.format("%Y-%m-%d %H:%M"),
+        to = to.format("%Y-%m-%d %H:%M"),
+    );
+    for (date, category) in failures {
+        svg.push_str(&format!(
+            "<line class=\"failure\" x1=\"{x:.1}\" y1=\"{t}\" x2=\"{x:.1}\" y2=\"{b}\" stroke=\"#c00\" stroke-opacity=\"0.5\"><title>{date}: {category}</title></line>\n",
+            x = x(date),
+            t = TOP,
+            b = HEIGHT - BOTTOM,
+            date = date.format("%Y-%m-%d %H:%M"),
+            category = escape(category),
+        ));
+    }
+    for (class, value, color) in [
+        ("expected", chart.expected, "#393"),
+        ("minimum", chart.minimum, "#c90"),
+    ] {
+        if let Some(value) = value {
+            svg.push_str(&format!(
+                "<line class=\"{class}\" x1=\"{l}\" y1=\"{y:.1}\" x2=\"{r}\" y2=\"{y:.1}\" stroke=\"{color}\" stroke-dasharray=\"6 4\"><title>{class}: {value:.2} {unit}</title></line>\n",
+                l = LEFT,
+                r = WIDTH - RIGHT,
+                y = y(value),
+                unit = chart.unit,
+            ));
+        }
+    }
+    let line: Vec<String> = chart
+        .points
+        .iter()
+        .map(|(date, value)| format!("{:.1},{:.1}", x(date), y(*value)))
+        .collect();
+    svg.push_str(&format!(
+        "<polyline fill=\"none\" stroke=\"#36c\" stroke-width=\"1.5\" points=\"{}\"/>\n",
+        line.join(" ")
+    ));
+    for (date, value) in chart.points.iter() {
+        let bellow = chart.minimum.is_some_and(|minimum| *value < minimum);
+        svg.push_str(&format!(
+            "<circle{} cx=\"{:.1}\" cy=\"{:.1}\" r=\"{}\" fill=\"{}\"><title>{}: {:.2} {}</title></circle>\n",
+            if bellow { " class=\"bellow\"" } else { "" },
+            x(date),
+            y(*value),
+            if bellow { 4 } else { 2 },
+            if bellow { "#c00" } else { "#36c" },
+            date.format("%Y-%m-%d %H:%M"),
+            value,
+            chart.unit
+        ));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Escapes text to be written in HTML.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Expectation;
+    use crate::test_support::create_data_dir;
+    use crate::units::Unit;
+    use chrono::Duration;
+    use std::fs;
+
+    #[test]
+    fn renders_charts_with_expectation_failures_and_recent_runs() {
+        let data_dir = create_data_dir("dashboard");
+        let date =
+            |hours_ago| (Utc::now() - Duration::hours(hours_ago)).format("%Y/%m/%d %H:%M:%S");
+        fs::write(
+            data_dir.join("speed.csv"),
+            format!(
+                "date,ping,speeds_download,speeds_upload,server_host,status,error_category,error_message,speeds_unit\n\
+                 {},5,100,50,h.com,ok,,,mbps\n\
+                 {},,,,,failed,timeout,<killed>,\n\
+                 {},6,20,50,h.com,ok,,,mbps\n\
+                 {},6,20,50,h.com,ok,,,mbps\n",
+                date(24 * 30),
+                date(3),
+                date(2),
+                date(1)
+            ),
+        )
+        .unwrap();
+        let serve = Serve {
+            address: "".to_owned(),
+            units: Unit::Mbps,
+            interface: None,
+            expectation: Some(Expectation {
+                download: 100.0,
+                upload: 50.0,
+                threshold: 20,
+            }),
+            count: 8,
+            failures_window: 8,
+        };
+        let html = render(&serve, &data_dir, Some("7d")).unwrap();
+        assert_eq!(3, html.matches("<svg").count());
+        assert_eq!(2, html.matches("class=\"expected\"").count());
+        assert_eq!(2, html.matches("class=\"minimum\"").count());
+        assert_eq!(2, html.matches("class=\"bellow\"").count());
+        assert_eq!(3, html.matches("class=\"failure\"").count());
+        assert!(html.contains("<a href=\"/?range=7d\" class=\"selected\">"));
+        assert_eq!(2, html.matches("<a href=\"/raw/").count());
+        assert!(html.contains("&lt;killed&gt;"));
+        let html = render(&serve, &data_dir, Some("all")).unwrap();
+        assert_eq!(3, html.matches("<a href=\"/raw/").count());
+        assert!(html.contains(": 1 failed</title>"), "daily rollups");
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+}
@@ -0,0 +1,175 @@
+use serde::Deserialize;
+use std::fmt;
+use std::time::Duration;
+
+use crate::probe::{self, Target, Uplink};
+
+/// Name resolved to check DNS, the one Ookla's CLI needs first.
+const DNS_NAME: &str = "www.speedtest.net";
+
+/// Public addresses, of two providers, checked for connectivity past the ISP.
+const PUBLIC_TARGETS: [&str; 2] = ["1.1.1.1:443", "8.8.8.8:53"];
+
+/// Attempts of each check, and how long each waits for an answer.
+const ATTEMPTS: u8 = 2;
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Where a failed measurement most likely failed, as stored in the `fault`
+/// column of the summary file.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fault {
+    /// There is no default gateway.
+    LocalNetwork,
+    /// Names do not resolve, but the internet is reachable.
+    Dns,
+    /// The gateway answers, but nothing past it does.
+    Upstream,
+    /// Everything answers, so the speed test service itself failed.
+    SpeedtestService,
+    /// Nothing past the gateway answers, and neither does the gateway on the
+    /// port probed, which many do not listen on.
+    Unknown,
+}
+
+impl Fault {
+    /// How the fault is named in messages.
+    pub fn description(self) -> &'static str {
+        match self {
+            Fault::LocalNetwork => "local network",
+            Fault::Dns => "DNS",
+            Fault::Upstream => "ISP/upstream",
+            Fault::SpeedtestService => "speedtest service",
+            Fault::Unknown => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Fault::LocalNetwork => "local_network",
+            Fault::Dns => "dns",
+            Fault::Upstream => "upstream",
+            Fault::SpeedtestService => "speedtest_service",
+            Fault::Unknown => "unknown",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+/// What the check of the default gateway found.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gateway {
+    /// There is no default route.
+    Missing,
+    Answered,
+    /// The gateway did not answer on the port probed, which does not mean it is down.
+    Silent,
+}
+
+/// The checks run after a measurement failed, and what they point to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnosis {
+    pub gateway: Gateway,
+    pub dns: bool,
+    /// Whether any of the public addresses answered.
+    pub public_ip: bool,
+    pub fault: Fault,
+}
+
+impl Diagnosis {
+    /// Runs the checks through the uplink of the measurement, all at the same
+    /// time, which takes up to a few seconds.
+    pub fn run(uplink: &Uplink) -> Diagnosis {
+        let gateway = probe::default_gateway(uplink.interface().as_deref());
+        let mut targets = vec![Target::Dns(DNS_NAME.to_owned())];
+        targets.extend(
+            PUBLIC_TARGETS
+                .iter()
+                .map(|address| Target::Tcp((*address).to_owned())),
+        );
+        if gateway.is_some() {
+            targets.push(Target::Gateway(probe::GATEWAY_PORT));
+        }
+        let answered: Vec<bool> = probe::probe_round(&targets, uplink, ATTEMPTS, TIMEOUT)
+            .iter()
+            .map(|row| row.received > 0)
+            .collect();
+        let gateway = match gateway {
+            None => Gateway::Missing,
+            Some(_) if answered[3] => Gateway::Answered,
+            Some(_) => Gateway::Silent,
+        };
+        Diagnosis::from_checks(gateway, answered[0], answered[1] || answered[2])
+    }
+
+    /// Classifies the results of the checks. When the internet is reachable the
+    /// gateway does not matter, and DNS failing without it says nothing more.
+    pub fn from_checks(gateway: Gateway, dns: bool, public_ip: bool) -> Diagnosis {
+        let fault = match (gateway, dns, public_ip) {
+            (_, true, true) => Fault::SpeedtestService,
+            (_, false, true) => Fault::Dns,
+            (Gateway::Answered, _, false) => Fault::Upstream,
+            (Gateway::Missing, _, false) => Fault::LocalNetwork,
+            (Gateway::Silent, _, false) => Fault::Unknown,
+        };
+        Diagnosis {
+            gateway,
+            dns,
+            public_ip,
+            fault,
+        }
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let check = |ok: bool| if ok { "ok" } else { "failed" };
+        writeln!(f, "Likely cause: {}.", self.fault.description())?;
+        let gateway = match self.gateway {
+            Gateway::Missing => "not found",
+            Gateway::Answered => "ok",
+            Gateway::Silent => "no answer on port 53",
+        };
+        writeln!(f, "Default gateway: {}", gateway)?;
+        writeln!(f, "DNS resolution of {}: {}", DNS_NAME, check(self.dns))?;
+        write!(
+            f,
+            "Public IPs ({}): {}",
+            PUBLIC_TARGETS.join(", "),
+            check(self.public_ip)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn classifies_the_checks() {
+        let fault = |gateway, dns, public_ip| Diagnosis::from_checks(gateway, dns, public_ip).fault;
+        assert_eq!(Fault::LocalNetwork, fault(Gateway::Missing, false, false));
+        assert_eq!(Fault::Unknown, fault(Gateway::Silent, false, false));
+        assert_eq!(Fault::Unknown, fault(Gateway::Silent, true, false));
+        assert_eq!(Fault::Upstream, fault(Gateway::Answered, false, false));
+        assert_eq!(Fault::Upstream, fault(Gateway::Answered, true, false));
+        assert_eq!(Fault::Dns, fault(Gateway::Answered, false, true));
+        assert_eq!(Fault::Dns, fault(Gateway::Silent, false, true));
+        assert_eq!(
+            Fault::SpeedtestService,
+            fault(Gateway::Answered, true, true)
+        );
+        assert_eq!(Fault::SpeedtestService, fault(Gateway::Missing, true, true));
+        assert_eq!("speedtest_service", Fault::SpeedtestService.to_string());
+        assert_eq!(
+            "Likely cause: ISP/upstream.\n\
+             Default gateway: ok\n\
+             DNS resolution of www.speedtest.net: failed\n\
+             Public IPs (1.1.1.1:443, 8.8.8.8:53): failed",
+            Diagnosis::from_checks(Gateway::Answered, false, false).to_string()
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use std::error::Error as StdError;
+use std::fmt;
+
+use crate::results::ErrorCategory;
+
+type Source = Box<dyn StdError + Send + Sync>;
+
+/// Errors returned by this crate. Each kind of error has its own process exit code,
+/// see [`Error::exit_code`].
+#[derive(Debug)]
+pub enum Error {
+    /// Invalid configuration, like a malformed e-mail address.
+    Config {
+        message: String,
+        source: Option<Source>,
+    },
+    /// The speedtest binary could not be found or started.
+    BinaryNotFound {
+        message: String,
+        source: Option<Source>,
+    },
+    /// The speedtest process exited with an error.
+    ProcessFailed {
+        message: String,
+        source: Option<Source>,
+    },
+    /// The speedtest process did not finish in time and was killed.
+    Timeout {
+        message: String,
+        source: Option<Source>,
+    },
+    /// The output of the speedtest process could not be parsed.
+    Parse {
+        message: String,
+        source: Option<Source>,
+    },
+    /// Stored results could not be read or written.
+    Storage {
+        message: String,
+        source: Option<Source>,
+    },
+    /// An e-mail message could not be sent.
+    Mail {
+        message: String,
+        source: Option<Source>,
+    },
+    /// Results could not be sent to an external service, like InfluxDB.
+    Publish {
+        message: String,
+        source: Option<Source>,
+    },
+    /// Results could not be formatted to be shown.
+    Output {
+        message: String,
+        source: Option<Source>,
+    },
+}
+
+impl Error {
+    pub fn config(message: impl Into<String>, source: impl Into<Source>) -> Error {
+        Error::Config {
+            message: message.into(),
+            source: Some(source.into()),
+        }
+    }
+
+    pub fn storage(message: impl Into<String>, source: impl Into<Source>) -> Error {
+        Error::Storage {
+            message: message.into(),
+            source: Some(source.into()),
+        }
+    }
+
+    pub fn mail(message: impl Into<String>, source: impl Into<Source>) -> Error {
+        Error::Mail {
+            message: message.into(),
+            source: Some(source.into()),
+        }
+    }
+
+    pub fn publish(message: impl Into<String>, source: impl Into<Source>) -> Error {
+        Error::Publish {
+            message: message.into(),
+            source: Some(source.into()),
+        }
+    }
+
+    pub fn output(message: impl Into<String>, source: impl Into<Source>) -> Error {
+        Error::Output {
+            message: message.into(),
+            source: Some(source.into()),
+        }
+    }
+
+    /// Creates the error for a failed measurement of the given category.
+    pub fn measurement(
+        category: ErrorCategory,
+        message: impl Into<String>,
+        source: Option<Source>,
+    ) -> Error {
+        let message = message.into();
+        match category {
+            ErrorCategory::BinaryMissing => Error::BinaryNotFound { message, source },
+            ErrorCategory::Timeout => Error::Timeout { message, source },
+            ErrorCategory::ParseError => Error::Parse { message, source },
+            ErrorCategory::NonZeroExit => Error::ProcessFailed { message, source },
+        }
+    }
+
+    /// The category stored in the summary file, for errors that fail a measurement.
+    pub fn category(&self) -> Option<ErrorCategory> {
+        match self {
+            Error::BinaryNotFound { .. } => Some(ErrorCategory::BinaryMissing),
+            Error::ProcessFailed { .. } => Some(ErrorCategory::NonZeroExit),
+            Error::Timeout { .. } => Some(ErrorCategory::Timeout),
+            Error::Parse { .. } => Some(ErrorCategory::ParseError),
+            Error::Config { .. }
+            | Error::Storage { .. }
+            | Error::Mail { .. }
+            | Error::Publish { .. }
+            | Error::Output { .. } => None,
+        }
+    }
+
+    /// The process exit code for this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config { .. } => 2,
+            Error::BinaryNotFound { .. } => 3,
+            Error::ProcessFailed { .. } => 4,
+            Error::Timeout { .. } => 5,
+            Error::Parse { .. } => 6,
+            Error::Storage { .. } => 7,
+            Error::Mail { .. } => 8,
+            Error::Publish { .. } => 9,
+            Error::Output { .. } => 10,
+        }
+    }
+
+    /// The message of this error followed by the messages of its sources, one per line.
+    pub fn describe(&self) -> String {
+        let mut description = self.to_string();
+        let mut source = self.source();
+        while let Some(err) = source {
+            description.push_str(&format!("\nCaused by: {}", err));
+            source = err.source();
+        }
+        description
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            Error::Config { message, .. }
+            | Error::BinaryNotFound { message, .. }
+            | Error::ProcessFailed { message, .. }
+            | Error::Timeout { message, .. }
+            | Error::Parse { message, .. }
+            | Error::Storage { message, .. }
+            | Error::Mail { message, .. }
+            | Error::Publish { message, .. }
+            | Error::Output { message, .. } => message,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Config { source, .. }
+            | Error::BinaryNotFound { source, .. }
+            | Error::ProcessFailed { source, .. }
+            | Error::Timeout { source, .. }
+            | Error::Parse { source, .. }
+            | Error::Storage { source, .. }
+            | Error::Mail { source, .. }
+            | Error::Publish { source, .. }
+            | Error::Output { source, .. } => source
+                .as_ref()
+                .map(|source| source.as_ref() as &(dyn StdError + 'static)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn describe_includes_the_sources() {
+        let err = Error::storage(
+            "Error when writing to file",
+            std::io::Error::other("disk full"),
+        );
+        assert_eq!(
+            "Error when writing to file\nCaused by: disk full",
+            err.describe()
+        );
+    }
+
+    #[test]
+    fn measurement_errors_keep_their_category() {
+        for category in [
+            ErrorCategory::BinaryMissing,
+            ErrorCategory::Timeout,
+            ErrorCategory::ParseError,
+            ErrorCategory::NonZeroExit,
+        ] {
+            assert_eq!(
+                Some(category),
+                Error::measurement(category, "", None).category()
+            );
+        }
+    }
+
+    #[test]
+    fn each_kind_of_error_has_its_own_exit_code() {
+        let mut exit_codes = vec![
+            Error::config("", "").exit_code(),
+            Error::measurement(ErrorCategory::BinaryMissing, "", None).exit_code(),
+            Error::measurement(ErrorCategory::NonZeroExit, "", None).exit_code(),
+            Error::measurement(ErrorCategory::Timeout, "", None).exit_code(),
+            Error::measurement(ErrorCategory::ParseError, "", None).exit_code(),
+            Error::storage("", "").exit_code(),
+            Error::mail("", "").exit_code(),
+            Error::publish("", "").exit_code(),
+            Error::output("", "").exit_code(),
+        ];
+        exit_codes.sort();
+        exit_codes.dedup();
+        assert_eq!(9, exit_codes.len());
+        assert!(!exit_codes.contains(&0) && !exit_codes.contains(&1));
+    }
+}
@@ -0,0 +1,253 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::Value as Json;
+use std::fs;
+use std::io::{self, prelude::*, BufWriter};
+use std::path::Path;
+
+use crate::config::{Export, ExportFormat};
+use crate::error::Error;
+use crate::influx::Point;
+use crate::parquet::{self, Kind, Value};
+use crate::storage::{data_dir, read_results, ResultCsv};
+use crate::units::Unit;
+
+/// Columns of the typed formats (`ndjson`, `csv` and `parquet`). Speeds are in
+/// Mbps whatever the unit of the row, and the columns after `error_message`
+/// come from the raw JSON of the measurement, being null when it is gone.
+pub const COLUMNS: [(&str, Kind); 30] = [
+    ("timestamp", Kind::Timestamp),
+    ("status", Kind::String),
+    ("download_mbps", Kind::Double),
+    ("upload_mbps", Kind::Double),
+    ("ping_ms", Kind::Double),
+    ("jitter_ms", Kind::Double),
+    ("packet_loss_percent", Kind::Double),
+    ("samples", Kind::Int64),
+    ("server_id", Kind::Int64),
+    ("server_host", Kind::String),
+    ("server_location", Kind::String),
+    ("server_country", Kind::String),
+    ("isp", Kind::String),
+    ("client_ip", Kind::String),
+    ("interface", Kind::String),
+    ("error_category", Kind::String),
+    ("error_message", Kind::String),
+    ("result_id", Kind::String),
+    ("result_url", Kind::String),
+    ("server_name", Kind::String),
+    ("server_ip", Kind::String),
+    ("server_port", Kind::Int64),
+    ("internal_ip", Kind::String),
+    ("is_vpn", Kind::Boolean),
+    ("download_bytes", Kind::Int64),
+    ("upload_bytes", Kind::Int64),
+    ("download_elapsed_ms", Kind::Int64),
+    ("upload_elapsed_ms", Kind::Int64),
+    ("ping_low_ms", Kind::Double),
+    ("ping_high_ms", Kind::Double),
+];
+
+/// Writes the history in the summary file to stdout, in the format set by `export`.
+pub fn export(export: Export) -> Result<(), Error> {
+    let data_dir = data_dir()?;
+    let rows = read_results(&data_dir)?;
+    let selected: Vec<&ResultCsv> = rows
+        .iter()
+        .filter(|row| {
+            (export.interface.is_none() || row.interface.as_deref() == export.interface.as_deref())
+                && export.from.is_none_or(|from| row.date >= from)
+                && export.to.is_none_or(|to| row.date < to)
+        })
+        .collect();
+    let mut out = BufWriter::new(io::stdout().lock());
+    let write_error = |err| Error::storage("Error when writing export", err);
+    let mut count = 0;
+    match export.format {
+        ExportFormat::Influx => {
+            for row in selected.iter() {
+                if let Some(point) = Point::from_row(row) {
+                    writeln!(out, "{}", point.to_line(&export.host)).map_err(write_error)?;
+                    count += 1;
+                }
+            }
+        }
+        ExportFormat::Ndjson => {
+            for row in selected.iter() {
+                writeln!(out, "{}", to_json(&record(&data_dir, row))).map_err(write_error)?;
+                count += 1;
+            }
+        }
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(&mut out);
+            writer
+                .write_record(COLUMNS.iter().map(|(name, _)| name))
+                .map_err(|err| Error::storage("Error when writing export", err))?;
+            for row in selected.iter() {
+                writer
+                    .write_record(record(&data_dir, row).iter().map(to_text))
+                    .map_err(|err| Error::storage("Error when writing export", err))?;
+                count += 1;
+            }
+            writer.flush().map_err(write_error)?;
+        }
+        ExportFormat::Parquet => {
+            let records: Vec<Vec<Option<Value>>> =
+                selected.iter().map(|row| record(&data_dir, row)).collect();
+            parquet::write(&COLUMNS, &records)
+                .and_then(|file| out.write_all(&file))
+                .map_err(write_error)?;
+            count = records.len();
+        }
+    }
+    out.flush().map_err(write_error)?;
+    info!("Exported {} of {} rows.", count, rows.len());
+    Ok(())
+}
+
+/// The values of a row in the order of `COLUMNS`, with the fields of its raw
+/// JSON, if it is still in the data directory.
+pub fn record(data_dir: &Path, row: &ResultCsv) -> Vec<Option<Value>> {
+    let raw: Json = if row.failed() {
+        Json::Null
+    } else {
+        fs::read_to_string(data_dir.join(row.raw_file_name()))
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or(Json::Null)
+    };
+    let text = |value: &Option<String>| value.clone().map(Value::String);
+    let raw_text = |pointer: &str| {
+        raw.pointer(pointer)
+            .and_then(Json::as_str)
+            .map(|text| Value::String(text.to_owned()))
+    };
+    let raw_int = |pointer: &str| {
+        raw.pointer(pointer)
+            .and_then(Json::as_i64)
+            .map(Value::Int64)
+    };
+    let raw_float = |pointer: &str| {
+        raw.pointer(pointer)
+            .and_then(Json::as_f64)
+            .map(Value::Double)
+    };
+    vec![
+        Some(Value::Timestamp(row.date.timestamp_micros())),
+        Some(Value::String(
+            if row.failed() { "failed" } else { "ok" }.to_owned(),
+        )),
+        row.download_in(Unit::Mbps).map(Value::Double),
+        row.upload_in(Unit::Mbps).map(Value::Double),
+        row.ping.map(Value::Double),
+        row.jitter.map(Value::Double),
+        row.packet_loss.map(Value::Double),
+        row.samples.map(|samples| Value::Int64(samples as i64)),
+        row.server_id.map(|id| Value::Int64(id as i64)),
+        text(&row.server_host),
+        text(&row.server_location),
+        text(&row.server_country),
+        text(&row.client_isp),
+        text(&row.client_ip),
+        text(&row.interface),
+        row.error_category
+            .map(|category| Value::String(category.to_string())),
+        text(&row.error_message),
+        raw_text("/result/id"),
+        raw_text("/result/url"),
+        raw_text("/server/name"),
+        raw_text("/server/ip"),
+        raw_int("/server/port"),
+        raw_text("/interface/internalIp"),
+        raw.pointer("/interface/isVpn")
+            .and_then(Json::as_bool)
+            .map(Value::Boolean),
+        raw_int("/download/bytes"),
+        raw_int("/upload/bytes"),
+        raw_int("/download/elapsed"),
+        raw_int("/upload/elapsed"),
+        raw_float("/ping/low"),
+        raw_float("/ping/high"),
+    ]
+}
+
+/// A record as a JSON object, with the timestamp in RFC 3339.
+fn to_json(record: &[Option<Value>]) -> String {
+    let fields: Vec<String> = COLUMNS
+        .iter()
+        .zip(record)
+        .map(|((name, _), value)| {
+            let value = match value {
+                None => Json::Null,
+                Some(Value::Boolean(value)) => Json::from(*value),
+                Some(Value::Int64(value)) => Json::from(*value),
+                Some(Value::Double(value)) => Json::from(*value),
+                Some(Value::String(value)) => Json::from(value.as_str()),
+                Some(Value::Timestamp(micros)) => Json::from(format_timestamp(*micros)),
+            };
+            format!("{}:{}", Json::from(*name), value)
+        })
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+/// A value as CSV text, empty for nulls.
+fn to_text(value: &Option<Value>) -> String {
+    match value {
+        None => String::new(),
+        Some(Value::Boolean(value)) => value.to_string(),
+        Some(Value::Int64(value)) => value.to_string(),
+        Some(Value::Double(value)) => value.to_string(),
+        Some(Value::String(value)) => value.clone(),
+        Some(Value::Timestamp(micros)) => format_timestamp(*micros),
+    }
+}
+
+fn format_timestamp(micros: i64) -> String {
+    DateTime::<Utc>::from_timestamp_micros(micros)
+        .unwrap_or_default()
+        .to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::result_file_stem;
+    use crate::test_support::create_data_dir;
+    use chrono::TimeZone;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn records_have_the_fields_of_the_raw_json() {
+        let data_dir = create_data_dir("export-raw");
+        let date = Utc.with_ymd_and_hms(2021, 1, 3, 12, 10, 0).unwrap();
+        let raw_file = format!("{}-1.json", result_file_stem(&date));
+        fs::write(data_dir.join(&raw_file), crate::run::SIMULATED_RESULT).unwrap();
+        let row = ResultCsv {
+            date,
+            raw_file: Some(raw_file),
+            speeds_download: Some(100.0),
+            server_id: Some(99999),
+            ..Default::default()
+        };
+        let record = record(&data_dir, &row);
+        assert_eq!(COLUMNS.len(), record.len());
+        let json: Json = serde_json::from_str(&to_json(&record)).unwrap();
+        assert_eq!("2021-01-03T12:10:00Z", json["timestamp"]);
+        assert_eq!("ok", json["status"]);
+        assert_eq!(104.8576, json["download_mbps"], "Legacy rows are in Mibps");
+        assert_eq!(Json::Null, json["upload_mbps"]);
+        assert_eq!(99999, json["server_id"]);
+        assert_eq!("Some Server", json["server_name"]);
+        assert_eq!(false, json["is_vpn"]);
+        assert_eq!(176063552, json["download_bytes"]);
+        assert_eq!(
+            "2021-01-03T12:10:00Z,ok,104.8576,,,,,,99999",
+            record[..9]
+                .iter()
+                .map(to_text)
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+}
@@ -0,0 +1,561 @@
+use chrono::{DateTime, Utc};
+use std::env;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+
+use crate::aggregate::Stats;
+use crate::config::{ChartStyle, History};
+use crate::error::Error;
+use crate::rollup::{self, Rollup};
+use crate::storage::{data_dir, read_results_since};
+use crate::timezone::Zone;
+
+/// Rows of a line chart.
+const HEIGHT: usize = 10;
+/// Columns taken by the labels on the left of a line chart, `┤` excluded.
+const LABEL_WIDTH: usize = 9;
+const MIN_WIDTH: usize = 10;
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+/// Prints charts of the download, upload and ping in the summary file, see `render`.
+pub fn history(history: History) -> Result<(), Error> {
+    let now = Utc::now();
+    let measurements = read(&data_dir()?, &history, now)?;
+    let color = io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+    print!("{}", render(&history, &measurements, now, color));
+    Ok(())
+}
+
+/// Whether the range is charted from the daily rollups, which are of every
+/// interface, so that long ranges do not read the whole summary file.
+fn is_daily(history: &History) -> bool {
+    history.interface.is_none() && history.last >= rollup::LONG_RANGE
+}
+
+/// The start of the range, or the earliest date there is when `history.last`
+/// goes back further than that.
+fn start(history: &History, now: DateTime<Utc>) -> DateTime<Utc> {
+    now.checked_sub_signed(history.last)
+        .unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
+
+/// The measurements of the last `history.last` before `now`, as the daily
+/// rollups (see `is_daily`) or as a rollup per row of the interface.
+fn read(data_dir: &Path, history: &History, now: DateTime<Utc>) -> Result<Vec<Rollup>, Error> {
+    let from = start(history, now);
+    if is_daily(history) {
+        return rollup::read_daily(data_dir, Some(from), history.units);
+    }
+    Ok(read_results_since(data_dir, from)?
+        .iter()
+        .filter(|row| {
+            history.interface.is_none() || row.interface.as_deref() == history.interface.as_deref()
+        })
+        .map(|row| rollup::from_row(row, history.units))
+        .collect())
+}
+
+/// The values of a chart, spread over its columns by date.
+struct Series {
+    title: &'static str,
+    unit: &'static str,
+    /// Statistics of every measurement, or of every day, in the range, from the
+    /// oldest to the newest.
+    values: Vec<Stats>,
+    daily: bool,
+    /// Mean of the values in each column, `None` where there are none.
+    columns: Vec<Option<f64>>,
+    expected: Option<f64>,
+    minimum: Option<f64>,
+}
+
+impl Series {
+    fn is_bellow(&self, value: f64) -> bool {
+        self.minimum.is_some_and(|minimum| value < minimum)
+    }
+}
+
+/// Renders the measurements of the last `history.last` before `now`, as read by
+/// `read`, as text with ANSI colors if `color` is set. Each column of a chart is
+/// a slice of the range, showing the mean of the measurements taken in it.
+pub fn render(
+    history: &History,
+    measurements: &[Rollup],
+    now: DateTime<Utc>,
+    color: bool,
+) -> String {
+    let from = start(history, now);
+    let measurements: Vec<&Rollup> = measurements
+        .iter()
+        .filter(|rollup| rollup.start >= from && rollup.start <= now)
+        .collect();
+    if measurements.is_empty() {
+        return format!(
+            "No measurements since {} ({}).\n",
+            history.timezone.format(&from, DATE_FORMAT),
+            history.timezone.label(&now)
+        );
+    }
+    let width = match history.style {
+        ChartStyle::Chart => history.width.saturating_sub(LABEL_WIDTH + 1),
+        ChartStyle::Sparkline => history.width,
+    }
+    .max(MIN_WIDTH);
+    let span = (now - from).num_seconds().max(1);
+    let column = |date: DateTime<Utc>| {
+        (((date - from).num_seconds() * width as i64 / span) as usize).min(width - 1)
+    };
+    let mut failures = vec![false; width];
+    for rollup in measurements.iter().filter(|rollup| rollup.failed > 0) {
+        failures[column(rollup.start)] = true;
+    }
+    let daily = is_daily(history);
+    let series = |title: &'static str,
+                  unit: &'static str,
+                  value: &dyn Fn(&Rollup) -> Option<&Stats>,
+                  reference: Option<(f64, f64)>| {
+        let mut sums = vec![(0.0, 0); width];
+        let mut values = vec![];
+        for rollup in measurements.iter() {
+            if let Some(stats) = value(rollup) {
+                let (sum, count) = &mut sums[column(rollup.start)];
+                *sum += stats.mean * stats.count as f64;
+                *count += stats.count;
+                values.push(stats.clone());
+            }
+        }
+        Series {
+            title,
+            unit,
+            values,
+            daily,
+            columns: sums
+                .into_iter()
+                .map(|(sum, count)| (count > 0).then(|| sum / count as f64))
+                .collect(),
+            expected: reference.map(|(expected, _)| expected),
+            minimum: reference.map(|(_, minimum)| minimum),
+        }
+    };
+    let expectation = history.expectation.as_ref();
+    let unit = history.units.symbol();
+    let charts = [
+        series(
+            "Download",
+            unit,
+            &|rollup| rollup.download.as_ref(),
+            expectation.map(|expectation| (expectation.download, expectation.minimum_download())),
+        ),
+        series(
+            "Upload",
+            unit,
+            &|rollup| rollup.upload.as_ref(),
+            expectation.map(|expectation| (expectation.upload, expectation.minimum_upload())),
+        ),
+        series("Ping", "ms", &|rollup| rollup.ping.as_ref(), None),
+    ];
+    let failed = failures.iter().filter(|failed| **failed).count();
+    let mut text = format!(
+        "{} measurement(s) from {} to {} ({}), {} failed.\n",
+        measurements
+            .iter()
+            .map(|rollup| rollup.measurements)
+            .sum::<usize>(),
+        history.timezone.format(&from, DATE_FORMAT),
+        history.timezone.format(&now, DATE_FORMAT),
+        history.timezone.label(&now),
+        measurements
+            .iter()
+            .map(|rollup| rollup.failed)
+            .sum::<usize>()
+    );
+    if daily {
+        text.push_str("Charted from the daily rollups, of every interface.\n");
+    }
+    match history.style {
+        _ if expectation.is_none() => {}
+        ChartStyle::Chart => {
+            text.push_str("Lines: '-' expected, '·' minimum. Points: '▼' bellow minimum.\n")
+        }
+        ChartStyle::Sparkline => text.push_str("Bars: '▼' bellow minimum.\n"),
+    }
+    for series in charts.iter() {
+        text.push('\n');
+        text.push_str(&render_summary(series));
+        match history.style {
+            ChartStyle::Chart => {
+                text.push_str(&render_chart(series, color));
+                if failed > 0 {
+                    text.push_str(&format!(
+                        "{:>width$}  {}\n",
+                        "failed",
+                        render_failures(&failures, color),
+                        width = LABEL_WIDTH - 1
+                    ));
+                }
+                text.push_str(&render_dates(from, now, width, history.timezone));
+            }
+            ChartStyle::Sparkline => {
+                text.push_str(&render_sparkline(series, color));
+                if failed > 0 {
+                    text.push_str(&render_failures(&failures, color));
+                    text.push('\n');
+                }
+            }
+        }
+    }
+    text
+}
+
+fn render_summary(series: &Series) -> String {
+    let Some(last) = series.values.last() else {
+        return format!("{} ({}): no measurements\n", series.title, series.unit);
+    };
+    let count: usize = series.values.iter().map(|stats| stats.count).sum();
+    let sum: f64 = series
+        .values
+        .iter()
+        .map(|stats| stats.mean * stats.count as f64)
+        .sum();
+    let mut summary = format!(
+        "{} ({}): min {:.2}, mean {:.2}, max {:.2}, last {:.2}",
+        series.title,
+        series.unit,
+        series
+            .values
+            .iter()
+            .map(|stats| stats.min)
+            .fold(f64::INFINITY, f64::min),
+        sum / count as f64,
+        series
+            .values
+            .iter()
+            .map(|stats| stats.max)
+            .fold(f64::NEG_INFINITY, f64::max),
+        last.mean
+    );
+    if let (Some(expected), Some(minimum)) = (series.expected, series.minimum) {
+        let bellow = series
+            .values
+            .iter()
+            .filter(|stats| series.is_bellow(stats.mean))
+            .count();
+        summary.push_str(&format!(
+            ", expected {:.2}, {} of {}{} bellow {:.2}",
+            expected,
+            bellow,
+            series.values.len(),
+            if series.daily { " days" } else { "" },
+            minimum
+        ));
+    }
+    summary.push('\n');
+    summary
+}
+
+fn render_chart(series: &Series, color: bool) -> String {
+    let values: Vec<f64> = series.columns.iter().flatten().copied().collect();
+    if values.is_empty() {
+        return String::new();
+    }
+    let references = series.expected.iter().chain(series.minimum.iter());
+    let mut low = values
+        .iter()
+        .chain(references.clone())
+        .copied()
+        .fold(f64::INFINITY, f64::min);
+    let mut high = values
+        .iter()
+        .chain(references)
+        .copied()
+        .fold(f64::NEG_INFINITY, f64::max);
+    if high <= low {
+        low -= 1.0;
+        high += 1.0;
+    }
+    let row_of =
+        |value: f64| ((high - value) / (high - low) * (HEIGHT - 1) as f64).round() as usize;
+    let mut grid = vec![vec![(' ', false); series.columns.len()]; HEIGHT];
+    let mut labels = vec![None; HEIGHT];
+    labels[0] = Some(high);
+    labels[HEIGHT - 1] = Some(low);
+    for (reference, line) in [(series.expected, '-'), (series.minimum, '·')] {
+        if let Some(reference) = reference {
+            let row = row_of(reference);
+            grid[row].fill((line, false));
+            labels[row] = Some(reference);
+        }
+    }
+    for (column, value) in series.columns.iter().enumerate() {
+        if let Some(value) = value {
+            grid[row_of(*value)][column] = if series.is_bellow(*value) {
+                ('▼', true)
+            } else {
+                ('•', false)
+            };
+        }
+    }
+    let mut chart = String::new();
+    for (row, label) in grid.iter().zip(labels) {
+        let label = label.map_or(String::new(), |label| format!("{:.1}", label));
+        chart.push_str(&format!("{:>width$} ┤", label, width = LABEL_WIDTH - 1));
+        let line: String = row
+            .iter()
+            .map(|(character, highlight)| paint(*character, *highlight && color))
+            .collect();
+        chart.push_str(line.trim_end());
+        chart.push('\n');
+    }
+    chart.push_str(&format!(
+        "{}└{}\n",
+        " ".repeat(LABEL_WIDTH),
+        "─".repeat(series.columns.len())
+    ));
+    chart
+}
+
+fn render_sparkline(series: &Series, color: bool) -> String {
+    let values: Vec<f64> = series.columns.iter().flatten().copied().collect();
+    let low = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let high = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let mut sparkline: String = series
+        .columns
+        .iter()
+        .map(|value| match value {
+            Some(value) => {
+                let level = if high <= low {
+                    SPARKS.len() / 2
+                } else {
+                    ((value - low) / (high - low) * (SPARKS.len() - 1) as f64).round() as usize
+                };
+                // a glyph of its own, as colors may be off or not seen
+                if series.is_bellow(*value) {
+                    paint('▼', color)
+                } else {
+                    SPARKS[level].to_string()
+                }
+            }
+            None => " ".to_owned(),
+        })
+        .collect();
+    sparkline.push('\n');
+    sparkline
+}
+
+fn render_failures(failures: &[bool], color: bool) -> String {
+    failures
+        .iter()
+        .map(|failed| {
+            if *failed {
+                paint('x', color)
+            } else {
+                " ".to_owned()
+            }
+        })
+        .collect::<String>()
+        .trim_end()
+        .to_owned()
+}
+
+/// The first and last dates of the range, under the columns of a chart.
+fn render_dates(from: DateTime<Utc>, now: DateTime<Utc>, width: usize, zone: Zone) -> String {
+    let from = zone.format(&from, DATE_FORMAT);
+    let now = zone.format(&now, DATE_FORMAT);
+    let padding = width.saturating_sub(from.len() + now.len());
+    if padding == 0 {
+        return format!("{}{}\n", " ".repeat(LABEL_WIDTH + 1), from);
+    }
+    format!(
+        "{}{}{}{}\n",
+        " ".repeat(LABEL_WIDTH + 1),
+        from,
+        " ".repeat(padding),
+        now
+    )
+}
+
+/// The character, in red if `highlight` is set.
+fn paint(character: char, highlight: bool) -> String {
+    if highlight {
+        format!("\x1b[31m{}\x1b[0m", character)
+    } else {
+        character.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Expectation;
+    use crate::storage::{ResultCsv, Status};
+    use crate::units::Unit;
+    use chrono::{Duration, TimeZone};
+    use pretty_assertions::assert_eq;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2021, 1, 11, 0, 0, 0).unwrap()
+    }
+
+    fn row(days_ago: i64, download: f64, upload: f64, ping: f64) -> ResultCsv {
+        ResultCsv {
+            date: now() - Duration::days(days_ago),
+            ping: Some(ping),
+            speeds_download: Some(download),
+            speeds_upload: Some(upload),
+            speeds_unit: Some(Unit::Mbps),
+            interface: Some("eth0".to_owned()),
+            ..Default::default()
+        }
+    }
+
+    fn create_rows() -> Vec<Rollup> {
+        [
+            row(20, 1000.0, 1000.0, 1.0),
+            row(9, 100.0, 50.0, 5.0),
+            row(7, 60.0, 45.0, 8.0),
+            ResultCsv {
+                status: Some(Status::Failed),
+                ..row(5, 0.0, 0.0, 0.0)
+            },
+            row(3, 90.0, 50.0, 6.0),
+            row(1, 100.0, 30.0, 5.0),
+        ]
+        .iter()
+        .map(|row| rollup::from_row(row, Unit::Mbps))
+        .collect()
+    }
+
+    fn history(style: ChartStyle, width: usize) -> History {
+        History {
+            last: Duration::days(10),
+            style,
+            width,
+            units: Unit::Mbps,
+            timezone: Zone::Utc,
+            interface: Some("eth0".to_owned()),
+            expectation: Some(Expectation {
+                download: 100.0,
+                upload: 50.0,
+                threshold: 20,
+            }),
+        }
+    }
+
+    #[test]
+    fn ranges_longer_than_there_are_dates_start_at_the_earliest_date() {
+        let history = History {
+            last: Duration::days(2_000_000_000),
+            ..history(ChartStyle::Sparkline, 10)
+        };
+        assert_eq!(DateTime::<Utc>::MIN_UTC, start(&history, now()));
+        let text = render(&history, &create_rows(), now(), false);
+        assert!(
+            text.starts_with("6 measurement(s) from -262143-01-01 00:00 to 2021-01-11 00:00"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn renders_sparklines() {
+        assert_eq!(
+            "\
+5 measurement(s) from 2021-01-01 00:00 to 2021-01-11 00:00 (UTC), 1 failed.
+Bars: '▼' bellow minimum.
+
+Download (Mbps): min 60.00, mean 87.50, max 100.00, last 100.00, expected 100.00, 1 of 4 bellow 80.00
+ █ ▼   ▆ █
+     x
+
+Upload (Mbps): min 30.00, mean 43.75, max 50.00, last 30.00, expected 50.00, 1 of 4 bellow 40.00
+ █ ▆   █ ▼
+     x
+
+Ping (ms): min 5.00, mean 6.00, max 8.00, last 5.00
+ ▁ █   ▃ ▁
+     x
+",
+            render(&history(ChartStyle::Sparkline, 10), &create_rows(), now(), false)
+        );
+    }
+
+    #[test]
+    fn renders_charts_with_reference_lines_and_highlights() {
+        let text = render(
+            &history(ChartStyle::Chart, 40),
+            &create_rows(),
+            now(),
+            false,
+        );
+        assert_eq!(
+            "\
+5 measurement(s) from 2021-01-01 00:00 to 2021-01-11 00:00 (UTC), 1 failed.
+Lines: '-' expected, '·' minimum. Points: '▼' bellow minimum.
+
+Download (Mbps): min 60.00, mean 87.50, max 100.00, last 100.00, expected 100.00, 1 of 4 bellow 80.00
+   100.0 ┤---•-----------------------•--
+         ┤
+         ┤                     •
+         ┤
+         ┤
+    80.0 ┤······························
+         ┤
+         ┤
+         ┤
+    60.0 ┤         ▼
+         └──────────────────────────────
+  failed                 x
+          2021-01-01 00:00
+",
+            text.lines().take(17).map(|line| format!("{}\n", line)).collect::<String>()
+        );
+        assert!(text.contains("    30.0 ┤                           ▼\n"));
+        let colored = render(&history(ChartStyle::Chart, 40), &create_rows(), now(), true);
+        assert_eq!(2, colored.matches("\x1b[31m▼\x1b[0m").count());
+        assert_eq!(
+            3,
+            colored.matches("\x1b[31mx\x1b[0m").count(),
+            "Failures are shown under each chart"
+        );
+        let wide = render(
+            &history(ChartStyle::Chart, 60),
+            &create_rows(),
+            now(),
+            false,
+        );
+        assert!(wide.contains("\n          2021-01-01 00:00                  2021-01-11 00:00\n"));
+    }
+
+    #[test]
+    fn renders_long_ranges_of_every_interface_from_daily_rollups() {
+        let history = History {
+            last: Duration::weeks(52),
+            interface: None,
+            ..history(ChartStyle::Sparkline, 10)
+        };
+        let rows = [
+            row(2, 100.0, 50.0, 5.0),
+            row(2, 60.0, 50.0, 5.0),
+            row(1, 90.0, 50.0, 5.0),
+        ];
+        let rows: Vec<&ResultCsv> = rows.iter().collect();
+        let daily = rollup::compute(&rows, crate::aggregate::Period::Day, Unit::Mbps);
+        let text = render(&history, &daily, now(), false);
+        assert!(text.starts_with(
+            "3 measurement(s) from 2020-01-13 00:00 to 2021-01-11 00:00 (UTC), 0 failed.\n\
+             Charted from the daily rollups, of every interface.\n"
+        ));
+        assert!(text.contains(
+            "Download (Mbps): min 60.00, mean 83.33, max 100.00, last 90.00, expected 100.00, 0 of 2 days bellow 80.00\n"
+        ));
+    }
+
+    #[test]
+    fn renders_nothing_without_measurements() {
+        assert_eq!(
+            "No measurements since 2021-01-01 00:00 (UTC).\n",
+            render(&history(ChartStyle::Chart, 40), &[], now(), false)
+        );
+    }
+}
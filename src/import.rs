@@ -0,0 +1,468 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::Path;
+
+use crate::config::{Import, ImportFormat};
+use crate::error::Error;
+use crate::rollup;
+use crate::storage::{
+    data_dir, format_date, lock_summary_file, parse_date, parse_row, parse_summary,
+    to_summary_columns, upgrade_summary_file, write_atomically, SUMMARY_HEADER,
+};
+use crate::units::{LEGACY_UNIT, STORED_UNIT};
+
+/// Columns of `speedtest-cli --csv`.
+const SPEEDTEST_CLI_HEADER: [&str; 10] = [
+    "Server ID",
+    "Sponsor",
+    "Server Name",
+    "Timestamp",
+    "Distance",
+    "Ping",
+    "Download",
+    "Upload",
+    "Share",
+    "IP Address",
+];
+
+/// What `merge` did to the summary file.
+#[derive(Debug, PartialEq)]
+pub struct Merge {
+    pub imported: usize,
+    /// Rows skipped as the summary file already has a row with their date.
+    pub duplicates: usize,
+}
+
+/// Imports the history of other tools into the summary file, see `merge`, and
+/// rebuilds the rollups.
+pub fn import(import: Import) -> Result<(), Error> {
+    let data_dir = data_dir()?;
+    let mut rows = vec![];
+    for path in import.files.iter() {
+        let format = match import.format {
+            Some(format) => format,
+            None => detect_format(path)?,
+        };
+        let file_rows = read_file(path, format)?;
+        info!(
+            "Read {} measurement(s) from {} as {:?}.",
+            file_rows.len(),
+            path.display(),
+            format
+        );
+        rows.extend(file_rows);
+    }
+    let merge = merge(&data_dir, rows, import.dry_run)?;
+    let prefix = if import.dry_run { "[dry run] " } else { "" };
+    println!(
+        "{}Imported {} measurement(s), skipped {} already in the summary file.",
+        prefix, merge.imported, merge.duplicates
+    );
+    if !import.dry_run && merge.imported > 0 {
+        rollup::rebuild(&data_dir)?;
+    }
+    Ok(())
+}
+
+/// The format of a file: .json files are from the Node.js tool, and CSV files
+/// are told apart by their header, or by their first row for `speedtest-cli`
+/// output without one.
+pub fn detect_format(path: &Path) -> Result<ImportFormat, Error> {
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        return Ok(ImportFormat::NodeJson);
+    }
+    let contents = fs::read_to_string(path)
+        .map_err(|err| Error::storage(format!("Error when reading {}", path.display()), err))?;
+    let first = parse_csv(&contents)
+        .into_iter()
+        .next()
+        .and_then(Result::ok)
+        .unwrap_or_default();
+    if first.iter().any(|column| column == "date") {
+        Ok(ImportFormat::NodeCsv)
+    } else if first.get(0) == Some(SPEEDTEST_CLI_HEADER[0])
+        || first.len() == SPEEDTEST_CLI_HEADER.len()
+            && first
+                .get(3)
+                .is_some_and(|timestamp| parse_date(timestamp).is_ok())
+    {
+        Ok(ImportFormat::SpeedtestCli)
+    } else {
+        Err(Error::Config {
+            message: format!(
+                "Could not tell the format of {}, set it with --format.",
+                path.display()
+            ),
+            source: None,
+        })
+    }
+}
+
+/// Reads the measurements of a file as summary file records, with the
+/// columns of `SUMMARY_HEADER`.
+pub fn read_file(path: &Path, format: ImportFormat) -> Result<Vec<Vec<String>>, Error> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| Error::storage(format!("Error when reading {}", path.display()), err))?;
+    match format {
+        ImportFormat::NodeJson => node_json_rows(path, &contents),
+        ImportFormat::NodeCsv => node_csv_rows(&contents),
+        ImportFormat::SpeedtestCli => speedtest_cli_rows(&contents),
+    }
+}
+
+/// Adds the rows to the summary file, skipping the ones with the date of a row
+/// already there (or of an earlier one of `rows`), and replaces it with a copy
+/// sorted by date, while locked. Rows that cannot be read are skipped with a
+/// warning.
+pub fn merge(data_dir: &Path, rows: Vec<Vec<String>>, dry_run: bool) -> Result<Merge, Error> {
+    let file_path = data_dir.join("speed.csv");
+    let mut contents = vec![];
+    let mut _lock = None;
+    if !dry_run {
+        if !data_dir.exists() {
+            fs::create_dir(data_dir)
+                .map_err(|err| Error::storage("Error when creating data directory", err))?;
+        }
+        _lock = Some(lock_summary_file(data_dir)?);
+        if file_path
+            .metadata()
+            .is_ok_and(|metadata| metadata.len() > 0)
+        {
+            upgrade_summary_file(&file_path)?;
+        }
+    }
+    if file_path.exists() {
+        let mut summary_file = File::open(&file_path)
+            .map_err(|err| Error::storage("Error when opening summary file", err))?;
+        summary_file
+            .lock_shared()
+            .map_err(|err| Error::storage("Error when locking summary file", err))?;
+        summary_file
+            .read_to_end(&mut contents)
+            .map_err(|err| Error::storage("Error when reading summary file", err))?;
+    }
+    if contents.is_empty() {
+        contents = format!("{}\n", SUMMARY_HEADER.join(",")).into_bytes();
+    }
+    let summary = parse_summary(&contents)?;
+    if !summary.bad_rows.is_empty() || contents.last() != Some(&b'\n') {
+        return Err(Error::Storage {
+            message: "The summary file has problems, run check --repair before importing."
+                .to_owned(),
+            source: None,
+        });
+    }
+    let header = csv::ByteRecord::from(SUMMARY_HEADER.to_vec());
+    let mut dates: HashSet<DateTime<Utc>> = summary.rows.iter().map(|(_, row)| row.date).collect();
+    let mut merged: Vec<(DateTime<Utc>, csv::ByteRecord)> = summary
+        .rows
+        .into_iter()
+        .map(|(record, row)| (row.date, record))
+        .collect();
+    let mut merge = Merge {
+        imported: 0,
+        duplicates: 0,
+    };
+    for values in rows {
+        let record = csv::ByteRecord::from(values);
+        match parse_row(&header, &record) {
+            Ok(row) if dates.insert(row.date) => {
+                merged.push((row.date, record));
+                merge.imported += 1;
+            }
+            Ok(_) => merge.duplicates += 1,
+            Err(error) => warn!("Skipping a measurement that cannot be imported: {}.", error),
+        }
+    }
+    if dry_run || merge.imported == 0 {
+        return Ok(merge);
+    }
+    merged.sort_by_key(|(date, _)| *date);
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for record in std::iter::once(&summary.header).chain(merged.iter().map(|(_, record)| record)) {
+        writer
+            .write_byte_record(record)
+            .map_err(|err| Error::storage("Error when importing into summary file", err))?;
+    }
+    let merged = writer
+        .into_inner()
+        .map_err(|err| Error::storage("Error when importing into summary file", err))?;
+    write_atomically(&file_path, &merged)
+        .map_err(|err| Error::storage("Error when writing summary file", err))?;
+    Ok(merge)
+}
+
+/// Rows of the .json files of the Node.js tool (the output of `speedtest-net`),
+/// which have no date of their own but are named after it, like
+/// `202011212124.json`. The names are in UTC, like the dates the tool wrote to
+/// its summary file.
+fn node_json_rows(path: &Path, contents: &str) -> Result<Vec<Vec<String>>, Error> {
+    let json: Value = serde_json::from_str(contents)
+        .map_err(|err| Error::storage(format!("Error when parsing {}", path.display()), err))?;
+    let results = match json {
+        Value::Array(results) => results,
+        result => vec![result],
+    };
+    let file_date = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .and_then(|stem| stem.split('-').next())
+        .and_then(|digits| match digits.len() {
+            12 => NaiveDateTime::parse_from_str(digits, "%Y%m%d%H%M").ok(),
+            14 => NaiveDateTime::parse_from_str(digits, "%Y%m%d%H%M%S").ok(),
+            _ => None,
+        })
+        .map(|date| date.and_utc());
+    let mut rows = vec![];
+    for result in results {
+        let date = ["date", "timestamp"]
+            .iter()
+            .find_map(|field| {
+                result[field]
+                    .as_str()
+                    .and_then(|date| parse_date(date).ok())
+            })
+            .or(file_date);
+        let date = match date {
+            Some(date) => date,
+            None => {
+                warn!(
+                    "Skipping a measurement of {} without a date, name the file after it, like 202011212124.json.",
+                    path.display()
+                );
+                continue;
+            }
+        };
+        let text = |value: &Value| match value {
+            Value::Null => String::new(),
+            Value::String(text) => text.clone(),
+            value => value.to_string(),
+        };
+        let server = &result["server"];
+        rows.push(summary_row(&[
+            ("date", format_date(&date)),
+            ("ping", text(&server["ping"])),
+            ("speeds_download", text(&result["speeds"]["download"])),
+            ("speeds_upload", text(&result["speeds"]["upload"])),
+            ("client_ip", text(&result["client"]["ip"])),
+            ("client_isp", text(&result["client"]["isp"])),
+            ("server_host", text(&server["host"])),
+            ("server_lat", text(&server["lat"])),
+            ("server_lon", text(&server["lon"])),
+            ("server_location", text(&server["location"])),
+            ("server_country", text(&server["country"])),
+            ("location_distance", text(&server["distance"])),
+            ("server_ping", text(&server["ping"])),
+            ("server_id", text(&server["id"])),
+            ("speeds_unit", LEGACY_UNIT.to_string()),
+        ]));
+    }
+    Ok(rows)
+}
+
+/// Rows of a summary file with a header, written by the Node.js tool or by
+/// any version of this one.
+fn node_csv_rows(contents: &str) -> Result<Vec<Vec<String>>, Error> {
+    let mut records = parse_csv(contents).into_iter();
+    let headers = match records.next() {
+        Some(headers) => headers.map_err(|err| Error::storage("Error when reading CSV", err))?,
+        None => return Ok(vec![]),
+    };
+    let mut rows = vec![];
+    for record in records {
+        let record = record.map_err(|err| Error::storage("Error when reading CSV", err))?;
+        let mut row = to_summary_columns(&headers, &record);
+        if !headers.iter().any(|header| header == "status") {
+            set(&mut row, "status", "ok");
+            set(&mut row, "samples", "1");
+        }
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// Rows of the output of `speedtest-cli --csv`, whose speeds are in bits per
+/// second.
+fn speedtest_cli_rows(contents: &str) -> Result<Vec<Vec<String>>, Error> {
+    let mut rows = vec![];
+    for record in parse_csv(contents) {
+        let record = record.map_err(|err| Error::storage("Error when reading CSV", err))?;
+        if record.get(0) == Some(SPEEDTEST_CLI_HEADER[0]) {
+            continue;
+        }
+        let field = |index: usize| record.get(index).unwrap_or_default().to_owned();
+        let date = match parse_date(&field(3)) {
+            Ok(date) => date,
+            Err(_) => {
+                warn!(
+                    "Skipping a speedtest-cli row with the timestamp '{}'.",
+                    field(3)
+                );
+                continue;
+            }
+        };
+        let speed = |index: usize| {
+            field(index).parse::<f64>().map_or(String::new(), |bits| {
+                format!("{:.2}", STORED_UNIT.from_bytes_per_second(bits / 8.0))
+            })
+        };
+        rows.push(summary_row(&[
+            ("date", format_date(&date)),
+            ("ping", field(5)),
+            ("speeds_download", speed(6)),
+            ("speeds_upload", speed(7)),
+            ("client_ip", field(9)),
+            ("server_location", field(2)),
+            ("location_distance", field(4)),
+            ("server_id", field(0)),
+            ("speeds_unit", STORED_UNIT.to_string()),
+        ]));
+    }
+    Ok(rows)
+}
+
+/// A record with the values of the given columns, the others being empty, of a
+/// successful measurement.
+fn summary_row(values: &[(&str, String)]) -> Vec<String> {
+    let mut row = vec![String::new(); SUMMARY_HEADER.len()];
+    set(&mut row, "samples", "1");
+    set(&mut row, "status", "ok");
+    for (column, value) in values {
+        set(&mut row, column, value);
+    }
+    row
+}
+
+fn set(row: &mut [String], column: &str, value: &str) {
+    if let Some(index) = SUMMARY_HEADER.iter().position(|header| *header == column) {
+        row[index] = value.to_owned();
+    }
+}
+
+fn parse_csv(contents: &str) -> Vec<csv::Result<csv::StringRecord>> {
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(contents.as_bytes())
+        .records()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::read_results;
+    use crate::test_support::create_data_dir;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn reads_each_format() {
+        let dir = create_data_dir("import-formats");
+        let json = dir.join("202011212124.json");
+        fs::write(
+            &json,
+            r#"{"speeds":{"download":154.95,"upload":100.76,"originalDownload":17133838,"originalUpload":11139591},"client":{"ip":"84.6.0.1","lat":-23.5,"lon":-46.6,"isp":"Some ISP"},"server":{"host":"h.com:8080","lat":-23.55,"lon":-46.63,"location":"São Paulo","country":"Brazil","cc":"BR","distance":1.79,"ping":5.7,"id":"99999"}}"#,
+        )
+        .unwrap();
+        let node_csv = dir.join("speed.csv");
+        fs::write(
+            &node_csv,
+            "date,ping,speeds_download,speeds_upload,client_ip,client_isp,server_host,server_lat,server_lon,server_location,server_country,location_distance,server_ping,server_id\n2020/11/21 21:24:00,5.7,154.95,100.76,84.6.0.1,Some ISP,h.com:8080,-23.55,-46.63,São Paulo,Brazil,1.79,5.7,99999\n",
+        )
+        .unwrap();
+        let cli_csv = dir.join("speedtest-cli.csv");
+        fs::write(
+            &cli_csv,
+            "99999,Some ISP,São Paulo,2020-11-21T21:24:00.123456Z,1.79,5.7,162480000.5,105660000.1,,84.6.0.1\n",
+        )
+        .unwrap();
+        assert_eq!(ImportFormat::NodeJson, detect_format(&json).unwrap());
+        assert_eq!(ImportFormat::NodeCsv, detect_format(&node_csv).unwrap());
+        assert_eq!(ImportFormat::SpeedtestCli, detect_format(&cli_csv).unwrap());
+        let row = "5.7,154.95,100.76,84.6.0.1,Some ISP,h.com:8080,-23.55,-46.63,São Paulo,Brazil,1.79,5.7,99999,,1,,,,ok,,,mibps,,,,,,,,,,";
+        assert_eq!(
+            vec![format!("2020-11-21T21:24:00Z,{}", row)],
+            read_file(&json, ImportFormat::NodeJson)
+                .unwrap()
+                .iter()
+                .map(|row| row.join(","))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![format!("2020-11-21T21:24:00Z,{}", row)],
+            read_file(&node_csv, ImportFormat::NodeCsv)
+                .unwrap()
+                .iter()
+                .map(|row| row.join(","))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["2020-11-21T21:24:00Z,5.7,162.48,105.66,84.6.0.1,,,,,São Paulo,,1.79,,99999,,1,,,,ok,,,mbps,,,,,,,,,,".to_owned()],
+            read_file(&cli_csv, ImportFormat::SpeedtestCli)
+                .unwrap()
+                .iter()
+                .map(|row| row.join(","))
+                .collect::<Vec<_>>()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn merges_sorted_without_duplicates() {
+        let data_dir = create_data_dir("import-merge");
+        let row = |date: &str, download: &str| {
+            summary_row(&[
+                ("date", date.to_owned()),
+                ("speeds_download", download.to_owned()),
+                ("speeds_unit", STORED_UNIT.to_string()),
+            ])
+        };
+        fs::write(
+            data_dir.join("speed.csv"),
+            format!(
+                "{}\n{}\n",
+                SUMMARY_HEADER.join(","),
+                row("2021/01/02 00:00:00", "200").join(",")
+            ),
+        )
+        .unwrap();
+        let rows = vec![
+            row("2021/01/03 00:00:00", "300"),
+            row("2021/01/02 00:00:00", "0"),
+            row("2021/01/01 00:00:00", "100"),
+            row("2021/01/01 00:00:00", "0"),
+            row("yesterday", "0"),
+        ];
+        assert_eq!(
+            Merge {
+                imported: 2,
+                duplicates: 2
+            },
+            merge(&data_dir, rows.clone(), true).unwrap()
+        );
+        assert_eq!(1, read_results(&data_dir).unwrap().len());
+        merge(&data_dir, rows.clone(), false).unwrap();
+        assert_eq!(
+            vec![Some(100.0), Some(200.0), Some(300.0)],
+            read_results(&data_dir)
+                .unwrap()
+                .iter()
+                .map(|row| row.speeds_download)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Merge {
+                imported: 0,
+                duplicates: 4
+            },
+            merge(&data_dir, rows, false).unwrap()
+        );
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+}
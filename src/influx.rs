@@ -0,0 +1,341 @@
+use base64::prelude::*;
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+use url::Url;
+
+use crate::config::{Influx, InfluxTarget};
+use crate::error::Error;
+use crate::output::round;
+use crate::results::SpeedResult;
+use crate::storage::ResultCsv;
+use crate::units::Unit;
+
+/// Name of the measurement the points are written to.
+pub const MEASUREMENT: &str = "speedtest";
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A measurement as an InfluxDB point. Bandwidths are in Mbps, latencies in
+/// milliseconds and packet loss in percent.
+#[derive(Debug, PartialEq)]
+pub struct Point {
+    pub date: DateTime<Utc>,
+    pub download: f64,
+    pub upload: f64,
+    pub ping: f64,
+    pub jitter: Option<f64>,
+    pub packet_loss: Option<f64>,
+    pub samples: usize,
+    pub server: String,
+    pub isp: String,
+    pub interface: String,
+}
+
+impl Point {
+    pub fn from_result(result: &SpeedResult) -> Point {
+        Point {
+            date: result.date,
+            download: round(Unit::Mbps.from_bytes_per_second(result.download), 2),
+            upload: round(Unit::Mbps.from_bytes_per_second(result.upload), 2),
+            ping: result.ping,
+            jitter: Some(result.jitter),
+            packet_loss: result.packet_loss,
+            samples: result.samples,
+            server: result.server_host.clone(),
+            isp: result.client_isp.clone(),
+            interface: result.interface.clone(),
+        }
+    }
+
+    /// The point of a row of the summary file, or `None` for failed measurements.
+    pub fn from_row(row: &ResultCsv) -> Option<Point> {
+        if row.failed() {
+            return None;
+        }
+        Some(Point {
+            date: row.date,
+            download: round(row.download_in(Unit::Mbps)?, 2),
+            upload: round(row.upload_in(Unit::Mbps)?, 2),
+            ping: row.ping?,
+            jitter: row.jitter,
+            packet_loss: row.packet_loss,
+            samples: row.samples.unwrap_or(1),
+            server: row.server_host.clone().unwrap_or_default(),
+            isp: row.client_isp.clone().unwrap_or_default(),
+            interface: row.interface.clone().unwrap_or_default(),
+        })
+    }
+
+    /// The point in line protocol, with a timestamp in seconds.
+    pub fn to_line(&self, host: &str) -> String {
+        let mut line = MEASUREMENT.to_owned();
+        // tags sorted by key, as recommended by InfluxDB, empty tags are not allowed
+        for (key, value) in [
+            ("host", host),
+            ("interface", &self.interface),
+            ("isp", &self.isp),
+            ("server", &self.server),
+        ] {
+            if !value.is_empty() {
+                line.push_str(&format!(",{}={}", key, escape_tag(value)));
+            }
+        }
+        let mut fields = vec![
+            format!("download={}", self.download),
+            format!("upload={}", self.upload),
+            format!("ping={}", self.ping),
+        ];
+        if let Some(jitter) = self.jitter {
+            fields.push(format!("jitter={}", jitter));
+        }
+        if let Some(packet_loss) = self.packet_loss {
+            fields.push(format!("packet_loss={}", packet_loss));
+        }
+        fields.push(format!("samples={}i", self.samples));
+        line.push_str(&format!(" {} {}", fields.join(","), self.date.timestamp()));
+        line
+    }
+}
+
+fn escape_tag(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+/// Writes the points to the InfluxDB server.
+pub fn push(influx: &Influx, points: &[Point]) -> Result<(), Error> {
+    let url = write_url(influx)?;
+    let body = points
+        .iter()
+        .map(|point| point.to_line(&influx.host))
+        .collect::<Vec<_>>()
+        .join("\n");
+    debug!(
+        "Writing {} point(s) to InfluxDB at {}.",
+        points.len(),
+        influx.url
+    );
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(TIMEOUT))
+        .http_status_as_error(false)
+        .build()
+        .into();
+    let mut request = agent
+        .post(url.as_str())
+        .header("Content-Type", "text/plain; charset=utf-8");
+    if let Some(authorization) = authorization(&influx.target) {
+        request = request.header("Authorization", authorization);
+    }
+    let mut response = request.send(body.as_bytes()).map_err(|err| {
+        Error::publish(
+            format!("Could not write results to InfluxDB at {}.", influx.url),
+            err,
+        )
+    })?;
+    if !response.status().is_success() {
+        let body = response.body_mut().read_to_string().unwrap_or_default();
+        return Err(Error::Publish {
+            message: format!(
+                "InfluxDB at {} refused the results with status {}: {}",
+                influx.url,
+                response.status().as_u16(),
+                body.trim()
+            ),
+            source: None,
+        });
+    }
+    info!("Results written to InfluxDB.");
+    Ok(())
+}
+
+/// The credentials go in a header, query strings end up in the logs of proxies
+/// and servers.
+fn authorization(target: &InfluxTarget) -> Option<String> {
+    match target {
+        InfluxTarget::V1 { credentials, .. } => credentials.as_ref().map(|credentials| {
+            let pair = format!("{}:{}", credentials.username, credentials.password);
+            format!("Basic {}", BASE64_STANDARD.encode(pair))
+        }),
+        InfluxTarget::V2 { token, .. } => Some(format!("Token {}", token)),
+    }
+}
+
+fn write_url(influx: &Influx) -> Result<Url, Error> {
+    let mut url = Url::parse(&influx.url)
+        .map_err(|err| Error::config(format!("Invalid InfluxDB URL '{}'", influx.url), err))?;
+    let path: &[&str] = match influx.target {
+        InfluxTarget::V1 { .. } => &["write"],
+        InfluxTarget::V2 { .. } => &["api", "v2", "write"],
+    };
+    url.path_segments_mut()
+        .map_err(|_| {
+            Error::config(
+                format!("Invalid InfluxDB URL '{}'", influx.url),
+                "URL cannot be a base",
+            )
+        })?
+        .pop_if_empty()
+        .extend(path);
+    {
+        let mut query = url.query_pairs_mut();
+        match &influx.target {
+            InfluxTarget::V1 { database, .. } => {
+                query.append_pair("db", database);
+            }
+            InfluxTarget::V2 { org, bucket, .. } => {
+                query.append_pair("org", org);
+                query.append_pair("bucket", bucket);
+            }
+        }
+        query.append_pair("precision", "s");
+    }
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Credentials;
+    use crate::results::convert_json;
+    use chrono::TimeZone;
+    use pretty_assertions::assert_eq;
+    use std::io::prelude::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn create_point() -> Point {
+        let mut result = convert_json(crate::run::SIMULATED_RESULT.to_owned()).unwrap();
+        result.date = Utc.with_ymd_and_hms(2021, 1, 3, 12, 10, 0).unwrap();
+        Point::from_result(&result)
+    }
+
+    #[test]
+    fn writes_points_as_line_protocol() {
+        assert_eq!(
+            "speedtest,host=my\\ host,interface=eth0,isp=Some\\ ISP,server=someserver.nonexistentxyz.com download=162.48,upload=105.66,ping=5.728,jitter=0.285,packet_loss=0,samples=1i 1609675800",
+            create_point().to_line("my host")
+        );
+    }
+
+    #[test]
+    fn legacy_rows_are_converted_to_mbps_and_failures_are_skipped() {
+        let row = ResultCsv {
+            date: Utc.with_ymd_and_hms(2021, 1, 3, 12, 10, 0).unwrap(),
+            ping: Some(5.0),
+            speeds_download: Some(100.0),
+            speeds_upload: Some(50.0),
+            client_isp: Some("Some, ISP".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            "speedtest,host=h,isp=Some\\,\\ ISP download=104.86,upload=52.43,ping=5,samples=1i 1609675800",
+            Point::from_row(&row).unwrap().to_line("h")
+        );
+        let failed = ResultCsv {
+            status: Some(crate::storage::Status::Failed),
+            ..row
+        };
+        assert_eq!(None, Point::from_row(&failed));
+    }
+
+    #[test]
+    fn builds_write_urls_for_each_version() {
+        let influx = |target| Influx {
+            url: "http://localhost:8086/".to_owned(),
+            target,
+            host: "h".to_owned(),
+        };
+        assert_eq!(
+            "http://localhost:8086/write?db=speed&precision=s",
+            write_url(&influx(InfluxTarget::V1 {
+                database: "speed".to_owned(),
+                credentials: Some(Credentials {
+                    username: "user".to_owned(),
+                    password: "pass".to_owned()
+                })
+            }))
+            .unwrap()
+            .as_str()
+        );
+        assert_eq!(
+            "http://localhost:8086/api/v2/write?org=my+org&bucket=speed&precision=s",
+            write_url(&influx(InfluxTarget::V2 {
+                org: "my org".to_owned(),
+                bucket: "speed".to_owned(),
+                token: "secret".to_owned()
+            }))
+            .unwrap()
+            .as_str()
+        );
+    }
+
+    #[test]
+    fn sends_credentials_in_the_authorization_header() {
+        assert_eq!(
+            Some("Basic dXNlcjpwYXNz".to_owned()),
+            authorization(&InfluxTarget::V1 {
+                database: "speed".to_owned(),
+                credentials: Some(Credentials {
+                    username: "user".to_owned(),
+                    password: "pass".to_owned()
+                })
+            })
+        );
+        assert_eq!(
+            None,
+            authorization(&InfluxTarget::V1 {
+                database: "speed".to_owned(),
+                credentials: None
+            })
+        );
+        assert_eq!(
+            Some("Token secret".to_owned()),
+            authorization(&InfluxTarget::V2 {
+                org: "org".to_owned(),
+                bucket: "speed".to_owned(),
+                token: "secret".to_owned()
+            })
+        );
+    }
+
+    #[test]
+    fn pushes_points_to_influxdb_v2() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut buffer = [0; 4096];
+            while !String::from_utf8_lossy(&request).ends_with("1609675800") {
+                let read = stream.read(&mut buffer).unwrap();
+                assert!(read > 0, "The client closed the connection early");
+                request.extend_from_slice(&buffer[..read]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        let influx = Influx {
+            url: format!("http://127.0.0.1:{}", port),
+            target: InfluxTarget::V2 {
+                org: "org".to_owned(),
+                bucket: "speed".to_owned(),
+                token: "secret".to_owned(),
+            },
+            host: "h".to_owned(),
+        };
+        push(&influx, &[create_point()]).unwrap();
+        let request = server.join().unwrap();
+        assert!(
+            request.starts_with("POST /api/v2/write?org=org&bucket=speed&precision=s HTTP/1.1\r\n")
+        );
+        assert!(request
+            .to_lowercase()
+            .contains("\r\nauthorization: token secret\r\n"));
+        assert!(request.ends_with(&create_point().to_line("h")));
+    }
+}
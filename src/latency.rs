@@ -0,0 +1,312 @@
+use socket2::{Domain, Protocol, Socket, Type};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+
+/// How many samples of the idle latency are taken before a measurement starts.
+const IDLE_SAMPLES: usize = 10;
+
+/// Time between two samples.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How long a sample waits for the target to answer before it is lost.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Port used when the target has none.
+const DEFAULT_PORT: u16 = 443;
+
+/// Latency samples, in milliseconds, taken to a target while the link was idle
+/// and while Ookla's CLI was loading it, to find bufferbloat.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoadedLatency {
+    pub idle: Vec<f64>,
+    pub download: Vec<f64>,
+    pub upload: Vec<f64>,
+}
+
+impl LoadedLatency {
+    /// The median (50th percentile) of the idle samples.
+    pub fn idle_median(&self) -> Option<f64> {
+        percentile(&self.idle, 50.0)
+    }
+
+    /// How much the median latency grew under load, in the worse of the two
+    /// directions.
+    pub fn increase(&self) -> Option<f64> {
+        let idle = self.idle_median()?;
+        [&self.download, &self.upload]
+            .iter()
+            .filter_map(|samples| percentile(samples, 50.0))
+            .map(|loaded| loaded - idle)
+            .reduce(f64::max)
+    }
+
+    pub fn grade(&self) -> Option<Grade> {
+        self.increase().map(Grade::from_increase)
+    }
+
+    /// The samples of all measurements, for a measurement made of several.
+    pub fn combine(measurements: impl IntoIterator<Item = LoadedLatency>) -> LoadedLatency {
+        let mut combined = LoadedLatency::default();
+        for measurement in measurements {
+            combined.idle.extend(measurement.idle);
+            combined.download.extend(measurement.download);
+            combined.upload.extend(measurement.upload);
+        }
+        combined
+    }
+}
+
+/// Nearest rank percentile of the values, `None` if there are none.
+pub fn percentile(values: &[f64], percent: f64) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+/// Bufferbloat grade, from how much the latency grows under load. The limits
+/// are the ones of the Waveform bufferbloat test.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Grade {
+    APlus,
+    A,
+    B,
+    C,
+    D,
+    F,
+}
+
+impl Grade {
+    /// The grade of a latency increase, in milliseconds.
+    pub fn from_increase(increase: f64) -> Grade {
+        match increase {
+            increase if increase < 5.0 => Grade::APlus,
+            increase if increase < 30.0 => Grade::A,
+            increase if increase < 60.0 => Grade::B,
+            increase if increase < 200.0 => Grade::C,
+            increase if increase < 400.0 => Grade::D,
+            _ => Grade::F,
+        }
+    }
+}
+
+impl fmt::Display for Grade {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Grade::APlus => "A+",
+            Grade::A => "A",
+            Grade::B => "B",
+            Grade::C => "C",
+            Grade::D => "D",
+            Grade::F => "F",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+// phases of Ookla's CLI, as kept by `Monitor`
+const IDLE: u8 = 0;
+const DOWNLOAD: u8 = 1;
+const UPLOAD: u8 = 2;
+
+/// Samples the latency to a target in the background while a measurement runs.
+/// The phase of the measurement comes from its progress lines, see `observer`.
+pub struct Monitor {
+    idle: Vec<f64>,
+    phase: Arc<AtomicU8>,
+    stop: Arc<AtomicBool>,
+    sampler: thread::JoinHandle<(Vec<f64>, Vec<f64>)>,
+}
+
+impl Monitor {
+    /// Takes the idle samples and starts sampling. `target` is a host and
+    /// port, like `1.1.1.1:443`, the port being 443 when it is left out.
+    pub fn start(target: &str) -> Result<Monitor, Error> {
+        let address = resolve(target)?;
+        let idle: Vec<f64> = (0..IDLE_SAMPLES)
+            .filter_map(|sample| {
+                if sample > 0 {
+                    thread::sleep(SAMPLE_INTERVAL);
+                }
+                probe(&address, PROBE_TIMEOUT).ok()
+            })
+            .collect();
+        let phase = Arc::new(AtomicU8::new(IDLE));
+        let stop = Arc::new(AtomicBool::new(false));
+        let sampler = {
+            let phase = phase.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let (mut download, mut upload) = (vec![], vec![]);
+                while !stop.load(Ordering::Relaxed) {
+                    let samples = match phase.load(Ordering::Relaxed) {
+                        DOWNLOAD => &mut download,
+                        UPLOAD => &mut upload,
+                        _ => {
+                            thread::sleep(SAMPLE_INTERVAL / 4);
+                            continue;
+                        }
+                    };
+                    if let Ok(latency) = probe(&address, PROBE_TIMEOUT) {
+                        samples.push(latency);
+                    }
+                    thread::sleep(SAMPLE_INTERVAL);
+                }
+                (download, upload)
+            })
+        };
+        Ok(Monitor {
+            idle,
+            phase,
+            stop,
+            sampler,
+        })
+    }
+
+    /// Follows the phases in the lines Ookla's CLI writes with
+    /// `--format=jsonl --progress=yes`.
+    pub fn observer(&self) -> impl FnMut(&[u8]) + Send + 'static {
+        let phase = self.phase.clone();
+        move |line| {
+            let kind = serde_json::from_slice::<serde_json::Value>(line)
+                .ok()
+                .and_then(|json| json["type"].as_str().map(str::to_owned));
+            let current = match kind.as_deref() {
+                Some("download") => DOWNLOAD,
+                Some("upload") => UPLOAD,
+                _ => IDLE,
+            };
+            phase.store(current, Ordering::Relaxed);
+        }
+    }
+
+    /// Stops sampling and returns the samples.
+    pub fn finish(self) -> LoadedLatency {
+        self.stop.store(true, Ordering::Relaxed);
+        let (download, upload) = self.sampler.join().unwrap_or_default();
+        LoadedLatency {
+            idle: self.idle,
+            download,
+            upload,
+        }
+    }
+}
+
+/// The address of a target, which may leave the port out.
+fn resolve(target: &str) -> Result<SocketAddr, Error> {
+    let has_port = target
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| !host.ends_with(':') && port.parse::<u16>().is_ok());
+    let target = if has_port {
+        target.to_owned()
+    } else {
+        format!("{}:{}", target, DEFAULT_PORT)
+    };
+    target
+        .to_socket_addrs()
+        .map_err(|err| Error::config(format!("Could not resolve latency target {}", target), err))?
+        .next()
+        .ok_or_else(|| Error::Config {
+            message: format!("Latency target {} has no address.", target),
+            source: None,
+        })
+}
+
+/// The time a TCP handshake with the address takes, in milliseconds. A
+/// handshake takes a single round trip, like an ICMP echo, but goes through an
+/// ordinary socket instead of a raw one, and routers do not deprioritize or
+/// filter it the way they often do ICMP. A refused connection also took a
+/// round trip, so it counts; fails when the target does not answer in time.
+pub fn probe(address: &SocketAddr, timeout: Duration) -> io::Result<f64> {
+    probe_from(address, None, timeout)
+}
+
+/// Like `probe`, leaving from the `source` address when there is one, to
+/// measure the uplink it belongs to instead of the default route.
+pub fn probe_from(
+    address: &SocketAddr,
+    source: Option<IpAddr>,
+    timeout: Duration,
+) -> io::Result<f64> {
+    let socket = Socket::new(
+        Domain::for_address(*address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    if let Some(source) = source {
+        socket.bind(&SocketAddr::new(source, 0).into())?;
+    }
+    let start = Instant::now();
+    match socket.connect_timeout(&(*address).into(), timeout) {
+        Ok(_) => {}
+        Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {}
+        Err(err) => return Err(err),
+    }
+    Ok(start.elapsed().as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::net::TcpListener;
+
+    #[test]
+    fn grades_the_increase_of_the_median() {
+        let latency = LoadedLatency {
+            idle: vec![10.0, 12.0, 11.0],
+            download: vec![50.0, 60.0, 70.0],
+            upload: vec![20.0, 25.0, 30.0],
+        };
+        assert_eq!(Some(11.0), latency.idle_median());
+        assert_eq!(Some(49.0), latency.increase());
+        assert_eq!(Some(Grade::B), latency.grade());
+        assert_eq!("A+", Grade::from_increase(4.9).to_string());
+        assert_eq!(Grade::F, Grade::from_increase(400.0));
+        assert_eq!(None, LoadedLatency::default().grade());
+        assert_eq!(Some(70.0), percentile(&latency.download, 90.0));
+        assert_eq!(Some(60.0), percentile(&latency.download, 50.0));
+        assert_eq!(None, percentile(&[], 50.0));
+    }
+
+    #[test]
+    fn samples_the_phases_of_the_progress_lines() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let monitor = Monitor::start(&listener.local_addr().unwrap().to_string()).unwrap();
+        let mut observe = monitor.observer();
+        observe(br#"{"type":"ping","ping":{"progress":1}}"#);
+        thread::sleep(SAMPLE_INTERVAL);
+        observe(br#"{"type":"download","download":{"progress":0.1}}"#);
+        thread::sleep(SAMPLE_INTERVAL * 3);
+        observe(br#"{"type":"upload","upload":{"progress":0.1}}"#);
+        thread::sleep(SAMPLE_INTERVAL * 3);
+        observe(br#"{"type":"result"}"#);
+        let latency = monitor.finish();
+        assert_eq!(IDLE_SAMPLES, latency.idle.len());
+        assert!(!latency.download.is_empty());
+        assert!(!latency.upload.is_empty());
+        assert!(latency.increase().is_some());
+    }
+
+    #[test]
+    fn resolves_targets_without_a_port() {
+        assert_eq!(
+            "127.0.0.1:443".parse::<SocketAddr>().unwrap(),
+            resolve("127.0.0.1").unwrap()
+        );
+        assert_eq!(
+            "[::1]:80".parse::<SocketAddr>().unwrap(),
+            resolve("[::1]:80").unwrap()
+        );
+    }
+}
@@ -0,0 +1,66 @@
+//! Runs speed tests with [Ookla's CLI](https://www.speedtest.net/apps/cli), keeps
+//! their history in a data directory and alerts when the bandwidth is bellow
+//! expectation.
+//!
+//! The `trackspeedtest` binary is a thin command line interface over this crate.
+//! The main modules are:
+//!
+//! * [`results`]: measurement results and parsing of Ookla's JSON output;
+//! * [`storage`]: reading and writing the per measurement JSON files and the
+//!   summary file (`speed.csv`);
+//! * [`units`]: bandwidth units (Mbps and Mibps);
+//! * [`timezone`]: the time zone dates are shown in;
+//! * [`aggregate`]: medians, spreads and averages of measurements;
+//! * [`rollup`]: hourly, daily and monthly statistics kept as results are added;
+//! * [`latency`]: latency under load, to find bufferbloat;
+//! * [`alert`]: evaluation of the latest measurements against expectations;
+//! * [`mail`]: e-mail notifications;
+//! * [`influx`]: sending results to InfluxDB;
+//! * [`mqtt`]: publishing results to MQTT, with Home Assistant discovery;
+//! * [`server`]: the web UI, rendered by [`dashboard`], and the JSON [`api`];
+//! * [`history`]: charts of the history for the terminal;
+//! * [`export`]: exporting the history in other formats, like Parquet with
+//!   [`parquet`];
+//! * [`import`]: importing the history of the Node.js tool and `speedtest-cli`;
+//! * [`prune`]: retention of the raw results, archived with [`archive`];
+//! * [`check`]: validation and repair of the summary file;
+//! * [`probe`]: frequent connectivity probes between measurements;
+//! * [`output`]: formatting of results for stdout;
+//! * [`run`]: running the measurements themselves, with a [`diagnosis`] of
+//!   the network when they fail;
+//! * [`logging`]: the leveled logger used by the binary;
+//! * [`error`]: the errors returned by all of the above.
+#[macro_use]
+extern crate derivative;
+#[macro_use]
+extern crate log;
+pub mod aggregate;
+pub mod alert;
+pub mod api;
+pub mod archive;
+pub mod check;
+pub mod config;
+pub mod dashboard;
+pub mod diagnosis;
+pub mod error;
+pub mod export;
+pub mod history;
+pub mod import;
+pub mod influx;
+pub mod latency;
+pub mod logging;
+pub mod mail;
+pub mod mqtt;
+pub mod output;
+pub mod parquet;
+pub mod probe;
+pub mod prune;
+pub mod results;
+pub mod rollup;
+pub mod run;
+pub mod server;
+pub mod storage;
+#[cfg(test)]
+mod test_support;
+pub mod timezone;
+pub mod units;
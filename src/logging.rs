@@ -0,0 +1,164 @@
+use chrono::{SecondsFormat, Utc};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::error::Error;
+
+/// Name of the log file written to the data directory when file logging is on.
+pub const LOG_FILE_NAME: &str = "trackspeedtest.log";
+
+/// How log lines are written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// `<timestamp> <LEVEL> <message>`.
+    Text,
+    /// One JSON object per line, with `timestamp`, `level`, `target` and `message`.
+    Json,
+}
+
+/// The level for a number of `-v` flags: warnings and errors by default, then info,
+/// debug and trace.
+pub fn level_for_verbosity(verbosity: u64) -> LevelFilter {
+    match verbosity {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Installs the logger. Logs go to stderr and, when `data_dir` is set, are also
+/// appended to `trackspeedtest.log` in it.
+pub fn init(verbosity: u64, format: LogFormat, data_dir: Option<&Path>) -> Result<(), Error> {
+    let file = match data_dir {
+        Some(data_dir) => {
+            fs::create_dir_all(data_dir)
+                .map_err(|err| Error::storage("Error when creating data directory", err))?;
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(data_dir.join(LOG_FILE_NAME))
+                .map_err(|err| Error::storage("Error when opening log file", err))?;
+            Some(Mutex::new(file))
+        }
+        None => None,
+    };
+    let level = level_for_verbosity(verbosity);
+    log::set_boxed_logger(Box::new(Logger {
+        level,
+        format,
+        file,
+    }))
+    .map_err(|err| Error::config("Could not set up logging", err.to_string()))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+struct Logger {
+    level: LevelFilter,
+    format: LogFormat,
+    file: Option<Mutex<File>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // dependencies (like the SMTP transport) only get to log warnings and errors
+        metadata.level() <= self.level
+            && (metadata.level() <= Level::Warn
+                || metadata.target().starts_with(env!("CARGO_CRATE_NAME")))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format_record(
+            self.format,
+            &Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            record.level(),
+            record.target(),
+            &record.args().to_string(),
+        );
+        eprintln!("{}", line);
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = writeln!(file, "{}", line);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.flush();
+            }
+        }
+    }
+}
+
+fn format_record(
+    format: LogFormat,
+    timestamp: &str,
+    level: Level,
+    target: &str,
+    message: &str,
+) -> String {
+    match format {
+        LogFormat::Text => format!("{} {:<5} {}", timestamp, level, message),
+        LogFormat::Json => serde_json::json!({
+            "timestamp": timestamp,
+            "level": level.as_str(),
+            "target": target,
+            "message": message,
+        })
+        .to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn each_verbose_flag_lowers_the_level() {
+        assert_eq!(
+            vec![
+                LevelFilter::Warn,
+                LevelFilter::Info,
+                LevelFilter::Debug,
+                LevelFilter::Trace,
+                LevelFilter::Trace
+            ],
+            (0..5).map(level_for_verbosity).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn formats_records_as_text_and_json() {
+        let timestamp = "2021-01-01T00:00:00.000Z";
+        assert_eq!(
+            "2021-01-01T00:00:00.000Z INFO  Got results",
+            format_record(
+                LogFormat::Text,
+                timestamp,
+                Level::Info,
+                "trackspeedtest::run",
+                "Got results"
+            )
+        );
+        assert_eq!(
+            r#"{"level":"WARN","message":"Attempt 1 failed.\nTimeout","target":"trackspeedtest::run","timestamp":"2021-01-01T00:00:00.000Z"}"#,
+            format_record(
+                LogFormat::Json,
+                timestamp,
+                Level::Warn,
+                "trackspeedtest::run",
+                "Attempt 1 failed.\nTimeout"
+            )
+        );
+    }
+}
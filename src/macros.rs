@@ -1,9 +0,0 @@
-macro_rules! printlnv {
-        ($($arg:tt)*) => ({
-            unsafe {
-                if $crate::VERBOSE {
-                    println!($($arg)*);
-                }
-            }
-        })
-    }
@@ -3,14 +3,18 @@ use std::str;
 use lettre::transport::smtp::authentication::Credentials;
 use lettre::{Message, SmtpTransport, Transport};
 
-use crate::args::Smtp;
+use crate::config::Smtp;
+use crate::error::Error;
 
-fn get_mailer(smtp: &Smtp) -> Result<SmtpTransport, String> {
+fn get_mailer(smtp: &Smtp) -> Result<SmtpTransport, Error> {
     let mut smtp_transport_builder = SmtpTransport::relay(&smtp.server)
         .map_err(|err| {
-            format!(
-                "Could not create smtp transport with relay '{}'. Error: {}",
-                smtp.server, err
+            Error::config(
+                format!(
+                    "Could not create smtp transport with relay '{}'",
+                    smtp.server
+                ),
+                err,
             )
         })?
         .port(smtp.port);
@@ -21,50 +25,52 @@ fn get_mailer(smtp: &Smtp) -> Result<SmtpTransport, String> {
     Ok(smtp_transport_builder.build())
 }
 
+/// Sends an e-mail message, or writes it to stdout when simulating.
 pub fn send_mail(
     simulate: bool,
-    email_address: String,
+    email_address: &str,
     subject: &str,
     message_body: &str,
-    smtp: Smtp,
-) -> Result<(), String> {
+    smtp: &Smtp,
+) -> Result<(), Error> {
     if simulate {
         println!(
             "--------------\nWould be sending e-mail message to: {}\nSubject: {}\nBody:\n{}\n--------------\n",
             email_address, subject, message_body
         );
     } else {
-        printlnv!("Preparing e-mail...");
+        debug!("Preparing e-mail...");
         let email = Message::builder()
             .from(smtp.email.parse().map_err(|err| {
-                format!(
-                    "Could not convert email for 'from' from text '{}'. Error: {}",
-                    smtp.email, err
+                Error::config(
+                    format!(
+                        "Could not convert email for 'from' from text '{}'",
+                        smtp.email
+                    ),
+                    err,
                 )
             })?)
             .to(email_address.parse().map_err(|err| {
-                format!(
-                    "Could not convert email for 'to' from text '{}'. Error: {}",
-                    email_address, err
+                Error::config(
+                    format!(
+                        "Could not convert email for 'to' from text '{}'",
+                        email_address
+                    ),
+                    err,
                 )
             })?)
             .subject(subject)
             .body(message_body.to_owned())
-            .map_err(|err| format!("Error when creating email: {}", err))?;
-        printlnv!("Preparing mailer...");
-        let mailer = get_mailer(&smtp)?;
-        printlnv!(
-            "Sending e-mail message to: {}\nSubject: {}\nBody:\n{}",
-            email_address,
-            subject,
-            message_body
-        );
+            .map_err(|err| Error::mail("Error when creating email", err))?;
+        debug!("Preparing mailer...");
+        let mailer = get_mailer(smtp)?;
+        info!("Sending e-mail message to: {}", email_address);
+        debug!("Subject: {}\nBody:\n{}", subject, message_body);
         let result = mailer.send(&email);
         if let Err(err) = result {
-            printlnv!("E-mail message was NOT sent successfully.\nError:\n{}", err);
-            return Err("Could not send email.".to_owned());
+            return Err(Error::mail("Could not send email.", err));
         } else {
-            printlnv!("E-mail message was sent successfully.");
+            info!("E-mail message was sent successfully.");
         }
     }
     Ok(())
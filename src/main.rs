@@ -1,36 +1,46 @@
-#[macro_use]
-mod macros;
-#[macro_use]
-extern crate derivative;
-mod alert;
 mod args;
-mod mail;
-mod run;
 use args::{Args, Command};
-
-static mut VERBOSE: bool = false;
+use trackspeedtest::error::Error;
+use trackspeedtest::{
+    alert, check, export, history, import, logging, probe, prune, run, server, storage,
+};
 
 fn main() {
     match run() {
         Err(None) => std::process::exit(1),
-        Err(Some(x)) => {
-            eprintln!("{}", x);
-            std::process::exit(1);
+        Err(Some(err)) => {
+            if log::max_level() == log::LevelFilter::Off {
+                // the logger was not set up yet
+                eprintln!("{}", err.describe());
+            } else {
+                log::error!("{}", err.describe());
+            }
+            std::process::exit(err.exit_code());
         }
         Ok(_) => std::process::exit(0),
     }
 }
 
-fn run() -> Result<(), Option<String>> {
+fn run() -> Result<(), Option<Error>> {
     let args = Args::new();
-    unsafe {
-        VERBOSE = args.verbose;
-    }
-    printlnv!("Args are {:?}.", args);
+    let log_dir = if args.log_file {
+        Some(storage::data_dir().map_err(Some)?)
+    } else {
+        None
+    };
+    logging::init(args.verbosity, args.log_format, log_dir.as_deref()).map_err(Some)?;
+    log::debug!("Args are {:?}.", args);
     match args.command {
         Some(config) => match config {
-            Command::Run(run) => run::run(run),
-            Command::Alert(alert) => alert::alert(alert),
+            Command::Run(run) => run::run(run).map_err(Some),
+            Command::Alert(alert) => alert::alert(alert).map_err(Some),
+            Command::Export(export) => export::export(export).map_err(Some),
+            Command::Serve(serve) => server::serve(serve).map_err(Some),
+            Command::History(history) => history::history(history).map_err(Some),
+            Command::Prune(prune) => prune::prune(prune).map_err(Some),
+            Command::Check(check) => check::check(check).map_err(Some),
+            Command::Import(import) => import::import(import).map_err(Some),
+            Command::Probe(probe) => probe::probe(probe).map_err(Some),
         },
         _ => Err(None),
     }
@@ -0,0 +1,276 @@
+use rumqttc::{
+    Client, ConnectReturnCode, ConnectionError, Event, MqttOptions, Outgoing, Packet, QoS,
+};
+use std::time::Duration;
+
+use crate::config::Mqtt;
+use crate::error::Error;
+use crate::output::OutputRecord;
+use crate::results::SpeedResult;
+use crate::timezone::Zone;
+use crate::units::Unit;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+/// The sensors announced to Home Assistant: field of the state, name, unit and device class.
+const SENSORS: [(&str, &str, &str, &str); 4] = [
+    ("download", "Download", "Mbit/s", "data_rate"),
+    ("upload", "Upload", "Mbit/s", "data_rate"),
+    ("ping", "Ping", "ms", "duration"),
+    ("jitter", "Jitter", "ms", "duration"),
+];
+
+/// Publishes the result as retained JSON to `<topic>/<node id>/state`, together
+/// with the Home Assistant discovery config of its sensors.
+pub fn publish(mqtt: &Mqtt, result: &SpeedResult) -> Result<(), Error> {
+    let publish_error = |err| {
+        Error::publish(
+            format!(
+                "Could not publish results to MQTT broker at {}:{}.",
+                mqtt.host, mqtt.port
+            ),
+            err,
+        )
+    };
+    let state = serde_json::to_string(&OutputRecord::new(result, Unit::Mbps, Zone::Utc))
+        .map_err(|err| Error::publish("Could not serialize results for MQTT.", err))?;
+    let mut messages = discovery_messages(mqtt);
+    messages.push((state_topic(mqtt), state));
+    debug!(
+        "Publishing {} message(s) to MQTT broker at {}:{}.",
+        messages.len(),
+        mqtt.host,
+        mqtt.port
+    );
+    send(mqtt, messages).map_err(publish_error)?;
+    info!("Results published to MQTT.");
+    Ok(())
+}
+
+fn state_topic(mqtt: &Mqtt) -> String {
+    format!("{}/{}/state", mqtt.topic, mqtt.node_id)
+}
+
+/// Topic and payload of the Home Assistant discovery config of each sensor.
+fn discovery_messages(mqtt: &Mqtt) -> Vec<(String, String)> {
+    let device_id = format!("trackspeedtest_{}", mqtt.node_id);
+    SENSORS
+        .iter()
+        .map(|(field, name, unit, device_class)| {
+            let config = serde_json::json!({
+                "name": name,
+                "unique_id": format!("{}_{}", device_id, field),
+                "state_topic": state_topic(mqtt),
+                "value_template": format!("{{{{ value_json.{} }}}}", field),
+                "unit_of_measurement": unit,
+                "device_class": device_class,
+                "state_class": "measurement",
+                "device": {
+                    "identifiers": [device_id],
+                    "name": format!("Speed test ({})", mqtt.node_id),
+                    "manufacturer": "trackspeedtest",
+                    "sw_version": env!("CARGO_PKG_VERSION"),
+                },
+            });
+            (
+                format!(
+                    "{}/sensor/{}/{}/config",
+                    mqtt.discovery_prefix, mqtt.node_id, field
+                ),
+                config.to_string(),
+            )
+        })
+        .collect()
+}
+
+/// Publishes the messages retained, with QoS 1, in an MQTT 3.1.1 session and
+/// disconnects once the broker acknowledged all of them. Every address the
+/// broker's name resolves to is tried until one connects.
+fn send(mqtt: &Mqtt, messages: Vec<(String, String)>) -> Result<(), String> {
+    let mut options = MqttOptions::new(
+        format!("trackspeedtest-{}", mqtt.node_id),
+        mqtt.host.as_str(),
+        mqtt.port,
+    );
+    options.set_keep_alive(KEEP_ALIVE).set_clean_session(true);
+    if let Some(credentials) = &mqtt.credentials {
+        options.set_credentials(credentials.username.as_str(), credentials.password.as_str());
+    }
+    let (client, mut connection) = Client::new(options, messages.len() + 1);
+    let mut network_options = connection.eventloop.network_options();
+    network_options.set_connection_timeout(TIMEOUT.as_secs());
+    connection.eventloop.set_network_options(network_options);
+    let mut unacknowledged = messages.len();
+    for (topic, payload) in messages {
+        client
+            .publish(topic, QoS::AtLeastOnce, true, payload)
+            .map_err(|err| err.to_string())?;
+    }
+    loop {
+        let event = connection
+            .recv_timeout(TIMEOUT)
+            .map_err(|_| "Timed out waiting for the broker".to_owned())?;
+        match event.map_err(describe)? {
+            Event::Incoming(Packet::PubAck(_)) => {
+                unacknowledged -= 1;
+                if unacknowledged == 0 {
+                    client.disconnect().map_err(|err| err.to_string())?;
+                }
+            }
+            Event::Outgoing(Outgoing::Disconnect) => return Ok(()),
+            _ => {}
+        }
+    }
+}
+
+fn describe(err: ConnectionError) -> String {
+    match err {
+        ConnectionError::ConnectionRefused(
+            ConnectReturnCode::BadUserNamePassword | ConnectReturnCode::NotAuthorized,
+        ) => "Broker refused the credentials".to_owned(),
+        ConnectionError::ConnectionRefused(code) => {
+            format!("Broker refused the connection: {:?}", code)
+        }
+        err => err.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Credentials;
+    use crate::results::convert_json;
+    use pretty_assertions::assert_eq;
+    use std::io::{self, prelude::*};
+    use std::net::TcpListener;
+    use std::thread;
+
+    const CONNECT: u8 = 0x10;
+    const CONNACK: u8 = 0x20;
+    const PUBLISH: u8 = 0x30;
+    const PUBACK: u8 = 0x40;
+    const DISCONNECT: u8 = 0xe0;
+
+    fn write_packet(stream: &mut impl Write, packet_type: u8, body: &[u8]) -> io::Result<()> {
+        let mut packet = vec![packet_type, body.len() as u8];
+        packet.extend_from_slice(body);
+        stream.write_all(&packet)
+    }
+
+    /// Reads a packet, returning its first byte (type and flags) and its body.
+    fn read_packet(stream: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+        let mut byte = [0; 1];
+        stream.read_exact(&mut byte)?;
+        let packet_type = byte[0];
+        let mut remaining = 0;
+        let mut multiplier = 1;
+        loop {
+            stream.read_exact(&mut byte)?;
+            remaining += (byte[0] & 0x7f) as usize * multiplier;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+            multiplier *= 128;
+        }
+        let mut body = vec![0; remaining];
+        stream.read_exact(&mut body)?;
+        Ok((packet_type, body))
+    }
+
+    /// A broker that accepts a single session and returns the retained messages
+    /// published in it.
+    fn start_broker_stub() -> (u16, thread::JoinHandle<Vec<(String, serde_json::Value)>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let broker = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let (packet_type, body) = read_packet(&mut stream).unwrap();
+            assert_eq!(CONNECT, packet_type);
+            assert_eq!(b"\0\x04MQTT", &body[..6]);
+            assert_eq!(0xc2, body[7], "Should use credentials and a clean session");
+            write_packet(&mut stream, CONNACK, &[0, 0]).unwrap();
+            let mut messages = vec![];
+            loop {
+                let (packet_type, body) = read_packet(&mut stream).unwrap();
+                if packet_type == DISCONNECT {
+                    return messages;
+                }
+                assert_eq!(PUBLISH | 0x02 | 0x01, packet_type);
+                let topic_length = u16::from_be_bytes([body[0], body[1]]) as usize;
+                let topic = String::from_utf8(body[2..2 + topic_length].to_vec()).unwrap();
+                let packet_id = &body[2 + topic_length..4 + topic_length];
+                write_packet(&mut stream, PUBACK, packet_id).unwrap();
+                let payload = serde_json::from_slice(&body[4 + topic_length..]).unwrap();
+                messages.push((topic, payload));
+            }
+        });
+        (port, broker)
+    }
+
+    #[test]
+    fn publishes_state_and_home_assistant_discovery() {
+        let (port, broker) = start_broker_stub();
+        let mqtt = Mqtt {
+            // the stub listens on IPv4 only, and localhost may resolve to ::1 first
+            host: "localhost".to_owned(),
+            port,
+            credentials: Some(Credentials {
+                username: "user".to_owned(),
+                password: "pass".to_owned(),
+            }),
+            topic: "trackspeedtest".to_owned(),
+            discovery_prefix: "homeassistant".to_owned(),
+            node_id: "router".to_owned(),
+        };
+        let result = convert_json(crate::run::SIMULATED_RESULT.to_owned()).unwrap();
+        publish(&mqtt, &result).unwrap();
+        let messages = broker.join().unwrap();
+        assert_eq!(
+            vec![
+                "homeassistant/sensor/router/download/config",
+                "homeassistant/sensor/router/upload/config",
+                "homeassistant/sensor/router/ping/config",
+                "homeassistant/sensor/router/jitter/config",
+                "trackspeedtest/router/state",
+            ],
+            messages
+                .iter()
+                .map(|(topic, _)| topic.as_str())
+                .collect::<Vec<_>>()
+        );
+        let download = &messages[0].1;
+        assert_eq!("trackspeedtest/router/state", download["state_topic"]);
+        assert_eq!("{{ value_json.download }}", download["value_template"]);
+        assert_eq!("Mbit/s", download["unit_of_measurement"]);
+        assert_eq!("trackspeedtest_router_download", download["unique_id"]);
+        let state = &messages[4].1;
+        assert_eq!(162.48, state["download"]);
+        assert_eq!(0.285, state["jitter"]);
+        assert_eq!("Mbps", state["bandwidth_unit"]);
+    }
+
+    #[test]
+    fn reports_refused_credentials() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let broker = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            read_packet(&mut stream).unwrap();
+            write_packet(&mut stream, CONNACK, &[0, 5]).unwrap();
+        });
+        let mqtt = Mqtt {
+            host: "127.0.0.1".to_owned(),
+            port,
+            credentials: None,
+            topic: "trackspeedtest".to_owned(),
+            discovery_prefix: "homeassistant".to_owned(),
+            node_id: "router".to_owned(),
+        };
+        assert_eq!(
+            Err("Broker refused the credentials".to_owned()),
+            send(&mqtt, vec![("topic".to_owned(), "{}".to_owned())])
+        );
+        broker.join().unwrap();
+    }
+}
@@ -0,0 +1,390 @@
+use serde::Serialize;
+
+use crate::config::OutputFormat;
+use crate::error::Error;
+use crate::latency::{percentile, LoadedLatency};
+use crate::results::SpeedResult;
+use crate::timezone::Zone;
+use crate::units::Unit;
+
+const LATENCY_UNIT: &str = "ms";
+
+/// A result as shown to the user, with the units of its fields.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct OutputRecord {
+    pub timestamp: String,
+    pub download: f64,
+    pub upload: f64,
+    pub bandwidth_unit: &'static str,
+    pub ping: f64,
+    pub jitter: f64,
+    pub latency_unit: &'static str,
+    /// In percent.
+    pub packet_loss: Option<f64>,
+    pub server_id: u32,
+    pub server_host: String,
+    pub server_location: String,
+    pub server_country: String,
+    pub isp: String,
+    pub client_ip: String,
+    pub interface: String,
+    pub samples: usize,
+    /// Median latency to the latency target before the measurement, in
+    /// `latency_unit`, when there is one.
+    pub idle_latency: Option<f64>,
+    pub download_latency_p50: Option<f64>,
+    pub download_latency_p90: Option<f64>,
+    pub upload_latency_p50: Option<f64>,
+    pub upload_latency_p90: Option<f64>,
+    pub bufferbloat_grade: Option<String>,
+}
+
+impl OutputRecord {
+    pub fn new(result: &SpeedResult, unit: Unit, zone: Zone) -> OutputRecord {
+        let latency = result.loaded_latency.as_ref();
+        let percentile = |samples: fn(&LoadedLatency) -> &Vec<f64>, percent| {
+            latency
+                .and_then(|latency| percentile(samples(latency), percent))
+                .map(|latency| round(latency, 3))
+        };
+        OutputRecord {
+            timestamp: zone.to_rfc3339(&result.date),
+            download: round(unit.from_bytes_per_second(result.download), 2),
+            upload: round(unit.from_bytes_per_second(result.upload), 2),
+            bandwidth_unit: unit.symbol(),
+            ping: round(result.ping, 3),
+            jitter: round(result.jitter, 3),
+            latency_unit: LATENCY_UNIT,
+            packet_loss: result.packet_loss.map(|packet_loss| round(packet_loss, 2)),
+            server_id: result.server_id,
+            server_host: result.server_host.clone(),
+            server_location: result.server_location.clone(),
+            server_country: result.server_country.clone(),
+            isp: result.client_isp.clone(),
+            client_ip: result.client_ip.clone(),
+            interface: result.interface.clone(),
+            samples: result.samples,
+            idle_latency: latency
+                .and_then(LoadedLatency::idle_median)
+                .map(|latency| round(latency, 3)),
+            download_latency_p50: percentile(|latency| &latency.download, 50.0),
+            download_latency_p90: percentile(|latency| &latency.download, 90.0),
+            upload_latency_p50: percentile(|latency| &latency.upload, 50.0),
+            upload_latency_p90: percentile(|latency| &latency.upload, 90.0),
+            bufferbloat_grade: latency
+                .and_then(LoadedLatency::grade)
+                .map(|grade| grade.to_string()),
+        }
+    }
+
+    /// Label, value and unit of each field, for the human readable formats.
+    fn fields(&self) -> Vec<(&'static str, String, Option<&'static str>)> {
+        let latency =
+            |latency: Option<f64>| latency.map_or("n/a".to_owned(), |latency| latency.to_string());
+        let mut fields = vec![
+            ("Timestamp", self.timestamp.clone(), None),
+            (
+                "Download",
+                self.download.to_string(),
+                Some(self.bandwidth_unit),
+            ),
+            ("Upload", self.upload.to_string(), Some(self.bandwidth_unit)),
+            ("Ping", self.ping.to_string(), Some(self.latency_unit)),
+            ("Jitter", self.jitter.to_string(), Some(self.latency_unit)),
+            (
+                "Packet loss",
+                self.packet_loss
+                    .map_or("n/a".to_owned(), |packet_loss| packet_loss.to_string()),
+                self.packet_loss.map(|_| "%"),
+            ),
+            ("Server id", self.server_id.to_string(), None),
+            ("Server host", self.server_host.clone(), None),
+            (
+                "Server location",
+                format!("{}, {}", self.server_location, self.server_country),
+                None,
+            ),
+            ("ISP", self.isp.clone(), None),
+            ("Client IP", self.client_ip.clone(), None),
+            ("Interface", self.interface.clone(), None),
+            ("Samples", self.samples.to_string(), None),
+        ];
+        if self.idle_latency.is_some() {
+            fields.extend([
+                (
+                    "Idle latency",
+                    latency(self.idle_latency),
+                    Some(self.latency_unit),
+                ),
+                (
+                    "Download latency",
+                    latency(self.download_latency_p50),
+                    Some(self.latency_unit),
+                ),
+                (
+                    "Upload latency",
+                    latency(self.upload_latency_p50),
+                    Some(self.latency_unit),
+                ),
+                (
+                    "Bufferbloat",
+                    self.bufferbloat_grade.clone().unwrap_or("n/a".to_owned()),
+                    None,
+                ),
+            ]);
+        }
+        fields
+    }
+}
+
+pub(crate) fn round(value: f64, decimals: i32) -> f64 {
+    let factor = 10f64.powi(decimals);
+    (value * factor).round() / factor
+}
+
+/// Formats the results to be written to stdout, with bandwidths in `unit` and
+/// timestamps in `zone`:
+///
+/// * `Plain`: the download and upload, in bytes per second, and the ping of
+///   each result, a number per line without units, whatever `unit` and `zone`;
+/// * `Json`: an array with an object per result;
+/// * `Csv`: a header followed by a row per result;
+/// * `Table`: aligned columns, with the units in the header;
+/// * `Text`: a `Field: value unit` line per field, with results separated by an empty line.
+pub fn format_results(
+    results: &[SpeedResult],
+    format: OutputFormat,
+    unit: Unit,
+    zone: Zone,
+) -> Result<String, Error> {
+    let records: Vec<OutputRecord> = results
+        .iter()
+        .map(|result| OutputRecord::new(result, unit, zone))
+        .collect();
+    let output = match format {
+        OutputFormat::Plain => results
+            .iter()
+            .map(|result| format!("{}\n{}\n{}", result.download, result.upload, result.ping))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        OutputFormat::Json => serde_json::to_string_pretty(&records)
+            .map_err(|err| Error::output("Error when formatting results as json", err))?,
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            for record in records.iter() {
+                writer
+                    .serialize(record)
+                    .map_err(|err| Error::output("Error when formatting results as csv", err))?;
+            }
+            let bytes = writer
+                .into_inner()
+                .map_err(|err| Error::output("Error when formatting results as csv", err))?;
+            String::from_utf8_lossy(&bytes).trim_end().to_owned()
+        }
+        OutputFormat::Table => format_table(&records),
+        OutputFormat::Text => records
+            .iter()
+            .map(|record| {
+                record
+                    .fields()
+                    .into_iter()
+                    .map(|(label, value, unit)| match unit {
+                        Some(unit) => format!("{}: {} {}", label, value, unit),
+                        None => format!("{}: {}", label, value),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+    };
+    Ok(output)
+}
+
+fn format_table(records: &[OutputRecord]) -> String {
+    let header: Vec<String> = match records.first() {
+        Some(record) => record
+            .fields()
+            .into_iter()
+            .map(|(label, _, unit)| match unit {
+                Some(unit) => format!("{} ({})", label, unit),
+                None => label.to_owned(),
+            })
+            .collect(),
+        None => return String::new(),
+    };
+    let rows: Vec<Vec<String>> = records
+        .iter()
+        .map(|record| {
+            record
+                .fields()
+                .into_iter()
+                .map(|(_, value, _)| value)
+                .collect()
+        })
+        .collect();
+    let widths: Vec<usize> = (0..header.len())
+        .map(|column| {
+            rows.iter()
+                .map(|row| row[column].chars().count())
+                .chain(std::iter::once(header[column].chars().count()))
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    std::iter::once(&header)
+        .chain(rows.iter())
+        .map(|row| {
+            row.iter()
+                .zip(widths.iter())
+                .map(|(value, width)| {
+                    format!("{}{}", value, " ".repeat(width - value.chars().count()))
+                })
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_owned()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::results::convert_json;
+    use chrono::{TimeZone, Utc};
+    use pretty_assertions::assert_eq;
+
+    fn create_result() -> SpeedResult {
+        let mut result = convert_json(crate::run::SIMULATED_RESULT.to_owned()).unwrap();
+        result.date = Utc.with_ymd_and_hms(2021, 1, 3, 12, 10, 0).unwrap();
+        result
+    }
+
+    #[test]
+    fn formats_results_as_json() {
+        let output = format_results(
+            &[create_result()],
+            OutputFormat::Json,
+            Unit::Mibps,
+            Zone::Utc,
+        )
+        .unwrap();
+        let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(
+            serde_json::json!([{
+                "timestamp": "2021-01-03T12:10:00Z",
+                "download": 154.95,
+                "upload": 100.76,
+                "bandwidth_unit": "Mibps",
+                "ping": 5.728,
+                "jitter": 0.285,
+                "latency_unit": "ms",
+                "packet_loss": 0.0,
+                "server_id": 99999,
+                "server_host": "someserver.nonexistentxyz.com",
+                "server_location": "São Paulo",
+                "server_country": "Brazil",
+                "isp": "Some ISP",
+                "client_ip": "84.6.0.1",
+                "interface": "eth0",
+                "samples": 1,
+                "idle_latency": null,
+                "download_latency_p50": null,
+                "download_latency_p90": null,
+                "upload_latency_p50": null,
+                "upload_latency_p90": null,
+                "bufferbloat_grade": null
+            }]),
+            json
+        );
+    }
+
+    #[test]
+    fn formats_results_as_plain_numbers_like_earlier_versions() {
+        let output = format_results(
+            &[create_result(), create_result()],
+            OutputFormat::Plain,
+            Unit::Mibps,
+            Zone::Utc,
+        )
+        .unwrap();
+        assert_eq!(
+            "20309419\n13206885\n5.728\n20309419\n13206885\n5.728",
+            output
+        );
+    }
+
+    #[test]
+    fn formats_results_as_csv() {
+        let output = format_results(
+            &[create_result()],
+            OutputFormat::Csv,
+            Unit::Mibps,
+            Zone::Utc,
+        )
+        .unwrap();
+        assert_eq!(
+            "timestamp,download,upload,bandwidth_unit,ping,jitter,latency_unit,packet_loss,server_id,server_host,server_location,server_country,isp,client_ip,interface,samples,idle_latency,download_latency_p50,download_latency_p90,upload_latency_p50,upload_latency_p90,bufferbloat_grade\n\
+             2021-01-03T12:10:00Z,154.95,100.76,Mibps,5.728,0.285,ms,0.0,99999,someserver.nonexistentxyz.com,São Paulo,Brazil,Some ISP,84.6.0.1,eth0,1,,,,,,",
+            output
+        );
+    }
+
+    #[test]
+    fn formats_results_as_text() {
+        let mut result = create_result();
+        result.packet_loss = None;
+        let zone = "-03:00".parse::<Zone>().unwrap();
+        let output = format_results(&[result], OutputFormat::Text, Unit::Mibps, zone).unwrap();
+        let lines: Vec<&str> = output.lines().take(6).collect();
+        assert_eq!(
+            vec![
+                "Timestamp: 2021-01-03T09:10:00-03:00",
+                "Download: 154.95 Mibps",
+                "Upload: 100.76 Mibps",
+                "Ping: 5.728 ms",
+                "Jitter: 0.285 ms",
+                "Packet loss: n/a",
+            ],
+            lines
+        );
+    }
+
+    #[test]
+    fn formats_loaded_latency_when_it_was_measured() {
+        let mut result = create_result();
+        result.loaded_latency = Some(LoadedLatency {
+            idle: vec![5.1, 5.3, 5.2],
+            download: vec![48.0, 52.5, 61.2],
+            upload: vec![18.4, 22.1, 25.9],
+        });
+        let output = format_results(&[result], OutputFormat::Text, Unit::Mibps, Zone::Utc).unwrap();
+        let lines: Vec<&str> = output.lines().skip(13).collect();
+        assert_eq!(
+            vec![
+                "Idle latency: 5.2 ms",
+                "Download latency: 52.5 ms",
+                "Upload latency: 22.1 ms",
+                "Bufferbloat: B",
+            ],
+            lines
+        );
+    }
+
+    #[test]
+    fn formats_results_as_a_table() {
+        let output = format_results(
+            &[create_result(), create_result()],
+            OutputFormat::Table,
+            Unit::Mibps,
+            Zone::Utc,
+        )
+        .unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(3, lines.len());
+        assert!(lines[0].starts_with("Timestamp             Download (Mibps)  Upload (Mibps)"));
+        assert!(lines[1].starts_with("2021-01-03T12:10:00Z  154.95            100.76"));
+    }
+}
@@ -0,0 +1,207 @@
+use parquet::basic::{LogicalType, Repetition, TimeUnit, Type as PhysicalType};
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::types::Type;
+use std::io;
+use std::sync::Arc;
+
+/// Physical types of the columns, with the annotations readers need to tell
+/// strings and timestamps apart.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Boolean,
+    Int64,
+    Double,
+    /// UTF-8 text.
+    String,
+    /// Microseconds since the Unix epoch, in UTC.
+    Timestamp,
+}
+
+/// A value of a column, of the matching `Kind`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Boolean(bool),
+    Int64(i64),
+    Double(f64),
+    String(String),
+    Timestamp(i64),
+}
+
+/// The rows as a Parquet file with a single row group. Every column is
+/// optional, `None` being null, and stored uncompressed so that any reader
+/// can open the file.
+pub fn write(columns: &[(&str, Kind)], rows: &[Vec<Option<Value>>]) -> io::Result<Vec<u8>> {
+    let fields = columns
+        .iter()
+        .map(|(name, kind)| field(name, *kind).map(Arc::new))
+        .collect::<Result<_, _>>()?;
+    let schema = Type::group_type_builder("schema")
+        .with_fields(fields)
+        .build()?;
+    let properties = WriterProperties::builder()
+        .set_created_by("trackspeedtest".to_owned())
+        .build();
+    let mut writer = SerializedFileWriter::new(vec![], Arc::new(schema), Arc::new(properties))?;
+    if !rows.is_empty() {
+        let mut row_group = writer.next_row_group()?;
+        for (index, (_, kind)) in columns.iter().enumerate() {
+            let levels: Vec<i16> = rows.iter().map(|row| row[index].is_some() as i16).collect();
+            let present = rows.iter().filter_map(|row| row[index].as_ref());
+            let mut column = row_group
+                .next_column()?
+                .expect("the schema has a column for each kind");
+            match kind {
+                Kind::Boolean => {
+                    let values: Vec<bool> = present
+                        .map(|value| matches!(value, Value::Boolean(true)))
+                        .collect();
+                    column
+                        .typed::<BoolType>()
+                        .write_batch(&values, Some(&levels), None)?;
+                }
+                Kind::Int64 | Kind::Timestamp => {
+                    let values: Vec<i64> = present
+                        .map(|value| match value {
+                            Value::Int64(value) | Value::Timestamp(value) => *value,
+                            _ => unreachable!("a column has values of its kind only"),
+                        })
+                        .collect();
+                    column
+                        .typed::<Int64Type>()
+                        .write_batch(&values, Some(&levels), None)?;
+                }
+                Kind::Double => {
+                    let values: Vec<f64> = present
+                        .map(|value| match value {
+                            Value::Double(value) => *value,
+                            _ => unreachable!("a column has values of its kind only"),
+                        })
+                        .collect();
+                    column
+                        .typed::<DoubleType>()
+                        .write_batch(&values, Some(&levels), None)?;
+                }
+                Kind::String => {
+                    let values: Vec<ByteArray> = present
+                        .map(|value| match value {
+                            Value::String(value) => value.as_str().into(),
+                            _ => unreachable!("a column has values of its kind only"),
+                        })
+                        .collect();
+                    column
+                        .typed::<ByteArrayType>()
+                        .write_batch(&values, Some(&levels), None)?;
+                }
+            }
+            column.close()?;
+        }
+        row_group.close()?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+/// The schema of a column.
+fn field(name: &str, kind: Kind) -> parquet::errors::Result<Type> {
+    let (physical_type, logical_type) = match kind {
+        Kind::Boolean => (PhysicalType::BOOLEAN, None),
+        Kind::Int64 => (PhysicalType::INT64, None),
+        Kind::Double => (PhysicalType::DOUBLE, None),
+        Kind::String => (PhysicalType::BYTE_ARRAY, Some(LogicalType::String)),
+        Kind::Timestamp => (
+            PhysicalType::INT64,
+            Some(LogicalType::Timestamp {
+                is_adjusted_to_u_t_c: true,
+                unit: TimeUnit::MICROS(Default::default()),
+            }),
+        ),
+    };
+    Type::primitive_type_builder(name, physical_type)
+        .with_repetition(Repetition::OPTIONAL)
+        .with_logical_type(logical_type)
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::Field;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn writes_files_parquet_readers_open() {
+        let columns = [
+            ("date", Kind::Timestamp),
+            ("isp", Kind::String),
+            ("download", Kind::Double),
+            ("samples", Kind::Int64),
+            ("failed", Kind::Boolean),
+        ];
+        let rows = vec![
+            vec![
+                Some(Value::Timestamp(1_609_675_800_000_000)),
+                Some(Value::String("Some ISP".to_owned())),
+                Some(Value::Double(93.5)),
+                Some(Value::Int64(3)),
+                Some(Value::Boolean(false)),
+            ],
+            vec![
+                Some(Value::Timestamp(1_609_679_400_000_000)),
+                None,
+                None,
+                None,
+                Some(Value::Boolean(true)),
+            ],
+        ];
+        let file = write(&columns, &rows).unwrap();
+        let reader = SerializedFileReader::new(bytes::Bytes::from(file)).unwrap();
+        let metadata = reader.metadata().file_metadata();
+        assert_eq!(2, metadata.num_rows());
+        assert_eq!(Some("trackspeedtest"), metadata.created_by());
+        let read: Vec<Vec<(String, Field)>> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap().into_columns())
+            .collect();
+        let expected = vec![
+            vec![
+                (
+                    "date".to_owned(),
+                    Field::TimestampMicros(1_609_675_800_000_000),
+                ),
+                ("isp".to_owned(), Field::Str("Some ISP".to_owned())),
+                ("download".to_owned(), Field::Double(93.5)),
+                ("samples".to_owned(), Field::Long(3)),
+                ("failed".to_owned(), Field::Bool(false)),
+            ],
+            vec![
+                (
+                    "date".to_owned(),
+                    Field::TimestampMicros(1_609_679_400_000_000),
+                ),
+                ("isp".to_owned(), Field::Null),
+                ("download".to_owned(), Field::Null),
+                ("samples".to_owned(), Field::Null),
+                ("failed".to_owned(), Field::Bool(true)),
+            ],
+        ];
+        assert_eq!(expected, read);
+    }
+
+    #[test]
+    fn writes_files_without_rows() {
+        let file = write(&[("date", Kind::Timestamp)], &[]).unwrap();
+        let reader = SerializedFileReader::new(bytes::Bytes::from(file)).unwrap();
+        assert_eq!(0, reader.metadata().file_metadata().num_rows());
+        assert_eq!(
+            1,
+            reader
+                .metadata()
+                .file_metadata()
+                .schema_descr()
+                .num_columns()
+        );
+    }
+}
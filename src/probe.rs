@@ -0,0 +1,812 @@
+use chrono::{DateTime, SubsecRound, Utc};
+use rev_lines::RawRevLines;
+use serde::Deserialize;
+use std::ffi::CStr;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use url::Url;
+
+use crate::aggregate::median;
+use crate::config::Probe;
+use crate::error::Error;
+use crate::latency;
+use crate::mail;
+use crate::storage::{data_dir, date_time_from_str, format_date};
+
+/// File the probes are written to, inside the data directory.
+pub const PROBES_FILE: &str = "probes.csv";
+
+/// Columns of the probes file.
+pub const PROBES_HEADER: [&str; 7] = [
+    "date",
+    "target",
+    "sent",
+    "received",
+    "packet_loss",
+    "latency",
+    "error",
+];
+
+/// Targets probed when none are given.
+pub const DEFAULT_TARGETS: [&str; 4] = [
+    "gateway",
+    "tcp:1.1.1.1:443",
+    "tcp:8.8.8.8:53",
+    "dns:www.speedtest.net",
+];
+
+/// Routing table of the kernel, where the default gateway is found.
+const ROUTE_TABLE: &str = "/proc/net/route";
+
+/// Port the gateway is probed on when none is given, home routers usually
+/// answer DNS on it.
+pub const GATEWAY_PORT: u16 = 53;
+
+/// Something to probe.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    /// A TCP handshake with the default gateway, on the port.
+    Gateway(u16),
+    /// A TCP handshake with a host and port, like `1.1.1.1:443`.
+    Tcp(String),
+    /// The resolution of a name.
+    Dns(String),
+    /// A HEAD request to an `http` or `https` URL.
+    Http(Url),
+}
+
+/// The text used in the command line and in the `target` column.
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::Gateway(GATEWAY_PORT) => write!(f, "gateway"),
+            Target::Gateway(port) => write!(f, "gateway:{}", port),
+            Target::Tcp(address) => write!(f, "tcp:{}", address),
+            Target::Dns(name) => write!(f, "dns:{}", name),
+            Target::Http(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Unknown probe target '{}', use gateway, gateway:PORT, tcp:HOST:PORT, dns:NAME or an http(s) URL.",
+                s
+            )
+        };
+        if s == "gateway" {
+            Ok(Target::Gateway(GATEWAY_PORT))
+        } else if let Some(port) = s.strip_prefix("gateway:") {
+            port.parse::<u16>()
+                .map(Target::Gateway)
+                .map_err(|_| invalid())
+        } else if let Some(address) = s.strip_prefix("tcp:") {
+            match address.rsplit_once(':') {
+                Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                    Ok(Target::Tcp(address.to_owned()))
+                }
+                _ => Err(invalid()),
+            }
+        } else if let Some(name) = s.strip_prefix("dns:").filter(|name| !name.is_empty()) {
+            Ok(Target::Dns(name.to_owned()))
+        } else if s.starts_with("http://") || s.starts_with("https://") {
+            Url::parse(s).map(Target::Http).map_err(|_| invalid())
+        } else {
+            Err(invalid())
+        }
+    }
+}
+
+/// A row of the probes file: a target probed `sent` times in a round.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ProbeRow {
+    #[serde(deserialize_with = "date_time_from_str")]
+    pub date: DateTime<Utc>,
+    pub target: String,
+    pub sent: u32,
+    pub received: u32,
+    /// In percent.
+    pub packet_loss: f64,
+    /// Median of the answers, in milliseconds.
+    pub latency: Option<f64>,
+    /// Error of the last attempt that failed.
+    pub error: Option<String>,
+}
+
+impl ProbeRow {
+    fn record(&self) -> Vec<String> {
+        vec![
+            format_date(&self.date),
+            self.target.clone(),
+            self.sent.to_string(),
+            self.received.to_string(),
+            format!("{:.1}", self.packet_loss),
+            self.latency
+                .map_or("".to_owned(), |latency| format!("{:.3}", latency)),
+            self.error.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+impl fmt::Display for ProbeRow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} of {} answered",
+            self.target, self.received, self.sent
+        )?;
+        if let Some(latency) = self.latency {
+            write!(f, ", {:.1} ms", latency)?;
+        }
+        if let Some(error) = &self.error {
+            write!(f, " ({})", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// The rows of the targets probed together.
+#[derive(Debug, PartialEq)]
+pub struct Round {
+    pub date: DateTime<Utc>,
+    pub rows: Vec<ProbeRow>,
+}
+
+impl Round {
+    /// Whether none of the targets past the gateway answered. A router that
+    /// still answers while its uplink is down is no connectivity, so the
+    /// gateway only counts when it is the only target.
+    pub fn is_outage(&self) -> bool {
+        let is_gateway =
+            |row: &&ProbeRow| row.target == "gateway" || row.target.starts_with("gateway:");
+        let mut past_gateway = self.rows.iter().filter(|row| !is_gateway(row)).peekable();
+        if past_gateway.peek().is_none() {
+            return self.rows.iter().all(|row| row.received == 0);
+        }
+        past_gateway.all(|row| row.received == 0)
+    }
+
+    /// Average packet loss of the targets, in percent.
+    pub fn packet_loss(&self) -> f64 {
+        self.rows.iter().map(|row| row.packet_loss).sum::<f64>() / self.rows.len().max(1) as f64
+    }
+}
+
+/// The uplink probes leave through, the default route when both are empty.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Uplink {
+    pub interface: Option<String>,
+    pub source_ip: Option<IpAddr>,
+}
+
+impl Uplink {
+    /// The interface of the uplink, found from the source IP when only it is set.
+    pub fn interface(&self) -> Option<String> {
+        self.interface.clone().or_else(|| {
+            let source_ip = self.source_ip?;
+            interface_addresses()
+                .into_iter()
+                .find(|(_, address)| *address == source_ip)
+                .map(|(name, _)| name)
+        })
+    }
+
+    /// The address probes leave from: the source IP, or the IPv4 address of
+    /// the interface.
+    pub fn source_address(&self) -> Option<IpAddr> {
+        self.source_ip.or_else(|| {
+            let interface = self.interface.as_deref()?;
+            interface_addresses()
+                .into_iter()
+                .find(|(name, address)| name == interface && address.is_ipv4())
+                .map(|(_, address)| address)
+        })
+    }
+}
+
+/// Where the probes of a round leave from and the gateway they find, looked
+/// up once per round.
+struct Route {
+    source: Option<IpAddr>,
+    gateway: Option<Ipv4Addr>,
+}
+
+/// Probes the targets, once or every `interval`, appends the results to the
+/// probes file and sends the alerts.
+pub fn probe(probe: Probe) -> Result<(), Error> {
+    let data_dir = data_dir()?;
+    loop {
+        let started = Instant::now();
+        let rows = probe_round(
+            &probe.targets,
+            &Uplink::default(),
+            probe.attempts,
+            probe.timeout,
+        );
+        for row in rows.iter() {
+            println!("{}", row);
+        }
+        append_rows(&data_dir, &rows)?;
+        if let Err(err) = send_alerts(&data_dir, &probe) {
+            warn!("Could not send probe alerts. Error:\n{}", err.describe());
+        }
+        match probe.interval {
+            Some(interval) => thread::sleep(interval.saturating_sub(started.elapsed())),
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Probes every target `attempts` times through the uplink, all targets at
+/// the same time.
+pub fn probe_round(
+    targets: &[Target],
+    uplink: &Uplink,
+    attempts: u8,
+    timeout: Duration,
+) -> Vec<ProbeRow> {
+    let date = Utc::now().trunc_subsecs(0);
+    let route = Route {
+        source: uplink.source_address(),
+        gateway: default_gateway(uplink.interface().as_deref()),
+    };
+    let route = &route;
+    thread::scope(|scope| {
+        let probes: Vec<_> = targets
+            .iter()
+            .map(|target| scope.spawn(move || probe_target(target, route, attempts, timeout, date)))
+            .collect();
+        probes
+            .into_iter()
+            .map(|probe| probe.join().expect("Probing should not panic."))
+            .collect()
+    })
+}
+
+fn probe_target(
+    target: &Target,
+    route: &Route,
+    attempts: u8,
+    timeout: Duration,
+    date: DateTime<Utc>,
+) -> ProbeRow {
+    let mut latencies = vec![];
+    let mut error = None;
+    for _ in 0..attempts {
+        match attempt(target, route, timeout) {
+            Ok(latency) => latencies.push(latency),
+            Err(err) => error = Some(err),
+        }
+    }
+    let sent = attempts as u32;
+    let received = latencies.len() as u32;
+    ProbeRow {
+        date,
+        target: target.to_string(),
+        sent,
+        received,
+        packet_loss: (sent - received) as f64 * 100.0 / sent.max(1) as f64,
+        latency: (!latencies.is_empty()).then(|| median(&latencies)),
+        error,
+    }
+}
+
+/// Probes the target once, returning how long it took to answer, in
+/// milliseconds, or why it did not. Names are resolved by the system, through
+/// its own route, and HTTP requests take the default route.
+fn attempt(target: &Target, route: &Route, timeout: Duration) -> Result<f64, String> {
+    match target {
+        Target::Gateway(port) => {
+            let gateway = route.gateway.ok_or("no default gateway")?;
+            latency::probe_from(&SocketAddr::from((gateway, *port)), route.source, timeout)
+                .map_err(describe)
+        }
+        Target::Tcp(address) => {
+            // a source address only reaches targets of its own family
+            let address = resolve(address, timeout)?
+                .into_iter()
+                .find(|address| {
+                    route
+                        .source
+                        .is_none_or(|source| source.is_ipv4() == address.is_ipv4())
+                })
+                .ok_or_else(|| format!("{} has no address the uplink can reach", address))?;
+            latency::probe_from(&address, route.source, timeout).map_err(describe)
+        }
+        Target::Dns(name) => {
+            let start = Instant::now();
+            resolve(&format!("{}:0", name), timeout)?;
+            Ok(start.elapsed().as_secs_f64() * 1000.0)
+        }
+        Target::Http(url) => {
+            let start = Instant::now();
+            let agent: ureq::Agent = ureq::Agent::config_builder()
+                .timeout_global(Some(timeout))
+                .http_status_as_error(false)
+                .build()
+                .into();
+            let response = agent.head(url.as_str()).call().map_err(|err| match err {
+                ureq::Error::Timeout(_) => "timed out".to_owned(),
+                ureq::Error::Io(err) => describe(err),
+                err => err.to_string(),
+            })?;
+            if response.status().is_server_error() {
+                return Err(format!("HTTP status {}", response.status().as_u16()));
+            }
+            Ok(start.elapsed().as_secs_f64() * 1000.0)
+        }
+    }
+}
+
+/// Resolves a `host:port`, giving up after `timeout`. The resolver of the
+/// system has no timeout of its own, so it runs in a thread that is left
+/// behind when it takes too long.
+pub fn resolve(address: &str, timeout: Duration) -> Result<Vec<SocketAddr>, String> {
+    let (sender, receiver) = mpsc::channel();
+    let owned = address.to_owned();
+    thread::spawn(move || {
+        let _ = sender.send(
+            owned
+                .to_socket_addrs()
+                .map(|addresses| addresses.collect::<Vec<_>>()),
+        );
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(Ok(addresses)) if !addresses.is_empty() => Ok(addresses),
+        Ok(Ok(_)) => Err(format!("{} has no address", address)),
+        Ok(Err(err)) => Err(format!("could not resolve {}: {}", address, err)),
+        Err(_) => Err(format!("resolving {} timed out", address)),
+    }
+}
+
+fn describe(err: io::Error) -> String {
+    match err.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => "timed out".to_owned(),
+        _ => err.to_string(),
+    }
+}
+
+/// The default gateway in the routing table of the kernel, of the interface
+/// when there is one.
+pub fn default_gateway(interface: Option<&str>) -> Option<Ipv4Addr> {
+    parse_route_table(&fs::read_to_string(ROUTE_TABLE).ok()?, interface)
+}
+
+/// The gateway of the default route with the lowest metric, through the
+/// interface when there is one, in the format of `/proc/net/route`, whose
+/// addresses are hexadecimal in the byte order of the machine.
+pub fn parse_route_table(table: &str, interface: Option<&str>) -> Option<Ipv4Addr> {
+    const RTF_UP: u32 = 0x1;
+    const RTF_GATEWAY: u32 = 0x2;
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if interface.is_some_and(|interface| fields.first() != Some(&interface)) {
+                return None;
+            }
+            let hex = |index: usize| u32::from_str_radix(fields.get(index)?, 16).ok();
+            let (destination, gateway, flags) = (hex(1)?, hex(2)?, hex(3)?);
+            let metric = fields.get(6)?.parse::<u32>().ok()?;
+            let default_route = destination == 0 && flags & RTF_UP != 0 && flags & RTF_GATEWAY != 0;
+            default_route.then(|| (metric, Ipv4Addr::from(gateway.to_ne_bytes())))
+        })
+        .min_by_key(|(metric, _)| *metric)
+        .map(|(_, gateway)| gateway)
+}
+
+/// The addresses of the network interfaces, with the name of their interface.
+fn interface_addresses() -> Vec<(String, IpAddr)> {
+    let mut addresses = vec![];
+    let mut list: *mut libc::ifaddrs = std::ptr::null_mut();
+    // the entries are only read until the list is freed
+    unsafe {
+        if libc::getifaddrs(&mut list) != 0 {
+            return addresses;
+        }
+        let mut current = list;
+        while let Some(entry) = current.as_ref() {
+            if let Some(address) = entry.ifa_addr.as_ref() {
+                let name = CStr::from_ptr(entry.ifa_name)
+                    .to_string_lossy()
+                    .into_owned();
+                match address.sa_family as libc::c_int {
+                    libc::AF_INET => {
+                        let address = &*(entry.ifa_addr as *const libc::sockaddr_in);
+                        let ip = Ipv4Addr::from(u32::from_be(address.sin_addr.s_addr));
+                        addresses.push((name, IpAddr::V4(ip)));
+                    }
+                    libc::AF_INET6 => {
+                        let address = &*(entry.ifa_addr as *const libc::sockaddr_in6);
+                        let ip = Ipv6Addr::from(address.sin6_addr.s6_addr);
+                        addresses.push((name, IpAddr::V6(ip)));
+                    }
+                    _ => {}
+                }
+            }
+            current = entry.ifa_next;
+        }
+        libc::freeifaddrs(list);
+    }
+    addresses
+}
+
+/// Appends the rows to the probes file, writing the header first when it is new.
+pub fn append_rows(data_dir: &Path, rows: &[ProbeRow]) -> Result<(), Error> {
+    fs::create_dir_all(data_dir)
+        .map_err(|err| Error::storage("Error when creating data directory", err))?;
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(data_dir.join(PROBES_FILE))
+        .map_err(|err| Error::storage("Error when opening probes file", err))?;
+    file.lock()
+        .map_err(|err| Error::storage("Error when locking probes file", err))?;
+    let new = file
+        .metadata()
+        .map_err(|err| Error::storage("Error when reading probes file", err))?
+        .len()
+        == 0;
+    let mut writer = csv::Writer::from_writer(&file);
+    if new {
+        writer
+            .write_record(PROBES_HEADER)
+            .map_err(|err| Error::storage("Error when writing probes file", err))?;
+    }
+    for row in rows {
+        writer
+            .write_record(row.record())
+            .map_err(|err| Error::storage("Error when writing probes file", err))?;
+    }
+    writer
+        .flush()
+        .map_err(|err| Error::storage("Error when writing probes file", err))?;
+    Ok(())
+}
+
+/// Reads the rounds of the probes file backwards, newest first, until
+/// `enough` is true for the rounds read so far or the file ends. The rows of
+/// each round keep the order of the file.
+pub fn latest_rounds(
+    data_dir: &Path,
+    mut enough: impl FnMut(&[Round]) -> bool,
+) -> Result<Vec<Round>, Error> {
+    let file_path = data_dir.join(PROBES_FILE);
+    if !file_path.exists() {
+        return Ok(vec![]);
+    }
+    let file = File::open(&file_path)
+        .map_err(|err| Error::storage("Error when opening probes file", err))?;
+    file.lock_shared()
+        .map_err(|err| Error::storage("Error when locking probes file", err))?;
+    let header = csv::StringRecord::from(PROBES_HEADER.to_vec());
+    let mut rounds: Vec<Round> = vec![];
+    for line in RawRevLines::new(BufReader::new(&file)) {
+        let line = line.map_err(|err| Error::storage("Error when reading probes file", err))?;
+        let row = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(line.as_slice())
+            .records()
+            .next()
+            .and_then(Result::ok)
+            .and_then(|record| record.deserialize::<ProbeRow>(Some(&header)).ok());
+        let row = match row {
+            Some(row) => row,
+            None => {
+                if !line.starts_with(b"date,") && !line.is_empty() {
+                    warn!("Skipping a malformed row of the probes file.");
+                }
+                continue;
+            }
+        };
+        match rounds.last_mut() {
+            Some(round) if round.date == row.date => round.rows.insert(0, row),
+            _ => {
+                if enough(&rounds) {
+                    return Ok(rounds);
+                }
+                rounds.push(Round {
+                    date: row.date,
+                    rows: vec![row],
+                });
+            }
+        }
+    }
+    Ok(rounds)
+}
+
+/// An outage that ended: the rounds from `from` until `to` got no answer.
+#[derive(Debug, PartialEq)]
+pub struct Outage {
+    pub from: DateTime<Utc>,
+    /// The first round that got an answer again.
+    pub to: DateTime<Utc>,
+}
+
+/// The outage that the latest round ended, if it did.
+pub fn ended_outage(rounds: &[Round]) -> Option<Outage> {
+    let (latest, previous) = rounds.split_first()?;
+    if latest.is_outage() {
+        return None;
+    }
+    let first_down = previous
+        .iter()
+        .take_while(|round| round.is_outage())
+        .last()?;
+    Some(Outage {
+        from: first_down.date,
+        to: latest.date,
+    })
+}
+
+/// The average packet loss of the latest `window` rounds, when there are
+/// enough, and of the `window` rounds before the latest one, to know if it
+/// just went over a limit.
+pub fn packet_loss(rounds: &[Round], window: usize) -> Option<(f64, f64)> {
+    if window == 0 || rounds.len() <= window {
+        return None;
+    }
+    let average =
+        |rounds: &[Round]| rounds.iter().map(Round::packet_loss).sum::<f64>() / rounds.len() as f64;
+    Some((average(&rounds[..window]), average(&rounds[1..=window])))
+}
+
+/// Sends an e-mail when the latest round ended an outage longer than
+/// `max_outage` (no e-mail can go out during one) and when the packet loss
+/// goes over `max_packet_loss`.
+fn send_alerts(data_dir: &Path, probe: &Probe) -> Result<(), Error> {
+    if probe.max_outage.is_none() && probe.max_packet_loss.is_none() {
+        return Ok(());
+    }
+    let window = probe.loss_window;
+    let rounds = latest_rounds(data_dir, |rounds| {
+        rounds.len() > window
+            && rounds.len() > 1
+            && rounds.last().is_some_and(|round| !round.is_outage())
+    })?;
+    let mut alerts = vec![];
+    if let (Some(max_outage), Some(outage)) = (probe.max_outage, ended_outage(&rounds)) {
+        let duration = outage.to - outage.from;
+        if duration >= max_outage {
+            alerts.push((
+                "Connectivity outage",
+                format!(
+                    "None of the probe targets past the gateway answered for about {} minute(s), from {} until {}.",
+                    duration.num_minutes(),
+                    probe.timezone.format(&outage.from, "%Y/%m/%d %H:%M:%S %:z"),
+                    probe.timezone.format(&outage.to, "%Y/%m/%d %H:%M:%S %:z")
+                ),
+            ));
+        }
+    }
+    if let (Some(max_packet_loss), Some((loss, previous_loss))) =
+        (probe.max_packet_loss, packet_loss(&rounds, window))
+    {
+        if loss > max_packet_loss && previous_loss <= max_packet_loss {
+            let targets = rounds[0]
+                .rows
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("\n");
+            alerts.push((
+                "Packet loss above expectation",
+                format!(
+                    "Packet loss averaged {:.1}% in the latest {} probe rounds, above the maximum of {}%.\n\nLatest round:\n{}",
+                    loss, window, max_packet_loss, targets
+                ),
+            ));
+        }
+    }
+    for (subject, message_body) in alerts {
+        warn!("{}", message_body);
+        if let Some(email_options) = &probe.email_options {
+            mail::send_mail(
+                probe.simulate,
+                &email_options.email,
+                subject,
+                &message_body,
+                &email_options.smtp,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::create_data_dir;
+    use chrono::TimeZone;
+    use pretty_assertions::assert_eq;
+    use std::io::prelude::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn parses_targets() {
+        for target in ["gateway", "gateway:80", "tcp:1.1.1.1:443", "dns:localhost"] {
+            assert_eq!(target, target.parse::<Target>().unwrap().to_string());
+        }
+        assert_eq!(
+            Target::Http(Url::parse("http://127.0.0.1:8080/").unwrap()),
+            "http://127.0.0.1:8080/".parse::<Target>().unwrap()
+        );
+        assert!("tcp:1.1.1.1".parse::<Target>().is_err());
+        assert!("icmp:1.1.1.1".parse::<Target>().is_err());
+    }
+
+    #[test]
+    fn finds_the_default_gateway() {
+        let table =
+            "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n\
+                     eth0\t0000A8C0\t00000000\t0001\t0\t0\t0\t00FFFFFF\t0\t0\t0\n\
+                     wlan0\t00000000\t0201A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0\n\
+                     eth0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0\n";
+        let expected = if cfg!(target_endian = "little") {
+            Ipv4Addr::new(192, 168, 1, 1)
+        } else {
+            Ipv4Addr::new(1, 1, 168, 192)
+        };
+        assert_eq!(Some(expected), parse_route_table(table, None));
+        let wlan = if cfg!(target_endian = "little") {
+            Ipv4Addr::new(192, 168, 1, 2)
+        } else {
+            Ipv4Addr::new(2, 1, 168, 192)
+        };
+        assert_eq!(Some(wlan), parse_route_table(table, Some("wlan0")));
+        assert_eq!(None, parse_route_table(table, Some("eth1")));
+        assert_eq!(None, parse_route_table("Iface\tDestination\n", None));
+    }
+
+    #[test]
+    fn probes_localhost_targets() {
+        let open = TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed_address = closed.local_addr().unwrap();
+        drop(closed);
+        let http = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", http.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            for stream in http.incoming().take(2) {
+                let mut stream = stream.unwrap();
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request).unwrap();
+                stream
+                    .write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")
+                    .unwrap();
+            }
+        });
+        let targets: Vec<Target> = [
+            format!("tcp:{}", open.local_addr().unwrap()),
+            format!("tcp:{}", closed_address),
+            "dns:localhost".to_owned(),
+            url,
+            "tcp:nonexistent.invalid:80".to_owned(),
+        ]
+        .iter()
+        .map(|target| target.parse().unwrap())
+        .collect();
+        let rows = probe_round(&targets, &Uplink::default(), 2, Duration::from_secs(2));
+        server.join().unwrap();
+        assert_eq!(
+            vec![2, 2, 2, 2, 0],
+            rows.iter().map(|row| row.received).collect::<Vec<_>>()
+        );
+        assert_eq!(100.0, rows[4].packet_loss);
+        assert!(rows[4].error.is_some());
+        assert!(rows[..4].iter().all(|row| row.latency.is_some()));
+    }
+
+    #[test]
+    fn probes_through_an_uplink() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target: Target = format!("tcp:{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let loopback = Uplink {
+            interface: Some("lo".to_owned()),
+            source_ip: None,
+        };
+        assert_eq!(
+            Some("127.0.0.1".parse().unwrap()),
+            loopback.source_address()
+        );
+        let by_ip = Uplink {
+            interface: None,
+            source_ip: Some("127.0.0.1".parse().unwrap()),
+        };
+        assert_eq!(Some("lo".to_owned()), by_ip.interface());
+        for uplink in [loopback, by_ip] {
+            let rows = probe_round(
+                std::slice::from_ref(&target),
+                &uplink,
+                1,
+                Duration::from_secs(2),
+            );
+            assert_eq!(1, rows[0].received, "{:?}", rows[0]);
+        }
+        let unreachable = Uplink {
+            interface: None,
+            source_ip: Some("::1".parse().unwrap()),
+        };
+        let rows = probe_round(&[target], &unreachable, 1, Duration::from_secs(2));
+        assert_eq!(0, rows[0].received);
+    }
+
+    #[test]
+    fn outages_are_decided_past_the_gateway() {
+        let round = |targets: &[(&str, u32)]| Round {
+            date: Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap(),
+            rows: targets
+                .iter()
+                .map(|(target, received)| ProbeRow {
+                    date: Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap(),
+                    target: (*target).to_owned(),
+                    sent: 3,
+                    received: *received,
+                    packet_loss: (3 - received) as f64 * 100.0 / 3.0,
+                    latency: None,
+                    error: None,
+                })
+                .collect(),
+        };
+        assert!(round(&[("gateway", 3), ("tcp:1.1.1.1:443", 0), ("dns:a", 0)]).is_outage());
+        assert!(!round(&[("gateway", 0), ("tcp:1.1.1.1:443", 1), ("dns:a", 0)]).is_outage());
+        assert!(round(&[("gateway:80", 3), ("tcp:1.1.1.1:443", 0)]).is_outage());
+        assert!(!round(&[("gateway", 3)]).is_outage());
+        assert!(round(&[("gateway", 0)]).is_outage());
+    }
+
+    #[test]
+    fn finds_outages_and_packet_loss_in_the_rounds() {
+        let data_dir = create_data_dir("probe-rounds");
+        let row = |minute: u32, target: &str, received: u32| ProbeRow {
+            date: Utc.with_ymd_and_hms(2021, 1, 1, 0, minute, 0).unwrap(),
+            target: target.to_owned(),
+            sent: 4,
+            received,
+            packet_loss: (4 - received) as f64 * 25.0,
+            latency: (received > 0).then_some(10.0),
+            error: (received < 4).then(|| "timed out".to_owned()),
+        };
+        for (minute, received) in [(0, 4), (1, 3), (2, 0), (3, 0), (4, 0), (5, 2)] {
+            append_rows(
+                &data_dir,
+                &[
+                    row(minute, "gateway", received),
+                    row(minute, "dns:a", received),
+                ],
+            )
+            .unwrap();
+        }
+        let rounds = latest_rounds(&data_dir, |_| false).unwrap();
+        assert_eq!(6, rounds.len());
+        assert_eq!(row(5, "dns:a", 2), rounds[0].rows[1]);
+        assert_eq!(
+            Some(Outage {
+                from: row(2, "", 0).date,
+                to: row(5, "", 0).date
+            }),
+            ended_outage(&rounds)
+        );
+        assert_eq!(None, ended_outage(&rounds[1..]));
+        assert_eq!(Some((75.0, 100.0)), packet_loss(&rounds, 2));
+        assert_eq!(Some((62.5, 12.5)), packet_loss(&rounds[3..], 2));
+        assert_eq!(None, packet_loss(&rounds, 6));
+        let latest = latest_rounds(&data_dir, |rounds| rounds.len() == 2).unwrap();
+        assert_eq!(2, latest.len());
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+}
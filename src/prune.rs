@@ -0,0 +1,359 @@
+use chrono::{DateTime, Datelike, Months, NaiveDate, NaiveDateTime, TimeDelta, Utc};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::archive::{tar_gz, Entry};
+use crate::config::{Prune, Retention};
+use crate::error::Error;
+use crate::storage::{data_dir, write_atomically};
+
+/// Directory of the monthly bundles, inside the data directory.
+pub const ARCHIVE_DIR: &str = "archive";
+
+/// A change to the data directory made by `prune`.
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    /// Moves raw results into a new `.tar.gz` bundle.
+    Archive {
+        bundle: PathBuf,
+        files: Vec<PathBuf>,
+    },
+    Delete(PathBuf),
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Archive { bundle, files } => write!(
+                f,
+                "Archive {} raw result(s) into {}",
+                files.len(),
+                bundle.display()
+            ),
+            Action::Delete(path) => write!(f, "Delete {}", path.display()),
+        }
+    }
+}
+
+/// Archives and deletes old raw results as set by `prune`, printing what is
+/// done, or only what would be done in a dry run.
+pub fn prune(prune: Prune) -> Result<(), Error> {
+    let data_dir = data_dir()?;
+    let actions = plan(&data_dir, &prune.retention, Utc::now())?;
+    if actions.is_empty() {
+        println!("Nothing to prune.");
+    }
+    for action in actions.iter() {
+        if prune.dry_run {
+            println!("[dry run] {}", action);
+        } else {
+            apply(action)?;
+            println!("{}", action);
+        }
+    }
+    Ok(())
+}
+
+/// Applies the retention after a run, see `plan`.
+pub fn apply_retention(data_dir: &Path, retention: &Retention) -> Result<(), Error> {
+    for action in plan(data_dir, retention, Utc::now())? {
+        apply(&action)?;
+        info!("{}.", action);
+    }
+    Ok(())
+}
+
+/// What has to be done for the data directory to follow the retention:
+///
+/// * raw results older than `keep_raw_days` are deleted or, when archiving,
+///   moved to a bundle of their month once every result of that month is old
+///   enough, so each month ends up in a single bundle;
+/// * bundles are deleted `delete_archives_after_months` after the end of
+///   their month, and raw results that would go to a deleted bundle are
+///   deleted instead.
+pub fn plan(
+    data_dir: &Path,
+    retention: &Retention,
+    now: DateTime<Utc>,
+) -> Result<Vec<Action>, Error> {
+    let mut actions = vec![];
+    let archive_dir = data_dir.join(ARCHIVE_DIR);
+    let expired = |month: NaiveDate| {
+        retention
+            .delete_archives_after_months
+            .is_some_and(|months| {
+                next_month(month)
+                    .checked_add_months(Months::new(months))
+                    .is_some_and(|end| end <= now.date_naive())
+            })
+    };
+    // a cutoff before the earliest date there is leaves nothing old enough
+    let cutoff = retention
+        .keep_raw_days
+        .and_then(|days| now.checked_sub_signed(TimeDelta::days(days as i64)));
+    if let Some(cutoff) = cutoff {
+        let mut months: BTreeMap<NaiveDate, Vec<PathBuf>> = BTreeMap::new();
+        for (path, date) in raw_results(data_dir)? {
+            if date >= cutoff {
+                continue;
+            }
+            if retention.archive {
+                months.entry(month_of(date)).or_default().push(path);
+            } else {
+                actions.push(Action::Delete(path));
+            }
+        }
+        for (month, mut files) in months {
+            files.sort();
+            if expired(month) {
+                actions.extend(files.into_iter().map(Action::Delete));
+            } else if next_month(month) <= cutoff.date_naive() {
+                actions.push(Action::Archive {
+                    bundle: bundle_path(&archive_dir, month),
+                    files,
+                });
+            }
+        }
+    }
+    if retention.delete_archives_after_months.is_some() {
+        for (path, month) in bundles(&archive_dir)? {
+            if expired(month) {
+                actions.push(Action::Delete(path));
+            }
+        }
+    }
+    Ok(actions)
+}
+
+/// Carries out the action. Bundles are written to a temporary file first, so
+/// raw results are only deleted once their bundle is complete.
+pub fn apply(action: &Action) -> Result<(), Error> {
+    match action {
+        Action::Archive { bundle, files } => {
+            let mut entries = vec![];
+            for file in files {
+                let contents = fs::read(file)
+                    .map_err(|err| Error::storage("Error when reading raw result", err))?;
+                let mtime = fs::metadata(file)
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+                    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                    .map_or(0, |since_epoch| since_epoch.as_secs());
+                entries.push(Entry {
+                    name: file_name(file).to_owned(),
+                    mtime,
+                    contents,
+                });
+            }
+            if let Some(archive_dir) = bundle.parent() {
+                fs::create_dir_all(archive_dir)
+                    .map_err(|err| Error::storage("Error when creating archive directory", err))?;
+            }
+            tar_gz(&entries)
+                .and_then(|archive| write_atomically(bundle, &archive))
+                .map_err(|err| Error::storage("Error when writing archive", err))?;
+            for file in files {
+                fs::remove_file(file)
+                    .map_err(|err| Error::storage("Error when deleting raw result", err))?;
+            }
+        }
+        Action::Delete(path) => {
+            fs::remove_file(path).map_err(|err| Error::storage("Error when deleting file", err))?
+        }
+    }
+    Ok(())
+}
+
+/// The raw results in the data directory, named like `20210131235959.json`
+/// or `20210131235959-1.json`, with their dates.
+fn raw_results(data_dir: &Path) -> Result<Vec<(PathBuf, DateTime<Utc>)>, Error> {
+    let mut results = vec![];
+    for path in list_dir(data_dir)? {
+        let date = file_name(&path)
+            .strip_suffix(".json")
+            .map(|stem| stem.split('-').next().unwrap_or_default())
+            .filter(|digits| digits.len() == 14)
+            .and_then(|digits| NaiveDateTime::parse_from_str(digits, "%Y%m%d%H%M%S").ok());
+        if let Some(date) = date {
+            results.push((path, date.and_utc()));
+        }
+    }
+    Ok(results)
+}
+
+/// The bundles in the archive directory, named like `2021-01.tar.gz` or
+/// `2021-01-2.tar.gz`, with their months.
+fn bundles(archive_dir: &Path) -> Result<Vec<(PathBuf, NaiveDate)>, Error> {
+    let mut bundles = vec![];
+    for path in list_dir(archive_dir)? {
+        let month = file_name(&path)
+            .strip_suffix(".tar.gz")
+            .and_then(|stem| stem.get(..7))
+            .and_then(|month| NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").ok());
+        if let Some(month) = month {
+            bundles.push((path, month));
+        }
+    }
+    bundles.sort();
+    Ok(bundles)
+}
+
+fn list_dir(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    fs::read_dir(dir)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|err| Error::storage("Error when listing data directory", err))
+}
+
+/// A bundle for the month that does not exist yet.
+fn bundle_path(archive_dir: &Path, month: NaiveDate) -> PathBuf {
+    let name = month.format("%Y-%m").to_string();
+    let mut path = archive_dir.join(format!("{}.tar.gz", name));
+    let mut suffix = 2;
+    while path.exists() {
+        path = archive_dir.join(format!("{}-{}.tar.gz", name, suffix));
+        suffix += 1;
+    }
+    path
+}
+
+fn month_of(date: DateTime<Utc>) -> NaiveDate {
+    date.date_naive().with_day(1).unwrap()
+}
+
+fn next_month(month: NaiveDate) -> NaiveDate {
+    month.checked_add_months(Months::new(1)).unwrap_or(month)
+}
+
+fn file_name(path: &Path) -> &str {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use chrono::TimeZone;
+    use pretty_assertions::assert_eq;
+
+    fn create_data_dir(name: &str) -> PathBuf {
+        let data_dir = test_support::create_data_dir(&format!("prune-{}", name));
+        fs::create_dir(data_dir.join(ARCHIVE_DIR)).unwrap();
+        for name in [
+            "20201215000000.json",
+            "20210101000000.json",
+            "20210131235959.json",
+            "20210131235959-1.json",
+            "20210215000000.json",
+            "20210310000000.json",
+            "speed.csv",
+        ] {
+            fs::write(data_dir.join(name), format!("{{\"file\":\"{}\"}}", name)).unwrap();
+        }
+        fs::write(data_dir.join(ARCHIVE_DIR).join("2020-06.tar.gz"), "").unwrap();
+        fs::write(data_dir.join(ARCHIVE_DIR).join("2020-12.tar.gz"), "").unwrap();
+        data_dir
+    }
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2021, 3, 20, 0, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn archives_whole_months_and_deletes_old_bundles() {
+        let data_dir = create_data_dir("archive");
+        let archive_dir = data_dir.join(ARCHIVE_DIR);
+        let retention = Retention {
+            keep_raw_days: Some(30),
+            archive: true,
+            delete_archives_after_months: Some(6),
+        };
+        let actions = plan(&data_dir, &retention, now()).unwrap();
+        assert_eq!(
+            vec![
+                Action::Archive {
+                    bundle: archive_dir.join("2020-12-2.tar.gz"),
+                    files: vec![data_dir.join("20201215000000.json")],
+                },
+                Action::Archive {
+                    bundle: archive_dir.join("2021-01.tar.gz"),
+                    files: vec![
+                        data_dir.join("20210101000000.json"),
+                        data_dir.join("20210131235959-1.json"),
+                        data_dir.join("20210131235959.json"),
+                    ],
+                },
+                Action::Delete(archive_dir.join("2020-06.tar.gz")),
+            ],
+            actions,
+            "February still has results from the last 30 days, March is recent"
+        );
+        for action in actions.iter() {
+            apply(action).unwrap();
+        }
+        assert!(!data_dir.join("20210101000000.json").exists());
+        assert!(data_dir.join("20210215000000.json").exists());
+        assert!(data_dir.join("speed.csv").exists());
+        assert!(!archive_dir.join("2020-06.tar.gz").exists());
+        let bundle = fs::read(archive_dir.join("2021-01.tar.gz")).unwrap();
+        assert_eq!([0x1f, 0x8b], bundle[..2]);
+        assert_eq!(
+            Vec::<Action>::new(),
+            plan(&data_dir, &retention, now()).unwrap()
+        );
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn deletes_raw_results_without_archiving() {
+        let data_dir = create_data_dir("delete");
+        let retention = Retention {
+            keep_raw_days: Some(30),
+            archive: false,
+            delete_archives_after_months: None,
+        };
+        let mut deleted: Vec<String> = plan(&data_dir, &retention, now())
+            .unwrap()
+            .iter()
+            .map(|action| match action {
+                Action::Delete(path) => file_name(path).to_owned(),
+                _ => panic!("Should only delete"),
+            })
+            .collect();
+        deleted.sort();
+        assert_eq!(
+            vec![
+                "20201215000000.json",
+                "20210101000000.json",
+                "20210131235959-1.json",
+                "20210131235959.json",
+                "20210215000000.json",
+            ],
+            deleted
+        );
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_everything_when_the_cutoff_is_before_the_earliest_date() {
+        let data_dir = create_data_dir("forever");
+        let retention = Retention {
+            keep_raw_days: Some(u32::MAX),
+            archive: false,
+            delete_archives_after_months: None,
+        };
+        assert_eq!(0, plan(&data_dir, &retention, now()).unwrap().len());
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+}
@@ -0,0 +1,155 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::fmt;
+
+use crate::diagnosis::Fault;
+use crate::error::Error;
+use crate::latency::LoadedLatency;
+
+/// Parses the JSON written by Ookla's `speedtest --format=json`.
+pub fn convert_json(json: String) -> Result<SpeedResult, Error> {
+    let result: serde_json::Result<RawSpeedResult> = serde_json::from_str(&json);
+    match result {
+        Ok(raw_result) => Ok(SpeedResult {
+            client_ip: raw_result.interface.external_ip,
+            client_isp: raw_result.isp,
+            interface: raw_result.interface.name,
+            date: raw_result
+                .timestamp
+                .as_deref()
+                .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok())
+                .map_or_else(Utc::now, |date| date.with_timezone(&Utc)),
+            download: raw_result.download.bandwidth,
+            upload: raw_result.upload.bandwidth,
+            ping: raw_result.ping.latency,
+            jitter: raw_result.ping.jitter,
+            packet_loss: raw_result.packet_loss,
+            server_country: raw_result.server.country,
+            server_host: raw_result.server.host,
+            server_id: raw_result.server.id,
+            server_location: raw_result.server.location,
+            samples: 1,
+            download_spread: 0.0,
+            upload_spread: 0.0,
+            ping_spread: 0.0,
+            loaded_latency: None,
+            raw_file: None,
+            jsonresult: json,
+        }),
+        Err(err) => Err(Error::Parse {
+            message: format!("Could not parse result. Json:\n{}", json),
+            source: Some(err.into()),
+        }),
+    }
+}
+
+/// Why a measurement failed, as stored in the `error_category` column of the summary file.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    BinaryMissing,
+    Timeout,
+    ParseError,
+    NonZeroExit,
+}
+
+impl fmt::Display for ErrorCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            ErrorCategory::BinaryMissing => "binary_missing",
+            ErrorCategory::Timeout => "timeout",
+            ErrorCategory::ParseError => "parse_error",
+            ErrorCategory::NonZeroExit => "non_zero_exit",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+/// A measurement that failed after all of its attempts.
+pub struct Failure {
+    pub date: DateTime<Utc>,
+    pub category: ErrorCategory,
+    /// The errors of every attempt.
+    pub details: String,
+    /// The error of the last attempt.
+    pub error: Error,
+    /// Where the network failed, when it was diagnosed.
+    pub fault: Option<Fault>,
+}
+
+#[derive(Derivative)]
+#[derivative(Debug)]
+/// A measurement, as taken by Ookla's CLI. Bandwidths are in bytes per second.
+pub struct SpeedResult {
+    pub date: DateTime<Utc>,
+    pub ping: f64,
+    /// Jitter of the ping, in milliseconds.
+    pub jitter: f64,
+    /// Percentage of lost packets, when Ookla's CLI could measure it.
+    pub packet_loss: Option<f64>,
+    pub download: f64,
+    pub upload: f64,
+    pub client_ip: String,
+    pub client_isp: String,
+    pub server_host: String,
+    pub server_location: String,
+    pub server_country: String,
+    pub server_id: u32,
+    pub interface: String,
+    pub samples: usize,
+    pub download_spread: f64,
+    pub upload_spread: f64,
+    pub ping_spread: f64,
+    /// Latency to the target of `--latency-target` while idle and under load.
+    pub loaded_latency: Option<LoadedLatency>,
+    /// Name of the file the raw JSON was written to, see `storage::write_to_result_file`.
+    pub raw_file: Option<String>,
+    #[derivative(Debug = "ignore")]
+    pub jsonresult: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawSpeedResult {
+    /// When the measurement started.
+    timestamp: Option<String>,
+    ping: RawPing,
+    download: RawBandwidth,
+    upload: RawBandwidth,
+    interface: RawInterface,
+    isp: String,
+    server: RawServer,
+    packet_loss: Option<f64>,
+}
+#[derive(Deserialize)]
+struct RawPing {
+    latency: f64,
+    #[serde(default)]
+    jitter: f64,
+}
+#[derive(Deserialize)]
+struct RawBandwidth {
+    bandwidth: f64,
+}
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawInterface {
+    external_ip: String,
+    name: String,
+}
+#[derive(Deserialize)]
+struct RawServer {
+    host: String,
+    location: String,
+    country: String,
+    id: u32,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RawServerList {
+    pub(crate) servers: Vec<RawServerListItem>,
+}
+#[derive(Deserialize)]
+pub(crate) struct RawServerListItem {
+    pub(crate) id: u32,
+}
@@ -0,0 +1,405 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::aggregate::{stats, Period, Stats};
+use crate::error::Error;
+use crate::output::round;
+use crate::storage::{self, read_results, read_results_since, ResultCsv};
+use crate::units::{Unit, STORED_UNIT};
+
+/// Directory of the rollup files, inside the data directory.
+pub const ROLLUP_DIR: &str = "rollups";
+
+/// Lock file held while rollup files are updated, inside `ROLLUP_DIR`.
+const LOCK_FILE: &str = ".lock";
+
+/// Ranges at least this long are charted from the daily rollups, a point per
+/// day, instead of from every row of the summary file.
+pub const LONG_RANGE: TimeDelta = TimeDelta::days(180);
+
+/// Periods rollups are kept for.
+pub const PERIODS: [Period; 3] = [Period::Hour, Period::Day, Period::Month];
+
+/// Statistics of the measurements taken in a period, of every interface.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Rollup {
+    #[serde(serialize_with = "serialize_date")]
+    pub start: DateTime<Utc>,
+    pub measurements: usize,
+    pub failed: usize,
+    pub download: Option<Stats>,
+    pub upload: Option<Stats>,
+    pub ping: Option<Stats>,
+}
+
+impl Rollup {
+    /// The rollup with its bandwidths converted from `from` to `to`.
+    pub fn convert(self, from: Unit, to: Unit) -> Rollup {
+        Rollup {
+            download: self.download.map(|stats| stats.convert(from, to)),
+            upload: self.upload.map(|stats| stats.convert(from, to)),
+            ..self
+        }
+    }
+}
+
+fn serialize_date<S: serde::Serializer>(
+    date: &DateTime<Utc>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&storage::format_date(date))
+}
+
+/// Groups the rows by period, with bandwidths in `unit`.
+pub fn compute(rows: &[&ResultCsv], period: Period, unit: Unit) -> Vec<Rollup> {
+    let mut periods: BTreeMap<DateTime<Utc>, Vec<&ResultCsv>> = BTreeMap::new();
+    for row in rows {
+        periods.entry(period.start(row.date)).or_default().push(row);
+    }
+    periods
+        .into_iter()
+        .map(|(start, rows)| summarize(start, &rows, unit))
+        .collect()
+}
+
+/// A rollup of a single row, with bandwidths in `unit`, so that rows can be
+/// charted like rollups.
+pub fn from_row(row: &ResultCsv, unit: Unit) -> Rollup {
+    summarize(row.date, &[row], unit)
+}
+
+fn summarize(start: DateTime<Utc>, rows: &[&ResultCsv], unit: Unit) -> Rollup {
+    let ok: Vec<&ResultCsv> = rows.iter().copied().filter(|row| !row.failed()).collect();
+    let values = |value: &dyn Fn(&ResultCsv) -> Option<f64>| {
+        stats(&ok.iter().filter_map(|row| value(row)).collect::<Vec<_>>())
+    };
+    Rollup {
+        start,
+        measurements: rows.len(),
+        failed: rows.len() - ok.len(),
+        download: values(&|row| row.download_in(unit)),
+        upload: values(&|row| row.upload_in(unit)),
+        ping: values(&|row| row.ping),
+    }
+}
+
+/// The rollups of the period, with bandwidths in `STORED_UNIT`. They are
+/// computed from the summary file if the rollup file was not written yet.
+pub fn read(data_dir: &Path, period: Period) -> Result<Vec<Rollup>, Error> {
+    let path = rollup_path(data_dir, period);
+    if !path.exists() {
+        let rows = read_results(data_dir)?;
+        return Ok(compute(
+            &rows.iter().collect::<Vec<_>>(),
+            period,
+            STORED_UNIT,
+        ));
+    }
+    csv::Reader::from_path(&path)
+        .map_err(|err| Error::storage("Error when opening rollup file", err))?
+        .deserialize::<RollupRecord>()
+        .map(|record| {
+            record
+                .map_err(|err| Error::storage("Error deserializing rollup", err))?
+                .into_rollup()
+        })
+        .collect()
+}
+
+/// The daily rollups of the days starting from `from` on, or all of them,
+/// with bandwidths in `unit`.
+pub fn read_daily(
+    data_dir: &Path,
+    from: Option<DateTime<Utc>>,
+    unit: Unit,
+) -> Result<Vec<Rollup>, Error> {
+    Ok(read(data_dir, Period::Day)?
+        .into_iter()
+        .filter(|rollup| from.is_none_or(|from| rollup.start >= from))
+        .map(|rollup| rollup.convert(STORED_UNIT, unit))
+        .collect())
+}
+
+/// Recomputes the rollups of the periods the dates are in, after measurements
+/// taken at those dates were appended to the summary file. Only the rows of
+/// those periods are read, from the end of the file, see `read_results_since`.
+/// Rollup files that do not exist yet are written from the whole summary file.
+pub fn update(data_dir: &Path, dates: &[DateTime<Utc>]) -> Result<(), Error> {
+    let _lock = lock(data_dir)?;
+    let Some(earliest) = dates.iter().min() else {
+        return Ok(());
+    };
+    if PERIODS
+        .iter()
+        .any(|period| !rollup_path(data_dir, *period).exists())
+    {
+        return write_all(data_dir);
+    }
+    // months are the longest periods
+    let rows = read_results_since(data_dir, Period::Month.start(*earliest))?;
+    for period in PERIODS {
+        let path = rollup_path(data_dir, period);
+        let starts: BTreeSet<DateTime<Utc>> =
+            dates.iter().map(|date| period.start(*date)).collect();
+        let affected: Vec<&ResultCsv> = rows
+            .iter()
+            .filter(|row| starts.contains(&period.start(row.date)))
+            .collect();
+        let mut rollups = read(data_dir, period)?;
+        rollups.retain(|rollup| !starts.contains(&rollup.start));
+        rollups.extend(compute(&affected, period, STORED_UNIT));
+        rollups.sort_by_key(|rollup| rollup.start);
+        write(&path, rollups)?;
+    }
+    Ok(())
+}
+
+/// Writes every rollup file again from the summary file.
+pub fn rebuild(data_dir: &Path) -> Result<(), Error> {
+    let _lock = lock(data_dir)?;
+    write_all(data_dir)
+}
+
+/// Writes every rollup file from the summary file, while locked.
+fn write_all(data_dir: &Path) -> Result<(), Error> {
+    let rows = read_results(data_dir)?;
+    for period in PERIODS {
+        write(
+            &rollup_path(data_dir, period),
+            compute(&rows.iter().collect::<Vec<_>>(), period, STORED_UNIT),
+        )?;
+    }
+    Ok(())
+}
+
+fn rollup_path(data_dir: &Path, period: Period) -> PathBuf {
+    let name = match period {
+        Period::Hour => "hourly.csv",
+        Period::Day => "daily.csv",
+        Period::Month => "monthly.csv",
+    };
+    data_dir.join(ROLLUP_DIR).join(name)
+}
+
+/// Locks the rollup files, so concurrent runs do not lose each other's updates.
+fn lock(data_dir: &Path) -> Result<fs::File, Error> {
+    let dir = data_dir.join(ROLLUP_DIR);
+    fs::create_dir_all(&dir)
+        .map_err(|err| Error::storage("Error when creating rollup directory", err))?;
+    storage::lock(&dir.join(LOCK_FILE))
+}
+
+/// Writes the rollups to a temporary file that then replaces the rollup file,
+/// so readers never see it half written.
+fn write(path: &Path, rollups: Vec<Rollup>) -> Result<(), Error> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for rollup in rollups {
+        writer
+            .serialize(RollupRecord::from_rollup(rollup))
+            .map_err(|err| Error::storage("Error when writing rollup file", err))?;
+    }
+    let contents = writer
+        .into_inner()
+        .map_err(|err| Error::storage("Error when writing rollup file", err))?;
+    storage::write_atomically(path, &contents)
+        .map_err(|err| Error::storage("Error when writing rollup file", err))
+}
+
+/// A row of a rollup file, with the statistics of each field in its own columns.
+#[derive(Debug, Serialize, Deserialize)]
+struct RollupRecord {
+    start: String,
+    measurements: usize,
+    failed: usize,
+    download_count: Option<usize>,
+    download_min: Option<f64>,
+    download_max: Option<f64>,
+    download_mean: Option<f64>,
+    download_median: Option<f64>,
+    upload_count: Option<usize>,
+    upload_min: Option<f64>,
+    upload_max: Option<f64>,
+    upload_mean: Option<f64>,
+    upload_median: Option<f64>,
+    ping_count: Option<usize>,
+    ping_min: Option<f64>,
+    ping_max: Option<f64>,
+    ping_mean: Option<f64>,
+    ping_median: Option<f64>,
+}
+
+type StatsColumns = (
+    Option<usize>,
+    Option<f64>,
+    Option<f64>,
+    Option<f64>,
+    Option<f64>,
+);
+
+fn to_columns(stats: Option<Stats>) -> StatsColumns {
+    match stats {
+        Some(stats) => (
+            Some(stats.count),
+            Some(round(stats.min, 3)),
+            Some(round(stats.max, 3)),
+            Some(round(stats.mean, 3)),
+            Some(round(stats.median, 3)),
+        ),
+        None => (None, None, None, None, None),
+    }
+}
+
+fn from_columns((count, min, max, mean, median): StatsColumns) -> Option<Stats> {
+    Some(Stats {
+        count: count?,
+        min: min?,
+        max: max?,
+        mean: mean?,
+        median: median?,
+    })
+}
+
+impl RollupRecord {
+    fn from_rollup(rollup: Rollup) -> RollupRecord {
+        let download = to_columns(rollup.download);
+        let upload = to_columns(rollup.upload);
+        let ping = to_columns(rollup.ping);
+        RollupRecord {
+            start: storage::format_date(&rollup.start),
+            measurements: rollup.measurements,
+            failed: rollup.failed,
+            download_count: download.0,
+            download_min: download.1,
+            download_max: download.2,
+            download_mean: download.3,
+            download_median: download.4,
+            upload_count: upload.0,
+            upload_min: upload.1,
+            upload_max: upload.2,
+            upload_mean: upload.3,
+            upload_median: upload.4,
+            ping_count: ping.0,
+            ping_min: ping.1,
+            ping_max: ping.2,
+            ping_mean: ping.3,
+            ping_median: ping.4,
+        }
+    }
+
+    fn into_rollup(self) -> Result<Rollup, Error> {
+        let start = DateTime::parse_from_rfc3339(&self.start)
+            .map_err(|err| Error::storage("Error deserializing rollup", err))?;
+        Ok(Rollup {
+            start: start.with_timezone(&Utc),
+            measurements: self.measurements,
+            failed: self.failed,
+            download: from_columns((
+                self.download_count,
+                self.download_min,
+                self.download_max,
+                self.download_mean,
+                self.download_median,
+            )),
+            upload: from_columns((
+                self.upload_count,
+                self.upload_min,
+                self.upload_max,
+                self.upload_mean,
+                self.upload_median,
+            )),
+            ping: from_columns((
+                self.ping_count,
+                self.ping_min,
+                self.ping_max,
+                self.ping_mean,
+                self.ping_median,
+            )),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::create_data_dir_with_summary;
+    use chrono::TimeZone;
+    use pretty_assertions::assert_eq;
+
+    const SUMMARY: &str = "\
+date,ping,speeds_download,speeds_upload,interface,status,speeds_unit
+2021/01/01 10:00:00,5,100,50,eth0,ok,mbps
+2021/01/01 10:30:00,7,80,40,wlan0,ok,mbps
+2021/01/01 22:00:00,,,,eth0,failed,
+2021/01/02 10:00:00,6,90,40,eth0,ok,mbps
+";
+
+    fn date(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2021, 1, day, hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn writes_rollups_of_each_period() {
+        let data_dir = create_data_dir_with_summary("rollup-write", SUMMARY);
+        update(&data_dir, &[date(2, 10)]).unwrap();
+        assert_eq!(
+            "start,measurements,failed,download_count,download_min,download_max,download_mean,download_median,upload_count,upload_min,upload_max,upload_mean,upload_median,ping_count,ping_min,ping_max,ping_mean,ping_median\n\
+             2021-01-01T00:00:00Z,3,1,2,80.0,100.0,90.0,90.0,2,40.0,50.0,45.0,45.0,2,5.0,7.0,6.0,6.0\n\
+             2021-01-02T00:00:00Z,1,0,1,90.0,90.0,90.0,90.0,1,40.0,40.0,40.0,40.0,1,6.0,6.0,6.0,6.0\n",
+            fs::read_to_string(data_dir.join(ROLLUP_DIR).join("daily.csv")).unwrap()
+        );
+        let hourly = read(&data_dir, Period::Hour).unwrap();
+        assert_eq!(3, hourly.len());
+        assert_eq!(date(1, 10), hourly[0].start);
+        assert_eq!(2, hourly[0].measurements);
+        let monthly = read(&data_dir, Period::Month).unwrap();
+        assert_eq!(4, monthly[0].measurements);
+        assert_eq!(
+            Some(90.0),
+            monthly[0].download.as_ref().map(|stats| stats.median)
+        );
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn updates_only_the_periods_of_new_measurements() {
+        let data_dir = create_data_dir_with_summary("rollup-update", SUMMARY);
+        rebuild(&data_dir).unwrap();
+        let daily = data_dir.join(ROLLUP_DIR).join("daily.csv");
+        // an edited rollup of another day shows it is not recomputed
+        let edited = fs::read_to_string(&daily)
+            .unwrap()
+            .replace("2021-01-01T00:00:00Z,3,1", "2021-01-01T00:00:00Z,33,1");
+        fs::write(&daily, edited).unwrap();
+        let mut summary = fs::read_to_string(data_dir.join("speed.csv")).unwrap();
+        summary.push_str("2021/01/02 11:00:00,8,70,30,eth0,ok,mbps\n");
+        fs::write(data_dir.join("speed.csv"), summary).unwrap();
+        update(&data_dir, &[date(2, 11)]).unwrap();
+        let rollups = read(&data_dir, Period::Day).unwrap();
+        assert_eq!(33, rollups[0].measurements);
+        assert_eq!(2, rollups[1].measurements);
+        assert_eq!(
+            Some(Stats {
+                count: 2,
+                min: 70.0,
+                max: 90.0,
+                mean: 80.0,
+                median: 80.0
+            }),
+            rollups[1].download
+        );
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn computes_rollups_without_files() {
+        let data_dir = create_data_dir_with_summary("rollup-read", SUMMARY);
+        let rollups = read(&data_dir, Period::Month).unwrap();
+        assert_eq!(1, rollups.len());
+        assert_eq!(1, rollups[0].failed);
+        assert!(!data_dir.join(ROLLUP_DIR).exists());
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+}
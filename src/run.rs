@@ -1,102 +1,415 @@
-use crate::args::EmailOptions;
-use crate::args::Run;
+use crate::aggregate::aggregate_results;
+use crate::config::{Aggregation, EmailOptions, Run};
+use crate::diagnosis::Diagnosis;
+use crate::error::Error;
+use crate::influx::{self, Point};
+use crate::latency::{LoadedLatency, Monitor};
 use crate::mail;
+use crate::mqtt;
+use crate::output::format_results;
+use crate::probe::Uplink;
+use crate::prune;
+use crate::results::{
+    convert_json, ErrorCategory, Failure, RawServerList, RawServerListItem, SpeedResult,
+};
+use crate::rollup;
+use crate::storage::{
+    append_to_summary_file, data_dir, failure_record, format_date, result_record,
+    write_to_result_file,
+};
 use chrono::{DateTime, Utc};
-use serde::Deserialize;
 use std::env;
-use std::fs::{self, File, OpenOptions};
 use std::io::prelude::*;
-use std::path::PathBuf;
-use std::process::Stdio;
-
-pub fn run(run: Run) -> Result<(), Option<String>> {
-    let json_result = run_speedtest(run.simulate, run.email_options)?;
-    let result = convert_json(json_result)?;
-    write_to_result_file(&result)?;
-    append_to_summary_file(&result)?;
-    if run.show_results {
-        println!("{}", &result.download);
-        println!("{}", &result.upload);
-        println!("{}", &result.ping);
-    }
-    printlnv!("Got results:\n{:?}", &result);
-    Ok(())
+use std::io::BufReader;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub(crate) const SIMULATED_RESULT: &str = r#"{"type":"result","timestamp":"2021-01-03T12:10:00Z","ping":{"jitter":0.28499999999999998,"latency":5.7279999999999998},"download":{"bandwidth":20309419,"bytes":176063552,"elapsed":8815},"upload":{"bandwidth":13206885,"bytes":195610380,"elapsed":15015},"packetLoss":0,"isp":"Some ISP","interface":{"internalIp":"192.168.1.2","name":"eth0","macAddr":"99:99:99:99:99:99","isVpn":false,"externalIp":"84.6.0.1"},"server":{"id":99999,"name":"Some Server","location":"São Paulo","country":"Brazil","host":"someserver.nonexistentxyz.com","port":10000,"ip":"15.22.77.1"},"result":{"id":"babad438-ac4b-47db-bc28-2de7e257bd28","url":"https://www.fakespeedtest.net/result/c/babad438-ac4b-47db-bc28-2de7e257bd28"}}"#;
+const SIMULATED_SERVER_LIST: &str = r#"{"type":"serverList","timestamp":"2021-01-03T12:09:00Z","servers":[{"id":99999,"host":"someserver.nonexistentxyz.com","port":8080,"name":"Some Server","location":"São Paulo","country":"Brazil"},{"id":99998,"host":"otherserver.nonexistentxyz.com","port":8080,"name":"Other Server","location":"Rio de Janeiro","country":"Brazil"},{"id":99997,"host":"anotherserver.nonexistentxyz.com","port":8080,"name":"Another Server","location":"Belo Horizonte","country":"Brazil"}]}"#;
+
+/// The simulated result of a measurement taken at `date`, dated like a real one.
+fn simulated_result(date: &DateTime<Utc>) -> String {
+    let mut json: serde_json::Value =
+        serde_json::from_str(SIMULATED_RESULT).expect("the simulated result is valid JSON");
+    json["timestamp"] = format_date(date).into();
+    json.to_string()
 }
 
-fn write_to_result_file(result: &SpeedResult) -> Result<(), String> {
-    let cwd = env::current_dir()
-        .map_err(|err| format!("Error when finding current working directory: {}", err))?;
-    let data_dir = cwd.join("data");
-    if !data_dir.exists() {
-        std::fs::create_dir(&data_dir)
-            .map_err(|err| format!("Error when creating data directory: {}", err))?;
+/// Latency samples of a simulated measurement with a latency target.
+fn simulated_latency() -> LoadedLatency {
+    LoadedLatency {
+        idle: vec![5.1, 5.3, 5.2],
+        download: vec![48.0, 52.5, 61.2],
+        upload: vec![18.4, 22.1, 25.9],
     }
-    let file_name = format!("{}.json", result.date.format("%Y%m%d%H%M%S"));
-    let file_path = data_dir.join(file_name);
-    fs::write(file_path, result.jsonresult.as_bytes())
-        .map_err(|err| format!("Error when writing to file: {}", err))?;
-    Ok(())
 }
 
-fn append_to_summary_file(result: &SpeedResult) -> Result<(), String> {
-    let cwd = env::current_dir()
-        .map_err(|err| format!("Error when finding current working directory: {}", err))?;
-    let data_dir = cwd.join("data");
-    let file_path = data_dir.join("speed.csv");
-    let mut file = if file_path.exists() {
-        OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(file_path)
-            .map_err(|err| format!("Error when creating file: {}", err))?
-    } else {
-        let mut file =
-            File::create(&file_path).map_err(|err| format!("Error creating file: {}", err))?;
-        file.write_all("date,ping,speeds_download,speeds_upload,client_ip,client_isp,server_host,server_lat,server_lon,server_location,server_country,location_distance,server_ping,server_id\n".as_bytes())
-            .map_err(|err| format!("Error writing header to file: {}", err))?;
-        file
+/// Measures the bandwidth and stores the results, as configured by `run`.
+pub fn run(run: Run) -> Result<(), Error> {
+    let data_dir = data_dir()?;
+    let servers = match choose_servers(&run) {
+        Ok(servers) => servers,
+        Err(err) => {
+            let failure = notify_failure(&run, vec![("Listing servers".to_owned(), err)]);
+            append_to_summary_file(&data_dir, failure_record(&failure, &run.interface))?;
+            update_rollups(&data_dir, &[failure.date]);
+            return Err(failure.error);
+        }
     };
-    let line = format!(
-        r#"{},{},{:.2},{:.2},"{}","{}","{}",null,null,"{}","{}",null,null,{}{}"#,
-        result.date.format("%Y/%m/%d %H:%M:%S"),
-        result.ping,
-        result.download * 8.0 / 1024.0 / 1024.0,
-        result.upload * 8.0 / 1024.0 / 1024.0,
-        result.client_ip,
-        result.client_isp,
-        result.server_host,
-        result.server_location,
-        result.server_country,
-        result.server_id,
-        "\n"
+    let samples = servers.len();
+    let mut results = vec![];
+    let mut failures = vec![];
+    for (sample, server_id) in servers.into_iter().enumerate() {
+        match measure(&run, server_id) {
+            Ok(mut result) => {
+                if let Some(interface) = &run.interface {
+                    result.interface = interface.clone();
+                }
+                result.raw_file = Some(write_to_result_file(&data_dir, &result)?);
+                debug!("Got results:\n{:?}", &result);
+                results.push(result);
+            }
+            Err(failure) => {
+                if samples > 1 {
+                    error!(
+                        "Sample {} of {} failed. Error:\n{}",
+                        sample + 1,
+                        samples,
+                        failure.error.describe()
+                    );
+                }
+                failures.push(failure);
+            }
+        }
+    }
+    let summary = match run.aggregation {
+        Aggregation::Median if !results.is_empty() => vec![aggregate_results(results)],
+        _ => results,
+    };
+    for result in summary.iter() {
+        append_to_summary_file(&data_dir, result_record(result))?;
+    }
+    if let Some(format) = run.show_results {
+        if !summary.is_empty() {
+            println!(
+                "{}",
+                format_results(&summary, format, run.units, run.timezone)?
+            );
+        }
+    }
+    for failure in failures.iter() {
+        append_to_summary_file(&data_dir, failure_record(failure, &run.interface))?;
+    }
+    let dates: Vec<DateTime<Utc>> = summary
+        .iter()
+        .map(|result| result.date)
+        .chain(failures.iter().map(|failure| failure.date))
+        .collect();
+    update_rollups(&data_dir, &dates);
+    let published = publish(&run, &summary);
+    if let Some(retention) = &run.retention {
+        if let Err(err) = prune::apply_retention(&data_dir, retention) {
+            warn!("Could not prune raw results: {}", err.describe());
+        }
+    }
+    match failed_samples_error(failures, samples) {
+        Some(err) => Err(err),
+        None => published,
+    }
+}
+
+/// The error of a run whose samples failed: the error of the failed sample or,
+/// when more than one failed, how many did, caused by the last error. Each
+/// failed sample was logged as it failed.
+fn failed_samples_error(mut failures: Vec<Failure>, samples: usize) -> Option<Error> {
+    let failed = failures.len();
+    let last = failures.pop()?;
+    if failed == 1 {
+        return Some(last.error);
+    }
+    Some(Error::measurement(
+        last.category,
+        format!("{} of {} samples failed.", failed, samples),
+        Some(last.error.into()),
+    ))
+}
+
+/// Rollups are derived from the summary file, so failing to update them does
+/// not fail the run.
+fn update_rollups(data_dir: &Path, dates: &[DateTime<Utc>]) {
+    if let Err(err) = rollup::update(data_dir, dates) {
+        warn!("Could not update rollups: {}", err.describe());
+    }
+}
+
+/// Sends the results to InfluxDB and MQTT, when configured. Both are tried even
+/// if the first fails.
+fn publish(run: &Run, summary: &[SpeedResult]) -> Result<(), Error> {
+    let mut published = Ok(());
+    if summary.is_empty() {
+        return published;
+    }
+    if let Some(influx) = &run.influx {
+        let points: Vec<Point> = summary.iter().map(Point::from_result).collect();
+        published = influx::push(influx, &points);
+    }
+    if let (Some(mqtt), Some(latest)) = (&run.mqtt, summary.last()) {
+        if let Err(err) = mqtt::publish(mqtt, latest) {
+            if let Err(previous) = published {
+                error!("{}", previous.describe());
+            }
+            published = Err(err);
+        }
+    }
+    published
+}
+
+fn measure(run: &Run, server_id: Option<u32>) -> Result<SpeedResult, Failure> {
+    let mut attempts = vec![];
+    let mut tried_servers = vec![];
+    let mut server_id = server_id;
+    for attempt in 0..=run.retries {
+        if attempt > 0 {
+            let delay = backoff_delay(run.retry_delay, attempt);
+            warn!(
+                "Measurement failed, retrying in {} seconds (retry {} of {}).",
+                delay.as_secs(),
+                attempt,
+                run.retries
+            );
+            thread::sleep(delay);
+            if run.retry_other_server {
+                server_id = next_server(run, &tried_servers).or(server_id);
+            }
+        }
+        tried_servers.push(server_id);
+        match run_speedtest(run, server_id).and_then(|(json, loaded_latency)| {
+            let mut result = convert_json(json)?;
+            result.loaded_latency = loaded_latency;
+            Ok(result)
+        }) {
+            Ok(result) => return Ok(result),
+            Err(err) => {
+                warn!("Attempt {} failed. Error:\n{}", attempt + 1, err.describe());
+                attempts.push((
+                    format!(
+                        "Attempt {} (server: {})",
+                        attempt + 1,
+                        server_id.map_or("automatic".to_owned(), |id| id.to_string())
+                    ),
+                    err,
+                ));
+            }
+        }
+    }
+    Err(notify_failure(run, attempts))
+}
+
+/// Sends the failure e-mail, if configured, after all attempts of a measurement failed.
+/// Each attempt is described by a label and its error.
+fn notify_failure(run: &Run, mut attempts: Vec<(String, Error)>) -> Failure {
+    let details = attempts
+        .iter()
+        .map(|(label, err)| format!("{}:\n{}", label, err.describe().trim_end()))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let attempt_count = attempts.len();
+    let (_, last_error) = attempts
+        .pop()
+        .expect("There should be at least one failed attempt.");
+    let category = last_error.category().unwrap_or(ErrorCategory::NonZeroExit);
+    let date = Utc::now();
+    let diagnosis = diagnose(run, category);
+    let mut message = format!(
+        "Could not measure bandwidth after {} attempt(s) ({}).",
+        attempt_count, category
     );
-    file.write(line.as_bytes())
-        .map_err(|err| format!("Error when writing to file: {}", err))?;
-    Ok(())
+    if let Some(diagnosis) = &diagnosis {
+        warn!("Diagnosis of the failure:\n{}", diagnosis);
+        message += &format!(" Likely cause: {}.", diagnosis.fault.description());
+    }
+    let email_body = match &diagnosis {
+        Some(diagnosis) => format!("{}\n\n{}\n\n{}", message, diagnosis, details),
+        None => format!("{}\n\n{}", message, details),
+    };
+    if let Err(err) = send_email_on_error(run.simulate, &email_body, &run.email_options) {
+        message += &format!("\nAlso, could not send e-mail. Error:\n{}", err.describe());
+    };
+    Failure {
+        date,
+        category,
+        details,
+        error: Error::measurement(category, message, Some(last_error.into())),
+        fault: diagnosis.map(|diagnosis| diagnosis.fault),
+    }
 }
 
-fn run_speedtest(simulate: bool, email_options: Option<EmailOptions>) -> Result<String, String> {
-    let (speedtestbin, args) = find_speedtest_binary_and_args(simulate)?;
-    let child = std::process::Command::new(&speedtestbin)
-        .args(args)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|err| {
-            format!(
-                "Could not run {}.\nError:\n{}",
-                speedtestbin.to_str().unwrap_or("<filename not found>"),
-                err
-            )
+/// Checks the uplink of the measurement to find where it failed. A missing
+/// binary is not about the network, and a simulation does not use it.
+fn diagnose(run: &Run, category: ErrorCategory) -> Option<Diagnosis> {
+    if run.simulate || category == ErrorCategory::BinaryMissing {
+        return None;
+    }
+    info!("Diagnosing the network...");
+    Some(Diagnosis::run(&Uplink {
+        interface: run.interface.clone(),
+        source_ip: run
+            .source_ip
+            .as_deref()
+            .and_then(|source_ip| source_ip.parse().ok()),
+    }))
+}
+
+/// Delay before retry number `retry` (starting at 1), doubling on every retry.
+fn backoff_delay(initial_delay: Duration, retry: u8) -> Duration {
+    initial_delay.saturating_mul(2u32.saturating_pow(retry.saturating_sub(1) as u32))
+}
+
+/// Picks the nearest server that was not tried yet. An automatic server
+/// selection is assumed to have picked the nearest server.
+fn next_server(run: &Run, tried_servers: &[Option<u32>]) -> Option<u32> {
+    let servers = match list_servers(run) {
+        Ok(servers) => servers,
+        Err(err) => {
+            warn!(
+                "Could not list servers to retry on. Error:\n{}",
+                err.describe()
+            );
+            return None;
+        }
+    };
+    let nearest = servers.first().map(|server| server.id);
+    let tried_automatic = tried_servers.contains(&None);
+    servers.iter().map(|server| server.id).find(|id| {
+        let tried = tried_servers.contains(&Some(*id)) || (tried_automatic && Some(*id) == nearest);
+        !tried
+    })
+}
+
+fn choose_servers(run: &Run) -> Result<Vec<Option<u32>>, Error> {
+    let repeat = run.repeat as usize;
+    if !run.different_servers {
+        return Ok(vec![None; repeat]);
+    }
+    let servers = list_servers(run)?;
+    if servers.is_empty() {
+        return Err(Error::Parse {
+            message: "Speedtest did not return any servers.".to_owned(),
+            source: None,
+        });
+    }
+    if servers.len() < repeat {
+        warn!(
+            "Only {} servers available, some of them will be measured more than once.",
+            servers.len()
+        );
+    }
+    Ok(servers
+        .iter()
+        .cycle()
+        .take(repeat)
+        .map(|server| Some(server.id))
+        .collect())
+}
+
+fn list_servers(run: &Run) -> Result<Vec<RawServerListItem>, Error> {
+    let mut args = vec![
+        "--accept-license".to_owned(),
+        "--accept-gdpr".to_owned(),
+        "--servers".to_owned(),
+        "--format=json".to_owned(),
+    ];
+    args.extend(binding_args(run));
+    let output = execute_speedtest(run.simulate, args, SIMULATED_SERVER_LIST, run.timeout, None)?;
+    if !output.status.success() {
+        return Err(Error::ProcessFailed {
+            message: format!(
+                "Could not list speedtest servers. Errors:\n{}",
+                String::from_utf8_lossy(&output.stderr)
+            ),
+            source: None,
+        });
+    }
+    let server_list: RawServerList =
+        serde_json::from_slice(&output.stdout).map_err(|err| Error::Parse {
+            message: "Could not parse server list.".to_owned(),
+            source: Some(err.into()),
         })?;
-    let output = child
-        .wait_with_output()
-        .map_err(|e| format!("Could wait for speedtest execution.\nError:\n{}", e))?;
+    debug!(
+        "Servers available: {:?}",
+        server_list
+            .servers
+            .iter()
+            .map(|server| server.id)
+            .collect::<Vec<_>>()
+    );
+    Ok(server_list.servers)
+}
+
+/// Runs a measurement and returns Ookla's JSON result. With a latency target the
+/// latency is sampled in the phases Ookla's CLI reports with its progress lines.
+fn run_speedtest(
+    run: &Run,
+    server_id: Option<u32>,
+) -> Result<(String, Option<LoadedLatency>), Error> {
+    let monitor = match &run.latency_target {
+        Some(target) if !run.simulate => match Monitor::start(target) {
+            Ok(monitor) => Some(monitor),
+            Err(err) => {
+                warn!(
+                    "Could not measure the latency under load. Error:\n{}",
+                    err.describe()
+                );
+                None
+            }
+        },
+        _ => None,
+    };
+    let mut args = vec!["--accept-license".to_owned(), "--accept-gdpr".to_owned()];
+    if monitor.is_some() {
+        args.push("--format=jsonl".to_owned());
+        args.push("--progress=yes".to_owned());
+    } else {
+        args.push("--format=json".to_owned());
+        args.push("--progress=no".to_owned());
+    }
+    args.extend(binding_args(run));
+    if let Some(server_id) = server_id {
+        args.push(format!("--server-id={}", server_id));
+    }
+    let simulated_result = simulated_result(&Utc::now());
+    let output = execute_speedtest(
+        run.simulate,
+        args,
+        &simulated_result,
+        run.timeout,
+        monitor
+            .as_ref()
+            .map(|monitor| Box::new(monitor.observer()) as LineObserver),
+    );
+    let loaded_latency = match monitor {
+        Some(monitor) => Some(monitor.finish()),
+        None if run.simulate && run.latency_target.is_some() => Some(simulated_latency()),
+        None => None,
+    };
+    let output = output?;
     if output.status.success() {
-        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        let stdout_text = String::from_utf8_lossy(&output.stdout);
+        // with progress lines the result is the line of type `result`
+        let json = stdout_text
+            .lines()
+            .rfind(|line| {
+                serde_json::from_str::<serde_json::Value>(line)
+                    .is_ok_and(|json| json["type"] == "result")
+            })
+            .unwrap_or(&stdout_text);
+        Ok((json.to_owned(), loaded_latency))
     } else {
         let stdout_text = String::from_utf8_lossy(&output.stdout);
-        let mut error_message = if stdout_text.is_empty() {
+        let error_message = if stdout_text.is_empty() {
             format!(
                 "Speedtest executable exited with an error and no output. Errors:\n{}",
                 String::from_utf8_lossy(&output.stderr)
@@ -108,70 +421,178 @@ fn run_speedtest(simulate: bool, email_options: Option<EmailOptions>) -> Result<
                 String::from_utf8_lossy(&output.stderr)
             )
         };
-        if let Err(msg) = send_email_on_error(simulate, &error_message, email_options) {
-            error_message += &format!("\nAlso, could not send e-mail. Error:\n{}", &msg);
-        };
-        Err(error_message)
+        Err(Error::ProcessFailed {
+            message: error_message,
+            source: None,
+        })
     }
 }
 
-fn find_speedtest_binary_and_args<'a>(simulate: bool) -> Result<(PathBuf, Vec<&'a str>), String> {
-    let (bin, args) = if simulate {
-        (
-            "echo",
-            vec![
-                r#"{"type":"result","timestamp":"2021-01-03T12:10:00Z","ping":{"jitter":0.28499999999999998,"latency":5.7279999999999998},"download":{"bandwidth":20309419,"bytes":176063552,"elapsed":8815},"upload":{"bandwidth":13206885,"bytes":195610380,"elapsed":15015},"packetLoss":0,"isp":"Some ISP","interface":{"internalIp":"192.168.1.2","name":"eth0","macAddr":"99:99:99:99:99:99","isVpn":false,"externalIp":"84.6.0.1"},"server":{"id":99999,"name":"Some Server","location":"São Paulo","country":"Brazil","host":"someserver.nonexistentxyz.com","port":10000,"ip":"15.22.77.1"},"result":{"id":"babad438-ac4b-47db-bc28-2de7e257bd28","url":"https://www.fakespeedtest.net/result/c/babad438-ac4b-47db-bc28-2de7e257bd28"}}"#,
-            ],
-        )
+fn binding_args(run: &Run) -> Vec<String> {
+    let mut args = vec![];
+    if let Some(interface) = &run.interface {
+        args.push(format!("--interface={}", interface));
+    }
+    if let Some(source_ip) = &run.source_ip {
+        args.push(format!("--ip={}", source_ip));
+    }
+    args
+}
+
+/// Called with each line the speedtest binary writes to stdout, as it is written.
+type LineObserver = Box<dyn FnMut(&[u8]) + Send>;
+
+/// Runs the speedtest binary with `args`, or, when simulating, `echo`es `simulated_output` instead.
+fn execute_speedtest(
+    simulate: bool,
+    args: Vec<String>,
+    simulated_output: &str,
+    timeout: Option<Duration>,
+    observer: Option<LineObserver>,
+) -> Result<Output, Error> {
+    let (speedtestbin, args) = if simulate {
+        (find_binary("echo")?, vec![simulated_output.to_owned()])
     } else {
-        (
-            "speedtest",
-            vec![
-                "--accept-license",
-                "--accept-gdpr",
-                "--format=json",
-                "--progress=no",
-            ],
-        )
+        (find_binary("speedtest")?, args)
     };
-    match which::which(bin) {
-        Ok(speedtestbin) => Ok((speedtestbin, args)),
-        Err(_) => {
-            let cwd = env::current_dir()
-                .map_err(|err| format!("Error when finding current working directory: {}", err))?;
-            let speedtestbin = cwd.join(bin);
-            if speedtestbin.exists() {
-                Ok((speedtestbin, args))
+    debug!("Running {:?} with args {:?}.", speedtestbin, args);
+    let child = std::process::Command::new(&speedtestbin)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .map_err(|err| {
+            let message = format!(
+                "Could not run {}.",
+                speedtestbin.to_str().unwrap_or("<filename not found>")
+            );
+            if err.kind() == std::io::ErrorKind::NotFound {
+                Error::BinaryNotFound {
+                    message,
+                    source: Some(err.into()),
+                }
             } else {
-                Err("Could not find speedtest binary.".to_owned())
+                Error::ProcessFailed {
+                    message,
+                    source: Some(err.into()),
+                }
             }
-        }
-    }
+        })?;
+    wait_with_timeout(child, timeout, observer)
 }
 
-fn convert_json(json: String) -> Result<SpeedResult, String> {
-    let result: serde_json::Result<RawSpeedResult> = serde_json::from_str(&json);
-    match result {
-        Ok(raw_result) => Ok(SpeedResult {
-            client_ip: raw_result.interface.external_ip,
-            client_isp: raw_result.isp,
-            date: Utc::now(),
-            download: raw_result.download.bandwidth,
-            upload: raw_result.upload.bandwidth,
-            ping: raw_result.ping.latency,
-            server_country: raw_result.server.country,
-            server_host: raw_result.server.host,
-            server_id: raw_result.server.id,
-            server_location: raw_result.server.location,
-            jsonresult: json,
-        }),
-        Err(err) => {
-            let msg = format!(
-                "Could not parse result. Json:\n{}\nError:{}",
-                String::from_utf8_lossy(json.as_bytes()),
-                err
+/// How many of the last lines of stderr go in the error of a process that was killed.
+const STDERR_TAIL_LINES: usize = 5;
+
+/// Waits for the child to exit. If it does not exit in time the child and every
+/// process in its process group are killed, and the error has the last lines
+/// it wrote to stderr, to tell where it hung.
+fn wait_with_timeout(
+    mut child: Child,
+    timeout: Option<Duration>,
+    observer: Option<LineObserver>,
+) -> Result<Output, Error> {
+    let wait_error = |err: std::io::Error| Error::ProcessFailed {
+        message: "Could wait for speedtest execution.".to_owned(),
+        source: Some(err.into()),
+    };
+    let stdout_reader = read_to_end_in_background(child.stdout.take(), observer);
+    let stderr_reader = read_to_end_in_background(child.stderr.take(), None);
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(wait_error)? {
+            break status;
+        }
+        if let Some(timeout) = timeout.filter(|timeout| start.elapsed() >= *timeout) {
+            warn!(
+                "Speedtest did not finish in {} seconds, killing it.",
+                timeout.as_secs()
             );
-            Err(msg)
+            // the child is the leader of its own process group, see `process_group(0)`
+            unsafe {
+                libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+            }
+            child.wait().map_err(wait_error)?;
+            // the pipes close once the whole group is gone
+            let _ = stdout_reader.join();
+            let stderr = stderr_reader.join().unwrap_or_default();
+            let mut message = format!(
+                "Speedtest executable did not finish in {} seconds and was killed.",
+                timeout.as_secs()
+            );
+            let tail = last_lines(&stderr, STDERR_TAIL_LINES);
+            if !tail.is_empty() {
+                message += &format!(" Last output on stderr:\n{}", tail);
+            }
+            return Err(Error::Timeout {
+                message,
+                source: None,
+            });
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+    Ok(Output {
+        status,
+        stdout: stdout_reader.join().unwrap_or_default(),
+        stderr: stderr_reader.join().unwrap_or_default(),
+    })
+}
+
+/// The last `count` lines of the output that are not blank.
+fn last_lines(output: &[u8], count: usize) -> String {
+    let output = String::from_utf8_lossy(output);
+    let lines: Vec<&str> = output
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty())
+        .collect();
+    lines[lines.len().saturating_sub(count)..].join("\n")
+}
+
+/// Reads the pipe to its end, passing each line to the observer as it comes.
+fn read_to_end_in_background<R: Read + Send + 'static>(
+    pipe: Option<R>,
+    mut observer: Option<LineObserver>,
+) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = vec![];
+        if let Some(pipe) = pipe {
+            let mut pipe = BufReader::new(pipe);
+            loop {
+                let start = buffer.len();
+                match pipe.read_until(b'\n', &mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {
+                        if let Some(observer) = observer.as_mut() {
+                            observer(&buffer[start..]);
+                        }
+                    }
+                }
+            }
+        }
+        buffer
+    })
+}
+
+fn find_binary(bin: &str) -> Result<PathBuf, Error> {
+    match which::which(bin) {
+        Ok(speedtestbin) => Ok(speedtestbin),
+        Err(which_err) => {
+            let speedtestbin = env::current_dir().map(|cwd| cwd.join(bin)).map_err(|err| {
+                Error::BinaryNotFound {
+                    message: "Error when finding current working directory.".to_owned(),
+                    source: Some(err.into()),
+                }
+            })?;
+            if speedtestbin.exists() {
+                Ok(speedtestbin)
+            } else {
+                Err(Error::BinaryNotFound {
+                    message: format!("Could not find {} binary.", bin),
+                    source: Some(which_err.into()),
+                })
+            }
         }
     }
 }
@@ -179,63 +600,171 @@ fn convert_json(json: String) -> Result<SpeedResult, String> {
 fn send_email_on_error(
     simulate: bool,
     message_body: &str,
-    optinal_email_options: Option<EmailOptions>,
-) -> Result<(), String> {
+    optinal_email_options: &Option<EmailOptions>,
+) -> Result<(), Error> {
     if let Some(email_options) = optinal_email_options {
         mail::send_mail(
             simulate,
-            email_options.email,
+            &email_options.email,
             "Could not measure bandwidth",
             message_body,
-            email_options.smtp,
+            &email_options.smtp,
         )?;
     }
     Ok(())
 }
 
-#[derive(Derivative)]
-#[derivative(Debug)]
-struct SpeedResult {
-    date: DateTime<Utc>,
-    ping: f64,
-    download: f64,
-    upload: f64,
-    client_ip: String,
-    client_isp: String,
-    server_host: String,
-    server_location: String,
-    server_country: String,
-    server_id: u32,
-    #[derivative(Debug = "ignore")]
-    jsonresult: String,
-}
-
-#[derive(Deserialize)]
-struct RawSpeedResult {
-    ping: RawPing,
-    download: RawBandwidth,
-    upload: RawBandwidth,
-    interface: RawInterface,
-    isp: String,
-    server: RawServer,
-}
-#[derive(Deserialize)]
-struct RawPing {
-    latency: f64,
-}
-#[derive(Deserialize)]
-struct RawBandwidth {
-    bandwidth: f64,
-}
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct RawInterface {
-    external_ip: String,
-}
-#[derive(Deserialize)]
-struct RawServer {
-    host: String,
-    location: String,
-    country: String,
-    id: u32,
+#[cfg(test)]
+mod tests {
+    mod timeout {
+        use super::super::*;
+        use std::process::Command;
+
+        fn spawn(script: &str) -> Child {
+            Command::new("sh")
+                .args(["-c", script])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .process_group(0)
+                .spawn()
+                .unwrap()
+        }
+
+        #[test]
+        fn process_that_finishes_in_time_returns_its_output() {
+            let output =
+                wait_with_timeout(spawn("echo done"), Some(Duration::from_secs(10)), None).unwrap();
+            assert!(output.status.success());
+            assert_eq!("done\n", String::from_utf8_lossy(&output.stdout));
+        }
+
+        #[test]
+        fn process_that_does_not_finish_in_time_is_killed_with_its_children() {
+            let dir = crate::test_support::create_data_dir("run-timeout");
+            let pids_file = dir.join("pids");
+            // the shell writes its own PID and the one of the child it started
+            let script = format!(
+                "echo connecting >&2; echo waiting >&2; sleep 30 & echo $$ $! > {}.partial && mv {0}.partial {0} && sleep 30",
+                pids_file.display()
+            );
+            let start = Instant::now();
+            let err = wait_with_timeout(spawn(&script), Some(Duration::from_millis(500)), None)
+                .unwrap_err();
+            assert_eq!(Some(ErrorCategory::Timeout), err.category());
+            assert!(start.elapsed() < Duration::from_secs(10));
+            assert!(
+                err.to_string()
+                    .ends_with(" Last output on stderr:\nconnecting\nwaiting"),
+                "{}",
+                err
+            );
+            let pids: Vec<libc::pid_t> = std::fs::read_to_string(&pids_file)
+                .unwrap()
+                .split_whitespace()
+                .map(|pid| pid.parse().unwrap())
+                .collect();
+            assert_eq!(2, pids.len());
+            for pid in pids {
+                // the orphaned child is gone once init reaps it
+                let deadline = Instant::now() + Duration::from_secs(5);
+                let gone = loop {
+                    let alive = unsafe { libc::kill(pid, 0) } == 0;
+                    let errno = std::io::Error::last_os_error().raw_os_error();
+                    if !alive && errno == Some(libc::ESRCH) {
+                        break true;
+                    }
+                    if Instant::now() > deadline {
+                        break false;
+                    }
+                    thread::sleep(Duration::from_millis(50));
+                };
+                assert!(gone, "Process {} should have been killed", pid);
+            }
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    mod stderr_tail {
+        use super::super::*;
+        use pretty_assertions::assert_eq;
+
+        #[test]
+        fn keeps_the_last_lines_that_are_not_blank() {
+            assert_eq!("c\nd", last_lines(b"a\nb\n\nc\nd\n\n", 2));
+            assert_eq!("a", last_lines(b"a\n", 5));
+            assert_eq!("", last_lines(b"", 5));
+        }
+    }
+
+    mod simulation {
+        use super::super::*;
+        use chrono::TimeZone;
+        use pretty_assertions::assert_eq;
+
+        #[test]
+        fn simulated_results_are_dated_when_they_are_taken() {
+            let date = Utc.with_ymd_and_hms(2022, 5, 6, 7, 8, 9).unwrap();
+            let result = convert_json(simulated_result(&date)).unwrap();
+            assert_eq!(date, result.date);
+            assert_eq!(
+                convert_json(SIMULATED_RESULT.to_owned()).unwrap().download,
+                result.download
+            );
+        }
+    }
+
+    mod failed_samples {
+        use super::super::*;
+        use pretty_assertions::assert_eq;
+
+        fn failure(message: &str) -> Failure {
+            Failure {
+                date: Utc::now(),
+                category: ErrorCategory::Timeout,
+                details: message.to_owned(),
+                error: Error::measurement(ErrorCategory::Timeout, message, None),
+                fault: None,
+            }
+        }
+
+        #[test]
+        fn a_single_failure_is_returned_as_is() {
+            let err = failed_samples_error(vec![failure("timed out")], 3).unwrap();
+            assert_eq!("timed out", err.describe());
+            assert!(failed_samples_error(vec![], 3).is_none());
+        }
+
+        #[test]
+        fn several_failures_are_counted_with_the_last_error() {
+            let err = failed_samples_error(vec![failure("first"), failure("second")], 3).unwrap();
+            assert_eq!("2 of 3 samples failed.\nCaused by: second", err.describe());
+            assert_eq!(Some(ErrorCategory::Timeout), err.category());
+        }
+    }
+
+    mod backoff {
+        use super::super::*;
+        use pretty_assertions::assert_eq;
+
+        #[test]
+        fn first_retry_waits_the_initial_delay() {
+            assert_eq!(
+                Duration::from_secs(30),
+                backoff_delay(Duration::from_secs(30), 1)
+            );
+        }
+
+        #[test]
+        fn delay_doubles_on_each_retry() {
+            assert_eq!(
+                Duration::from_secs(120),
+                backoff_delay(Duration::from_secs(30), 3)
+            );
+        }
+
+        #[test]
+        fn delay_does_not_overflow() {
+            assert_eq!(Duration::MAX, backoff_delay(Duration::MAX, 200));
+        }
+    }
 }
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::io::{self, prelude::*, BufReader};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::api;
+use crate::config::Serve;
+use crate::dashboard;
+use crate::error::Error;
+use crate::storage::data_dir;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// A request, as much as the server needs of it.
+#[derive(Debug, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+}
+
+/// A response, always sent with its length and closing the connection.
+#[derive(Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn html(body: String) -> Response {
+        Response {
+            status: 200,
+            content_type: "text/html; charset=utf-8",
+            body: body.into_bytes(),
+        }
+    }
+
+    pub fn json(body: String) -> Response {
+        Response {
+            status: 200,
+            content_type: "application/json",
+            body: body.into_bytes(),
+        }
+    }
+
+    pub fn error(status: u16, message: &str) -> Response {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: message.as_bytes().to_vec(),
+        }
+    }
+}
+
+/// Serves the web UI until the process is stopped.
+pub fn serve(serve: Serve) -> Result<(), Error> {
+    let data_dir = data_dir()?;
+    let listener = TcpListener::bind(&serve.address)
+        .map_err(|err| Error::config(format!("Could not listen on {}", serve.address), err))?;
+    println!("Serving on http://{}/", serve.address);
+    run_server(listener, serve, data_dir);
+    Ok(())
+}
+
+/// Answers the connections of the listener, each one in its own thread.
+pub fn run_server(listener: TcpListener, serve: Serve, data_dir: PathBuf) {
+    let context = Arc::new((serve, data_dir));
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let context = Arc::clone(&context);
+                thread::spawn(move || {
+                    let (serve, data_dir) = context.as_ref();
+                    if let Err(err) = answer(stream, serve, data_dir) {
+                        debug!("Could not answer request: {}", err);
+                    }
+                });
+            }
+            Err(err) => warn!("Could not accept connection: {}", err),
+        }
+    }
+}
+
+fn answer(mut stream: TcpStream, serve: &Serve, data_dir: &Path) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let response = match read_request(&mut stream)? {
+        Some(request) => {
+            let response = route(&request, serve, data_dir);
+            info!("{} {} {}", request.method, request.path, response.status);
+            response
+        }
+        None => Response::error(400, "Bad request"),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len()
+    )?;
+    stream.write_all(&response.body)?;
+    stream.flush()
+}
+
+fn route(request: &Request, serve: &Serve, data_dir: &Path) -> Response {
+    if request.method != "GET" {
+        return Response::error(405, "Method not allowed");
+    }
+    if let Some(result) = api::route(request, serve, data_dir) {
+        return result.unwrap_or_else(|err| {
+            error!("{}", err.describe());
+            Response::error(500, &err.to_string())
+        });
+    }
+    let result = match request.path.as_str() {
+        "/" => dashboard::render(
+            serve,
+            data_dir,
+            request.query.get("range").map(String::as_str),
+        )
+        .map(Response::html),
+        path => match path.strip_prefix("/raw/") {
+            Some(name) => Ok(raw_result(data_dir, name)),
+            None => Ok(Response::error(404, "Not found")),
+        },
+    };
+    result.unwrap_or_else(|err| {
+        error!("{}", err.describe());
+        Response::error(500, &err.to_string())
+    })
+}
+
+/// The JSON written by Ookla's CLI for a measurement, see `storage::write_to_result_file`.
+fn raw_result(data_dir: &Path, name: &str) -> Response {
+    let valid = name.strip_suffix(".json").is_some_and(|stem| {
+        !stem.is_empty() && stem.chars().all(|c| c.is_ascii_digit() || c == '-')
+    });
+    if !valid {
+        return Response::error(404, "Not found");
+    }
+    match std::fs::read_to_string(data_dir.join(name)) {
+        Ok(json) => Response::json(json),
+        Err(_) => Response::error(404, "Not found"),
+    }
+}
+
+fn read_request(stream: &mut TcpStream) -> io::Result<Option<Request>> {
+    let mut reader = BufReader::new(stream).take(MAX_HEAD_SIZE as u64);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // the headers are not used, but have to be read before answering
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line == "\r\n" || line == "\n" {
+            break;
+        }
+    }
+    Ok(parse_request_line(&request_line))
+}
+
+fn parse_request_line(line: &str) -> Option<Request> {
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_owned();
+    let target = parts.next()?;
+    if !parts.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    Some(Request {
+        method,
+        path: path.to_owned(),
+        query: url::form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect(),
+    })
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::create_data_dir;
+    use crate::units::Unit;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+
+    #[test]
+    fn parses_request_lines() {
+        assert_eq!(
+            Some(Request {
+                method: "GET".to_owned(),
+                path: "/results".to_owned(),
+                query: HashMap::from([
+                    ("from".to_owned(), "2021-01-01T00:00:00+00:00".to_owned()),
+                    ("page".to_owned(), "2".to_owned())
+                ]),
+            }),
+            parse_request_line("GET /results?from=2021-01-01T00:00:00%2B00:00&page=2 HTTP/1.1\r\n")
+        );
+        assert_eq!(None, parse_request_line("GET /\r\n"));
+    }
+
+    #[test]
+    fn serves_dashboard_and_raw_results() {
+        let data_dir = create_data_dir("server");
+        fs::write(
+            data_dir.join("20210101000000.json"),
+            "{\"type\":\"result\"}",
+        )
+        .unwrap();
+        fs::write(data_dir.join("secret.txt"), "secret").unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let serve = Serve {
+            address: format!("127.0.0.1:{}", port),
+            units: Unit::Mbps,
+            interface: None,
+            expectation: None,
+            count: 8,
+            failures_window: 8,
+        };
+        let server_data_dir = data_dir.clone();
+        thread::spawn(move || run_server(listener, serve, server_data_dir));
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .timeout_global(Some(Duration::from_secs(5)))
+            .http_status_as_error(false)
+            .build()
+            .into();
+        let get = |path: &str| {
+            let mut response = agent
+                .get(&format!("http://127.0.0.1:{}{}", port, path))
+                .call()
+                .unwrap();
+            let body = response.body_mut().read_to_string().unwrap();
+            (response.status().as_u16(), body)
+        };
+        let (status, dashboard) = get("/?range=7d");
+        assert_eq!(200, status);
+        assert!(dashboard.contains("<html"));
+        assert_eq!(
+            (200, "{\"type\":\"result\"}".to_owned()),
+            get("/raw/20210101000000.json")
+        );
+        assert_eq!(404, get("/raw/secret.txt").0);
+        assert_eq!(404, get("/raw/..%2Fsecret.txt").0);
+        let (status, api) = get("/results?page=1");
+        assert_eq!(200, status);
+        assert!(api.contains("\"total\":0"));
+        assert_eq!(404, get("/nothing").0);
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+}
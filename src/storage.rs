@@ -0,0 +1,1088 @@
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, TimeDelta, Utc};
+use rev_lines::RawRevLines;
+use serde::{de, Deserialize, Deserializer};
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::io::{self, BufReader};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::diagnosis::Fault;
+use crate::error::Error;
+use crate::latency::percentile;
+use crate::results::{ErrorCategory, Failure, SpeedResult};
+use crate::units::{Unit, LEGACY_UNIT, STORED_UNIT};
+
+/// Columns of the summary file (`speed.csv`).
+pub const SUMMARY_HEADER: [&str; 33] = [
+    "date",
+    "ping",
+    "speeds_download",
+    "speeds_upload",
+    "client_ip",
+    "client_isp",
+    "server_host",
+    "server_lat",
+    "server_lon",
+    "server_location",
+    "server_country",
+    "location_distance",
+    "server_ping",
+    "server_id",
+    "interface",
+    "samples",
+    "speeds_download_spread",
+    "speeds_upload_spread",
+    "ping_spread",
+    "status",
+    "error_category",
+    "error_message",
+    "speeds_unit",
+    "jitter",
+    "packet_loss",
+    "idle_latency",
+    "download_latency_p50",
+    "download_latency_p90",
+    "upload_latency_p50",
+    "upload_latency_p90",
+    "bufferbloat_grade",
+    "fault",
+    "raw_file",
+];
+
+/// How much older than the rows after it a row can be, as rows are appended
+/// when their measurement ends and runs can overlap. See `read_results_since`.
+const OUT_OF_ORDER: TimeDelta = TimeDelta::days(1);
+
+/// File held locked by the writers of the summary file, see `lock_summary_file`.
+const SUMMARY_LOCK_FILE: &str = ".speed.csv.lock";
+
+/// The directory where results are stored, `data` in the current working directory.
+pub fn data_dir() -> Result<PathBuf, Error> {
+    let cwd = env::current_dir()
+        .map_err(|err| Error::storage("Error when finding current working directory", err))?;
+    Ok(cwd.join("data"))
+}
+
+/// Writes the raw JSON of a measurement to its own file, named after the measurement date.
+/// The file is written under a temporary name first, so it never appears half written,
+/// and is then linked to the first free name, so concurrent runs do not overwrite
+/// each other's results. Returns the name the file was given, see `result_file_stem`.
+pub fn write_to_result_file(data_dir: &Path, result: &SpeedResult) -> Result<String, Error> {
+    if !data_dir.exists() {
+        std::fs::create_dir(data_dir)
+            .map_err(|err| Error::storage("Error when creating data directory", err))?;
+    }
+    let file_stem = result_file_stem(&result.date);
+    let partial_path = partial_path(&data_dir.join(format!("{}.json", file_stem)));
+    fs::write(&partial_path, result.jsonresult.as_bytes())
+        .map_err(|err| Error::storage("Error when writing to file", err))?;
+    let mut file_path = data_dir.join(format!("{}.json", file_stem));
+    let mut suffix = 1;
+    loop {
+        match fs::hard_link(&partial_path, &file_path) {
+            Ok(()) => break,
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                file_path = data_dir.join(format!("{}-{}.json", file_stem, suffix));
+                suffix += 1;
+            }
+            // file systems without hard links
+            Err(_) if !file_path.exists() => {
+                fs::rename(&partial_path, &file_path)
+                    .map_err(|err| Error::storage("Error when writing to file", err))?;
+                return Ok(file_name(&file_path));
+            }
+            Err(err) => {
+                let _ = fs::remove_file(&partial_path);
+                return Err(Error::storage("Error when writing to file", err));
+            }
+        }
+    }
+    fs::remove_file(&partial_path)
+        .map_err(|err| Error::storage("Error when removing temporary file", err))?;
+    Ok(file_name(&file_path))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map_or(String::new(), |name| name.to_string_lossy().into_owned())
+}
+
+/// Writes the contents to a temporary file that then replaces `path`, so readers
+/// see either the old or the new contents, never a mix of both.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let partial_path = partial_path(path);
+    fs::write(&partial_path, contents)
+        .and_then(|_| fs::rename(&partial_path, path))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&partial_path);
+        })
+}
+
+/// A hidden temporary file next to `path`, unique to this process.
+fn partial_path(path: &Path) -> PathBuf {
+    path.with_file_name(format!(".{}.{}.partial", file_name(path), process::id()))
+}
+
+/// Locks the summary file for writing, until the returned file is dropped.
+/// Writers hold a lock file of its own, as rewrites replace the summary file,
+/// and with it any lock held on the file itself.
+pub fn lock_summary_file(data_dir: &Path) -> Result<File, Error> {
+    lock(&data_dir.join(SUMMARY_LOCK_FILE))
+}
+
+/// Opens (creating it if needed) and locks a file used only for locking, which
+/// is unlocked when the returned file is dropped.
+pub fn lock(path: &Path) -> Result<File, Error> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .map_err(|err| Error::storage("Error when opening lock file", err))?;
+    file.lock()
+        .map_err(|err| Error::storage("Error when locking file", err))?;
+    Ok(file)
+}
+
+/// Format of the dates in rows written by older versions, in UTC.
+pub const LEGACY_DATE_FORMAT: &str = "%Y/%m/%d %H:%M:%S";
+
+/// A date as written to the summary file, in RFC 3339 with the UTC offset.
+pub fn format_date(date: &DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Parses a date of the summary file, in RFC 3339 or in `LEGACY_DATE_FORMAT`.
+pub fn parse_date(text: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(text)
+        .map(|date| date.with_timezone(&Utc))
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(text, LEGACY_DATE_FORMAT)
+                .map(|date| DateTime::from_naive_utc_and_offset(date, Utc))
+        })
+        .map_err(|err| format!("invalid date '{}': {}", text, err))
+}
+
+/// Name of the JSON file of a measurement, without the extension. When more than
+/// one measurement has the same date the following ones get a `-N` suffix.
+pub fn result_file_stem(date: &DateTime<Utc>) -> String {
+    date.format("%Y%m%d%H%M%S").to_string()
+}
+
+/// Appends a record (see `result_record` and `failure_record`) to the summary file,
+/// creating or upgrading it when needed. The file is locked while written, so
+/// concurrent runs do not write two headers or mix their lines.
+pub fn append_to_summary_file(data_dir: &Path, record: Vec<String>) -> Result<(), Error> {
+    if !data_dir.exists() {
+        std::fs::create_dir(data_dir)
+            .map_err(|err| Error::storage("Error when creating data directory", err))?;
+    }
+    let _lock = lock_summary_file(data_dir)?;
+    let file_path = data_dir.join("speed.csv");
+    let mut file = open_for_append(&file_path)?;
+    let length = file
+        .metadata()
+        .map_err(|err| Error::storage("Error when reading summary file", err))?
+        .len();
+    // writers hold the lock, so a partial line is left by an interrupted one
+    let complete = complete_length(&file)
+        .map_err(|err| Error::storage("Error when reading summary file", err))?;
+    if complete < length {
+        warn!("Discarding the partial last line of the summary file.");
+        file.set_len(complete)
+            .map_err(|err| Error::storage("Error when writing to file", err))?;
+    }
+    if complete == 0 {
+        file.write_all(format!("{}\n", SUMMARY_HEADER.join(",")).as_bytes())
+            .map_err(|err| Error::storage("Error writing header to file", err))?;
+    } else if upgrade_summary_file(&file_path)? {
+        // the upgraded file replaced the one opened
+        file = open_for_append(&file_path)?;
+    }
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(&file);
+    writer
+        .write_record(record)
+        .and_then(|_| writer.flush().map_err(csv::Error::from))
+        .map_err(|err| Error::storage("Error when writing to file", err))?;
+    Ok(())
+}
+
+/// Opens the summary file to append to it, locked so that readers do not see
+/// a line half written.
+fn open_for_append(file_path: &Path) -> Result<File, Error> {
+    let file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(file_path)
+        .map_err(|err| Error::storage("Error when creating file", err))?;
+    file.lock()
+        .map_err(|err| Error::storage("Error when locking summary file", err))?;
+    Ok(file)
+}
+
+/// Length of the file up to its last line break.
+fn complete_length(file: &File) -> io::Result<u64> {
+    let mut end = file.metadata()?.len();
+    let mut chunk = [0; 4096];
+    while end > 0 {
+        let start = end.saturating_sub(chunk.len() as u64);
+        let chunk = &mut chunk[..(end - start) as usize];
+        file.read_exact_at(chunk, start)?;
+        if let Some(last) = chunk.iter().rposition(|byte| *byte == b'\n') {
+            return Ok(start + last as u64 + 1);
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+/// Whether the file is empty or its last byte is a line break.
+fn ends_with_newline(file: &File) -> io::Result<bool> {
+    let length = file.metadata()?.len();
+    if length == 0 {
+        return Ok(true);
+    }
+    let mut last = [0; 1];
+    file.read_exact_at(&mut last, length - 1)?;
+    Ok(last[0] == b'\n')
+}
+
+pub fn result_record(result: &SpeedResult) -> Vec<String> {
+    let mut record = vec![
+        format_date(&result.date),
+        result.ping.to_string(),
+        format!("{:.2}", STORED_UNIT.from_bytes_per_second(result.download)),
+        format!("{:.2}", STORED_UNIT.from_bytes_per_second(result.upload)),
+        result.client_ip.clone(),
+        result.client_isp.clone(),
+        result.server_host.clone(),
+        "null".to_owned(),
+        "null".to_owned(),
+        result.server_location.clone(),
+        result.server_country.clone(),
+        "null".to_owned(),
+        "null".to_owned(),
+        result.server_id.to_string(),
+        result.interface.clone(),
+        result.samples.to_string(),
+        format!(
+            "{:.2}",
+            STORED_UNIT.from_bytes_per_second(result.download_spread)
+        ),
+        format!(
+            "{:.2}",
+            STORED_UNIT.from_bytes_per_second(result.upload_spread)
+        ),
+        format!("{:.3}", result.ping_spread),
+        "ok".to_owned(),
+        "".to_owned(),
+        "".to_owned(),
+        STORED_UNIT.to_string(),
+        format!("{:.3}", result.jitter),
+        result
+            .packet_loss
+            .map_or("".to_owned(), |packet_loss| packet_loss.to_string()),
+    ];
+    let latency = result.loaded_latency.clone().unwrap_or_default();
+    let milliseconds =
+        |latency: Option<f64>| latency.map_or("".to_owned(), |latency| format!("{:.3}", latency));
+    record.extend([
+        milliseconds(latency.idle_median()),
+        milliseconds(percentile(&latency.download, 50.0)),
+        milliseconds(percentile(&latency.download, 90.0)),
+        milliseconds(percentile(&latency.upload, 50.0)),
+        milliseconds(percentile(&latency.upload, 90.0)),
+        latency
+            .grade()
+            .map_or("".to_owned(), |grade| grade.to_string()),
+        "".to_owned(),
+        result.raw_file.clone().unwrap_or_default(),
+    ]);
+    record
+}
+
+pub fn failure_record(failure: &Failure, interface: &Option<String>) -> Vec<String> {
+    SUMMARY_HEADER
+        .iter()
+        .map(|column| match *column {
+            "date" => format_date(&failure.date),
+            "interface" => interface.clone().unwrap_or_default(),
+            "status" => "failed".to_owned(),
+            "error_category" => failure.category.to_string(),
+            "fault" => failure
+                .fault
+                .map_or("".to_owned(), |fault| fault.to_string()),
+            // keep every record in a single line, so the summary file can be read backwards
+            "error_message" => failure
+                .details
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" "),
+            _ => "".to_owned(),
+        })
+        .collect()
+}
+
+/// Rewrites a summary file created by an older version so its columns match
+/// `SUMMARY_HEADER`. Columns that did not exist before are left empty, except for
+/// `speeds_unit`, which is set to the unit older versions used (`LEGACY_UNIT`).
+/// The upgraded file replaces the old one, see `write_atomically`, so callers
+/// hold `lock_summary_file` and reopen the file when it returns `true`.
+pub fn upgrade_summary_file(file_path: &Path) -> Result<bool, Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_path(file_path)
+        .map_err(|err| Error::storage("Error when opening summary file", err))?;
+    let headers = reader
+        .headers()
+        .map_err(|err| Error::storage("Error when reading summary file header", err))?
+        .clone();
+    if headers.iter().eq(SUMMARY_HEADER) {
+        return Ok(false);
+    }
+    info!("Upgrading summary file columns from: {:?}", headers);
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer
+        .write_record(SUMMARY_HEADER)
+        .map_err(|err| Error::storage("Error when upgrading summary file", err))?;
+    for record in reader.records() {
+        let record =
+            record.map_err(|err| Error::storage("Error when upgrading summary file", err))?;
+        writer
+            .write_record(to_summary_columns(&headers, &record))
+            .map_err(|err| Error::storage("Error when upgrading summary file", err))?;
+    }
+    let contents = writer
+        .into_inner()
+        .map_err(|err| Error::storage("Error when upgrading summary file", err))?;
+    write_atomically(file_path, &contents)
+        .map_err(|err| Error::storage("Error when writing upgraded summary file", err))?;
+    Ok(true)
+}
+
+/// The values of a record with the `headers` columns, in the order of
+/// `SUMMARY_HEADER`. Columns that are missing are left empty, except for
+/// `speeds_unit`, which is set to the unit older versions used (`LEGACY_UNIT`).
+/// Dates in `LEGACY_DATE_FORMAT` are converted to RFC 3339, dates that do not
+/// parse are kept for `check` to report.
+pub fn to_summary_columns(headers: &csv::StringRecord, record: &csv::StringRecord) -> Vec<String> {
+    SUMMARY_HEADER
+        .iter()
+        .map(|column| {
+            headers
+                .iter()
+                .position(|header| header == *column)
+                .and_then(|index| record.get(index))
+                .map_or_else(
+                    || {
+                        if *column == "speeds_unit" {
+                            LEGACY_UNIT.to_string()
+                        } else {
+                            String::new()
+                        }
+                    },
+                    |value| match *column {
+                        "date" => upgrade_date(value),
+                        _ => value.to_owned(),
+                    },
+                )
+        })
+        .collect()
+}
+
+fn upgrade_date(text: &str) -> String {
+    match NaiveDateTime::parse_from_str(text, LEGACY_DATE_FORMAT) {
+        Ok(date) => format_date(&DateTime::from_naive_utc_and_offset(date, Utc)),
+        Err(_) => text.to_owned(),
+    }
+}
+
+/// Reads the latest `count` rows of the summary file, like `read_latest_results`.
+/// Returns `None` if there are not enough rows.
+pub fn get_latest_results(
+    data_dir: &Path,
+    count: u8,
+    interface: Option<&str>,
+    filter: impl Fn(&ResultCsv) -> bool,
+) -> Result<Option<Vec<ResultCsv>>, Error> {
+    let results = read_latest_results(data_dir, count, interface, filter)?;
+    if results.len() < count as usize {
+        return Ok(None);
+    }
+    Ok(Some(results))
+}
+
+/// Reads up to the latest `count` rows of the summary file, from the newest to
+/// the oldest, skipping rows from other interfaces or rejected by `filter`.
+/// Malformed rows are skipped with a warning, see `BadRow`.
+pub fn read_latest_results(
+    data_dir: &Path,
+    count: u8,
+    interface: Option<&str>,
+    filter: impl Fn(&ResultCsv) -> bool,
+) -> Result<Vec<ResultCsv>, Error> {
+    let mut results = vec![];
+    if count == 0 {
+        return Ok(results);
+    }
+    read_backwards(data_dir, |result| {
+        if (interface.is_none() || result.interface.as_deref() == interface) && filter(&result) {
+            results.push(result);
+        }
+        results.len() < count as usize
+    })?;
+    Ok(results)
+}
+
+/// Reads the rows of the summary file dated from `since` on, from the oldest to
+/// the newest, reading it from its end. Rows are appended as measurements end,
+/// so the file is read until rows are older than `since` by `OUT_OF_ORDER`.
+pub fn read_results_since(data_dir: &Path, since: DateTime<Utc>) -> Result<Vec<ResultCsv>, Error> {
+    let mut results = vec![];
+    read_backwards(data_dir, |result| {
+        let date = result.date;
+        if date >= since {
+            results.push(result);
+        }
+        date >= since - OUT_OF_ORDER
+    })?;
+    results.reverse();
+    Ok(results)
+}
+
+/// Passes the rows of the summary file to `visit`, from the newest to the oldest,
+/// until it returns `false`. A last line that is still being written is ignored
+/// and malformed rows are skipped with a warning, see `BadRow`.
+fn read_backwards(data_dir: &Path, mut visit: impl FnMut(ResultCsv) -> bool) -> Result<(), Error> {
+    let file_path = data_dir.join("speed.csv");
+    let file = if file_path.exists() {
+        File::open(&file_path)
+            .map_err(|err| Error::storage("Error when opening summary file", err))?
+    } else {
+        return Ok(());
+    };
+    file.lock_shared()
+        .map_err(|err| Error::storage("Error when locking summary file", err))?;
+    // a line without its line break is still being written, or was interrupted
+    let partial_line =
+        !ends_with_newline(&file).map_err(|err| Error::storage("Error when reading file", err))?;
+    let mut header_line = vec![];
+    BufReader::new(&file)
+        .read_until(b'\n', &mut header_line)
+        .map_err(|err| Error::storage("Error when reading file", err))?;
+    if header_line.last() == Some(&b'\n') {
+        header_line.pop();
+    }
+    if header_line.last() == Some(&b'\r') {
+        header_line.pop();
+    }
+    if header_line.is_empty() {
+        return Ok(());
+    }
+    let header = parse_csv_line(&header_line)?;
+    // line numbers are only needed to report malformed rows
+    let mut line_count = None;
+    for (lines_from_end, line) in RawRevLines::new(&file).enumerate() {
+        let line = line.map_err(|err| Error::storage("Error when reading file", err))?;
+        if line == header_line {
+            break;
+        }
+        if (partial_line && lines_from_end == 0) || line.is_empty() {
+            continue;
+        }
+        let record = parse_csv_line(&line)?;
+        match parse_row(&header, &record) {
+            Ok(result) => {
+                if !visit(result) {
+                    break;
+                }
+            }
+            Err(error) => {
+                if line_count.is_none() {
+                    line_count = Some(
+                        count_lines(&file)
+                            .map_err(|err| Error::storage("Error when reading file", err))?,
+                    );
+                }
+                let line = line_count.unwrap_or_default() - lines_from_end as u64;
+                warn_bad_row(&BadRow {
+                    line,
+                    error,
+                    record,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads every row of the summary file, from the oldest to the newest, ignoring
+/// a last line that is still being written. Malformed rows are skipped with a
+/// warning, see `BadRow`.
+pub fn read_results(data_dir: &Path) -> Result<Vec<ResultCsv>, Error> {
+    let file_path = data_dir.join("speed.csv");
+    if !file_path.exists() {
+        return Ok(vec![]);
+    }
+    let mut file = File::open(&file_path)
+        .map_err(|err| Error::storage("Error when opening summary file", err))?;
+    file.lock_shared()
+        .map_err(|err| Error::storage("Error when locking summary file", err))?;
+    let mut contents = vec![];
+    file.read_to_end(&mut contents)
+        .map_err(|err| Error::storage("Error when reading summary file", err))?;
+    drop(file);
+    let complete = contents
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(0, |last| last + 1);
+    let summary = parse_summary(&contents[..complete])?;
+    for bad_row in summary.bad_rows.iter() {
+        warn_bad_row(bad_row);
+    }
+    Ok(summary.rows.into_iter().map(|(_, row)| row).collect())
+}
+
+/// The rows of a summary file, see `parse_summary`.
+#[derive(Debug)]
+pub struct Summary {
+    pub header: csv::ByteRecord,
+    /// The rows that could be read, each with its record.
+    pub rows: Vec<(csv::ByteRecord, ResultCsv)>,
+    pub bad_rows: Vec<BadRow>,
+}
+
+/// A row of the summary file that could not be read, like one with a malformed
+/// date or more fields than the header.
+#[derive(Debug, PartialEq)]
+pub struct BadRow {
+    /// Line of the row in the file, the header being the first one.
+    pub line: u64,
+    pub error: String,
+    pub record: csv::ByteRecord,
+}
+
+impl fmt::Display for BadRow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.error)
+    }
+}
+
+/// Reads the rows of the contents of a summary file, setting apart the ones that
+/// cannot be read instead of failing.
+pub fn parse_summary(contents: &[u8]) -> Result<Summary, Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(contents);
+    let header = reader
+        .byte_headers()
+        .map_err(|err| Error::storage("Error when reading summary file header", err))?
+        .clone();
+    let mut rows = vec![];
+    let mut bad_rows = vec![];
+    for record in reader.byte_records() {
+        let record =
+            record.map_err(|err| Error::storage("Error when reading summary file", err))?;
+        match parse_row(&header, &record) {
+            Ok(row) => rows.push((record, row)),
+            Err(error) => bad_rows.push(BadRow {
+                line: record.position().map_or(0, |position| position.line()),
+                error,
+                record,
+            }),
+        }
+    }
+    Ok(Summary {
+        header,
+        rows,
+        bad_rows,
+    })
+}
+
+/// Reads a row, checking it has no more fields than the header, so a row with a
+/// stray comma does not shift its values into the wrong columns. Rows with less
+/// fields leave the missing columns empty.
+pub(crate) fn parse_row(
+    header: &csv::ByteRecord,
+    record: &csv::ByteRecord,
+) -> Result<ResultCsv, String> {
+    if record.len() > header.len() {
+        return Err(format!(
+            "has {} fields, the header has {}",
+            record.len(),
+            header.len()
+        ));
+    }
+    record
+        .deserialize(Some(header))
+        .map_err(|err| match err.kind() {
+            csv::ErrorKind::Deserialize { err, .. } => err.to_string(),
+            _ => err.to_string(),
+        })
+}
+
+fn warn_bad_row(bad_row: &BadRow) {
+    warn!("Skipping malformed row of the summary file at {}.", bad_row);
+}
+
+/// Number of lines of the file, counting a last line without a line break.
+fn count_lines(file: &File) -> io::Result<u64> {
+    let mut chunk = [0; 8192];
+    let mut position = 0;
+    let mut lines = 0;
+    let mut last = b'\n';
+    loop {
+        let read = file.read_at(&mut chunk, position)?;
+        if read == 0 {
+            break;
+        }
+        lines += chunk[..read].iter().filter(|byte| **byte == b'\n').count() as u64;
+        last = chunk[read - 1];
+        position += read as u64;
+    }
+    Ok(if last == b'\n' { lines } else { lines + 1 })
+}
+
+fn parse_csv_line(line: &[u8]) -> Result<csv::ByteRecord, Error> {
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(line)
+        .byte_records()
+        .next()
+        .unwrap_or_else(|| Ok(csv::ByteRecord::new()))
+        .map_err(|err| Error::storage("Error deserializing csv", err))
+}
+
+pub(crate) fn date_time_from_str<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: String = Deserialize::deserialize(deserializer)?;
+    parse_date(&s).map_err(de::Error::custom)
+}
+
+/// A row of the summary file. Speeds are in `speeds_unit`, see `ResultCsv::unit`.
+#[derive(Debug, Default, Deserialize)]
+#[serde()]
+pub struct ResultCsv {
+    #[serde(deserialize_with = "date_time_from_str")]
+    pub date: DateTime<Utc>,
+    pub ping: Option<f64>,
+    pub speeds_download: Option<f64>,
+    pub speeds_upload: Option<f64>,
+    pub client_ip: Option<String>,
+    pub client_isp: Option<String>,
+    pub server_host: Option<String>,
+    pub server_location: Option<String>,
+    pub server_country: Option<String>,
+    pub server_id: Option<u32>,
+    pub interface: Option<String>,
+    pub samples: Option<usize>,
+    pub status: Option<Status>,
+    pub error_category: Option<ErrorCategory>,
+    pub error_message: Option<String>,
+    pub speeds_unit: Option<Unit>,
+    pub jitter: Option<f64>,
+    pub packet_loss: Option<f64>,
+    /// Median latency to the target of `--latency-target` before the measurement.
+    pub idle_latency: Option<f64>,
+    pub download_latency_p50: Option<f64>,
+    pub download_latency_p90: Option<f64>,
+    pub upload_latency_p50: Option<f64>,
+    pub upload_latency_p90: Option<f64>,
+    pub bufferbloat_grade: Option<String>,
+    /// Where the network failed, for failed measurements that were diagnosed.
+    pub fault: Option<Fault>,
+    /// Name of the file with the raw JSON of the measurement, in the data directory.
+    pub raw_file: Option<String>,
+}
+
+impl ResultCsv {
+    /// The unit of the speeds of this row. Rows written before the `speeds_unit`
+    /// column existed are in `LEGACY_UNIT`.
+    pub fn unit(&self) -> Unit {
+        self.speeds_unit.unwrap_or(LEGACY_UNIT)
+    }
+
+    /// The download speed converted to `unit`.
+    pub fn download_in(&self, unit: Unit) -> Option<f64> {
+        self.speeds_download
+            .map(|download| self.unit().convert(download, unit))
+    }
+
+    /// The upload speed converted to `unit`.
+    pub fn upload_in(&self, unit: Unit) -> Option<f64> {
+        self.speeds_upload
+            .map(|upload| self.unit().convert(upload, unit))
+    }
+
+    /// The latency under load in the worse direction, the higher of the
+    /// download and upload medians.
+    pub fn loaded_latency(&self) -> Option<f64> {
+        match (self.download_latency_p50, self.upload_latency_p50) {
+            (Some(download), Some(upload)) => Some(download.max(upload)),
+            (download, upload) => download.or(upload),
+        }
+    }
+
+    pub fn failed(&self) -> bool {
+        self.status == Some(Status::Failed)
+    }
+
+    /// Name of the file with the raw JSON of the measurement. Rows written before
+    /// the `raw_file` column existed are assumed to have the first name of their
+    /// date, as measurements of the same second were rare.
+    pub fn raw_file_name(&self) -> String {
+        self.raw_file
+            .clone()
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| format!("{}.json", result_file_stem(&self.date)))
+    }
+}
+
+/// Whether a measurement succeeded.
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    Failed,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::create_data_dir_with_summary;
+    use pretty_assertions::assert_eq;
+
+    const OLD_HEADER: &str = "date,ping,speeds_download,speeds_upload,client_ip,client_isp,server_host,server_lat,server_lon,server_location,server_country,location_distance,server_ping,server_id";
+
+    #[test]
+    fn upgrades_summary_file_with_old_columns() {
+        let data_dir = create_data_dir_with_summary(
+            "storage-upgrade",
+            &format!(
+                "{}\n2021/01/01 00:00:00,5.7,154.95,100.76,\"84.6.0.1\",\"Some ISP\",\"h.com\",null,null,\"São Paulo\",\"Brazil\",null,null,99999\n",
+                OLD_HEADER
+            ),
+        );
+        let file_path = data_dir.join("speed.csv");
+        upgrade_summary_file(&file_path).unwrap();
+        let contents = fs::read_to_string(&file_path).unwrap();
+        let mut lines = contents.lines();
+        assert_eq!(Some(SUMMARY_HEADER.join(",").as_str()), lines.next());
+        assert_eq!(
+            Some("2021-01-01T00:00:00Z,5.7,154.95,100.76,84.6.0.1,Some ISP,h.com,null,null,São Paulo,Brazil,null,null,99999,,,,,,,,,mibps,,,,,,,,,,"),
+            lines.next()
+        );
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn reads_latest_results_of_an_interface() {
+        let data_dir = create_data_dir_with_summary(
+            "storage-latest",
+            &format!(
+                "{},interface\n2021/01/01 00:00:00,5,10,20,,,,,,,,,,1,eth0\n2021/01/01 01:00:00,5,30,40,,,,,,,,,,1,eth1\n2021/01/01 02:00:00,5,50,60,,,,,,,,,,1,eth0\n",
+                OLD_HEADER
+            ),
+        );
+        let results = get_latest_results(&data_dir, 2, Some("eth0"), |_| true)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            vec![Some(50.0), Some(10.0)],
+            results
+                .iter()
+                .map(|result| result.speeds_download)
+                .collect::<Vec<_>>()
+        );
+        assert!(get_latest_results(&data_dir, 3, Some("eth0"), |_| true)
+            .unwrap()
+            .is_none());
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn reads_results_since_a_date_from_the_end() {
+        let data_dir = create_data_dir_with_summary(
+            "storage-since",
+            &format!(
+                "{}\n2020/12/01 00:00:00,5,10,20,,,,,,,,,,1\n2021/01/01 10:00:00,5,30,40,,,,,,,,,,1\n2021/01/02 10:00:00,5,50,60,,,,,,,,,,1\n2021/01/01 23:00:00,5,70,80,,,,,,,,,,1\n",
+                OLD_HEADER
+            ),
+        );
+        let since = parse_date("2021-01-01T12:00:00Z").unwrap();
+        assert_eq!(
+            vec![Some(50.0), Some(70.0)],
+            read_results_since(&data_dir, since)
+                .unwrap()
+                .iter()
+                .map(|result| result.speeds_download)
+                .collect::<Vec<_>>(),
+            "Rows appended out of order are read"
+        );
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn converts_speeds_of_rows_in_different_units() {
+        let data_dir = create_data_dir_with_summary(
+            "storage-units",
+            &format!(
+                "{}\n2021/01/01 00:00:00,5,100,50,,,,,,,,,,1,,,,,,ok,,,\n2021/01/01 01:00:00,5,100,50,,,,,,,,,,1,,,,,,ok,,,mbps\n",
+                SUMMARY_HEADER.join(",")
+            ),
+        );
+        let results = get_latest_results(&data_dir, 2, None, |_| true)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            vec![Some(100.0), Some(104.8576)],
+            results
+                .iter()
+                .map(|result| result.download_in(Unit::Mbps))
+                .collect::<Vec<_>>()
+        );
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn reads_dates_with_offsets_and_in_the_legacy_format() {
+        let result = crate::results::convert_json(crate::run::SIMULATED_RESULT.to_owned()).unwrap();
+        assert_eq!("2021-01-03T12:10:00Z", result_record(&result)[0]);
+        let data_dir = create_data_dir_with_summary(
+            "storage-dates",
+            &format!(
+                "{}\n2021/01/03 12:10:00,5,100,50,,,,,,,,,,1\n2021-01-03T12:10:00Z,5,100,50,,,,,,,,,,1\n2021-01-03T14:10:00+02:00,5,100,50,,,,,,,,,,1\n",
+                OLD_HEADER
+            ),
+        );
+        assert_eq!(
+            vec![result.date; 3],
+            read_results(&data_dir)
+                .unwrap()
+                .iter()
+                .map(|row| row.date)
+                .collect::<Vec<_>>()
+        );
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn writes_loaded_latency_percentiles() {
+        let mut result =
+            crate::results::convert_json(crate::run::SIMULATED_RESULT.to_owned()).unwrap();
+        result.loaded_latency = Some(crate::latency::LoadedLatency {
+            idle: vec![5.0, 6.0],
+            download: (1..=10).map(|latency| latency as f64 * 10.0).collect(),
+            upload: vec![],
+        });
+        let record = result_record(&result);
+        assert_eq!(SUMMARY_HEADER.len(), record.len());
+        assert_eq!(
+            vec!["5.000", "50.000", "90.000", "", "", "B"],
+            record[SUMMARY_HEADER.len() - 8..SUMMARY_HEADER.len() - 2].to_vec()
+        );
+        let data_dir = create_data_dir_with_summary("storage-latency", "");
+        append_to_summary_file(&data_dir, record).unwrap();
+        let rows = read_results(&data_dir).unwrap();
+        assert_eq!(Some(50.0), rows[0].loaded_latency());
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn writes_the_fault_of_failures() {
+        let failure = Failure {
+            date: parse_date("2021-01-03T12:10:00Z").unwrap(),
+            category: ErrorCategory::Timeout,
+            details: "Attempt 1:\n  timed out".to_owned(),
+            error: Error::measurement(ErrorCategory::Timeout, "timed out", None),
+            fault: Some(Fault::Upstream),
+        };
+        let data_dir = create_data_dir_with_summary("storage-fault", "");
+        append_to_summary_file(&data_dir, failure_record(&failure, &None)).unwrap();
+        let rows = read_results(&data_dir).unwrap();
+        assert_eq!(Some(Fault::Upstream), rows[0].fault);
+        assert_eq!(Some(ErrorCategory::Timeout), rows[0].error_category);
+        assert_eq!(
+            Some("Attempt 1: timed out"),
+            rows[0].error_message.as_deref()
+        );
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    fn record(hour: u32, download: u32) -> Vec<String> {
+        format!(
+            "2021/01/01 {:02}:00:00,5,{},50,,,,,,,,,,1,,,,,,ok,,,mbps",
+            hour, download
+        )
+        .split(',')
+        .map(str::to_owned)
+        .collect()
+    }
+
+    #[test]
+    fn appends_from_concurrent_runs() {
+        let data_dir = create_data_dir_with_summary("storage-concurrent", "");
+        fs::remove_file(data_dir.join("speed.csv")).unwrap();
+        let threads: Vec<_> = (0..8)
+            .map(|hour| {
+                let data_dir = data_dir.clone();
+                std::thread::spawn(move || {
+                    for download in 0..10 {
+                        append_to_summary_file(&data_dir, record(hour, download)).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let contents = fs::read_to_string(data_dir.join("speed.csv")).unwrap();
+        assert_eq!(1, contents.matches("date,").count());
+        assert_eq!(80, read_results(&data_dir).unwrap().len());
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn appends_from_concurrent_runs_to_a_file_being_upgraded() {
+        let data_dir = create_data_dir_with_summary(
+            "storage-concurrent-upgrade",
+            &format!("{}\n2021/01/01 00:00:00,5,10,20,,,,,,,,,,1\n", OLD_HEADER),
+        );
+        let threads: Vec<_> = (0..8)
+            .map(|hour| {
+                let data_dir = data_dir.clone();
+                std::thread::spawn(move || {
+                    for download in 0..10 {
+                        append_to_summary_file(&data_dir, record(hour, download)).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let contents = fs::read_to_string(data_dir.join("speed.csv")).unwrap();
+        assert!(contents.starts_with(&format!("{}\n", SUMMARY_HEADER.join(","))));
+        assert_eq!(81, read_results(&data_dir).unwrap().len());
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn ignores_partial_last_line() {
+        let data_dir = create_data_dir_with_summary(
+            "storage-partial",
+            &format!(
+                "{}\n{}\n2021/01/01 01:00:00,5,1",
+                SUMMARY_HEADER.join(","),
+                record(0, 100).join(",")
+            ),
+        );
+        let downloads = |results: Vec<ResultCsv>| {
+            results
+                .iter()
+                .map(|result| result.speeds_download)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            vec![Some(100.0)],
+            downloads(read_results(&data_dir).unwrap())
+        );
+        assert_eq!(
+            vec![Some(100.0)],
+            downloads(
+                get_latest_results(&data_dir, 1, None, |_| true)
+                    .unwrap()
+                    .unwrap()
+            )
+        );
+        append_to_summary_file(&data_dir, record(2, 200)).unwrap();
+        assert_eq!(
+            vec![Some(100.0), Some(200.0)],
+            downloads(read_results(&data_dir).unwrap()),
+            "The partial line is replaced by the new row"
+        );
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn writes_results_of_the_same_second_to_different_files() {
+        let data_dir = create_data_dir_with_summary("storage-results", "");
+        let result = crate::results::convert_json(crate::run::SIMULATED_RESULT.to_owned()).unwrap();
+        let first = write_to_result_file(&data_dir, &result).unwrap();
+        let second = write_to_result_file(&data_dir, &result).unwrap();
+        let stem = result_file_stem(&result.date);
+        assert_eq!(format!("{}.json", stem), first);
+        assert_eq!(format!("{}-1.json", stem), second);
+        let mut names: Vec<String> = fs::read_dir(&data_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(
+            vec![
+                format!("{}-1.json", stem),
+                format!("{}.json", stem),
+                "speed.csv".to_owned(),
+            ],
+            names
+        );
+        assert_eq!(
+            result.jsonresult,
+            fs::read_to_string(data_dir.join(format!("{}.json", stem))).unwrap()
+        );
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn skips_malformed_rows() {
+        let data_dir = create_data_dir_with_summary(
+            "storage-malformed",
+            &format!(
+                "{}\n{}\nyesterday,5,1\n{}\n{},extra\n",
+                SUMMARY_HEADER.join(","),
+                record(0, 100).join(","),
+                record(2, 200).join(","),
+                record(3, 300).join(","),
+            ),
+        );
+        let downloads = |results: Vec<ResultCsv>| {
+            results
+                .iter()
+                .map(|result| result.speeds_download)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            vec![Some(200.0), Some(100.0)],
+            downloads(
+                get_latest_results(&data_dir, 2, None, |_| true)
+                    .unwrap()
+                    .unwrap()
+            )
+        );
+        assert_eq!(
+            vec![Some(100.0), Some(200.0)],
+            downloads(read_results(&data_dir).unwrap())
+        );
+        let contents = fs::read(data_dir.join("speed.csv")).unwrap();
+        assert_eq!(
+            vec![3, 5],
+            parse_summary(&contents)
+                .unwrap()
+                .bad_rows
+                .iter()
+                .map(|bad_row| bad_row.line)
+                .collect::<Vec<_>>()
+        );
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+}
@@ -0,0 +1,21 @@
+//! Fixtures shared by the tests of the modules.
+use std::fs;
+use std::path::PathBuf;
+
+/// An empty data directory for a test, named `trackspeedtest-<name>-<pid>` in the
+/// temporary directory. What a previous run left there is removed first.
+pub fn create_data_dir(name: &str) -> PathBuf {
+    let data_dir =
+        std::env::temp_dir().join(format!("trackspeedtest-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&data_dir);
+    fs::create_dir_all(&data_dir).unwrap();
+    data_dir
+}
+
+/// A data directory for a test, see `create_data_dir`, with `summary` as its
+/// `speed.csv`.
+pub fn create_data_dir_with_summary(name: &str, summary: &str) -> PathBuf {
+    let data_dir = create_data_dir(name);
+    fs::write(data_dir.join("speed.csv"), summary).unwrap();
+    data_dir
+}
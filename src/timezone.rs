@@ -0,0 +1,116 @@
+use chrono::{DateTime, FixedOffset, Local, SecondsFormat, Utc};
+use chrono_tz::Tz;
+use std::fmt;
+use std::str::FromStr;
+
+/// The time zone dates are shown in. Dates are always stored in UTC.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Zone {
+    Utc,
+    /// The zone of the system, set by the `TZ` environment variable or
+    /// `/etc/localtime`, falling back to UTC when neither is available.
+    Local,
+    /// A fixed offset from UTC, like `+02:00`.
+    Fixed(FixedOffset),
+    /// A zone of the IANA database, like `America/Sao_Paulo`, built into the
+    /// binary so that it does not depend on the zone files of the system.
+    Named(Tz),
+}
+
+impl Zone {
+    /// Formats `date` in this zone, with a `chrono` format string.
+    pub fn format(self, date: &DateTime<Utc>, format: &str) -> String {
+        match self {
+            Zone::Utc => date.format(format).to_string(),
+            Zone::Local => date.with_timezone(&Local).format(format).to_string(),
+            Zone::Fixed(offset) => date.with_timezone(&offset).format(format).to_string(),
+            Zone::Named(zone) => date.with_timezone(&zone).format(format).to_string(),
+        }
+    }
+
+    /// A name of the zone at `date` for labels, like `UTC` or `UTC+02:00`.
+    pub fn label(self, date: &DateTime<Utc>) -> String {
+        match self {
+            Zone::Utc => "UTC".to_owned(),
+            _ => self.format(date, "UTC%:z"),
+        }
+    }
+
+    /// Formats `date` in this zone as RFC 3339, with `Z` for UTC.
+    pub fn to_rfc3339(self, date: &DateTime<Utc>) -> String {
+        match self {
+            Zone::Utc => date.to_rfc3339_opts(SecondsFormat::Secs, true),
+            Zone::Local => date
+                .with_timezone(&Local)
+                .to_rfc3339_opts(SecondsFormat::Secs, true),
+            Zone::Fixed(offset) => date
+                .with_timezone(&offset)
+                .to_rfc3339_opts(SecondsFormat::Secs, true),
+            Zone::Named(zone) => date
+                .with_timezone(&zone)
+                .to_rfc3339_opts(SecondsFormat::Secs, true),
+        }
+    }
+}
+
+/// The name used in the command line, like `utc`, `+02:00` or `Europe/Paris`.
+impl fmt::Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Zone::Utc => write!(f, "utc"),
+            Zone::Local => write!(f, "local"),
+            Zone::Fixed(offset) => write!(f, "{}", offset),
+            Zone::Named(zone) => write!(f, "{}", zone.name()),
+        }
+    }
+}
+
+impl FromStr for Zone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "utc" | "z" => Ok(Zone::Utc),
+            "local" => Ok(Zone::Local),
+            _ => s
+                .parse::<FixedOffset>()
+                .map(Zone::Fixed)
+                .or_else(|_| s.parse::<Tz>().map(Zone::Named))
+                .map_err(|_| {
+                    format!(
+                        "Unknown time zone '{}', use utc, local, an offset like +02:00 or a name like Europe/Paris.",
+                        s
+                    )
+                }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn formats_dates_in_the_zone() {
+        let date = Utc.with_ymd_and_hms(2021, 1, 3, 23, 10, 0).unwrap();
+        assert_eq!("2021-01-03T23:10:00Z", Zone::Utc.to_rfc3339(&date));
+        let zone = "-03:00".parse::<Zone>().unwrap();
+        assert_eq!("2021-01-03T20:10:00-03:00", zone.to_rfc3339(&date));
+        let zone = "+05:30".parse::<Zone>().unwrap();
+        assert_eq!("2021/01/04 04:40", zone.format(&date, "%Y/%m/%d %H:%M"));
+        assert_eq!("UTC+05:30", zone.label(&date));
+        assert_eq!("UTC", Zone::Utc.label(&date));
+        assert_eq!(Zone::Utc, "UTC".parse::<Zone>().unwrap());
+        assert_eq!(Zone::Local, "local".parse::<Zone>().unwrap());
+        let zone = "America/Sao_Paulo".parse::<Zone>().unwrap();
+        assert_eq!("2021-01-03T20:10:00-03:00", zone.to_rfc3339(&date));
+        assert_eq!("America/Sao_Paulo", zone.to_string());
+        let summer = Utc.with_ymd_and_hms(2021, 7, 3, 23, 10, 0).unwrap();
+        let zone = "Europe/Paris".parse::<Zone>().unwrap();
+        assert_eq!("UTC+01:00", zone.label(&date));
+        assert_eq!("UTC+02:00", zone.label(&summer));
+        assert!("Mars/Olympus_Mons".parse::<Zone>().is_err());
+    }
+}
@@ -0,0 +1,98 @@
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+
+/// The unit of the speeds in rows of the summary file without a `speeds_unit`,
+/// written by versions that converted bytes to bits with `8 / 1024 / 1024`.
+pub const LEGACY_UNIT: Unit = Unit::Mibps;
+
+/// The unit of the speeds written to the summary file.
+pub const STORED_UNIT: Unit = Unit::Mbps;
+
+/// A bandwidth unit. Ookla's CLI reports bandwidths in bytes per second.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Unit {
+    /// Megabits per second (1,000,000 bits per second), as used by ISPs and speedtest.net.
+    Mbps,
+    /// Mebibits per second (1,048,576 bits per second).
+    Mibps,
+}
+
+impl Unit {
+    fn bits_per_second(self) -> f64 {
+        match self {
+            Unit::Mbps => 1_000_000.0,
+            Unit::Mibps => 1_048_576.0,
+        }
+    }
+
+    /// Converts a bandwidth in bytes per second to this unit.
+    pub fn from_bytes_per_second(self, bytes_per_second: f64) -> f64 {
+        bytes_per_second * 8.0 / self.bits_per_second()
+    }
+
+    /// Converts a bandwidth in this unit to another unit.
+    pub fn convert(self, value: f64, to: Unit) -> f64 {
+        value * self.bits_per_second() / to.bits_per_second()
+    }
+
+    /// The symbol shown next to values, like `Mbps`.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Unit::Mbps => "Mbps",
+            Unit::Mibps => "Mibps",
+        }
+    }
+}
+
+/// The name used in the command line and in the summary file, like `mbps`.
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Unit::Mbps => "mbps",
+            Unit::Mibps => "mibps",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+impl FromStr for Unit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mbps" => Ok(Unit::Mbps),
+            "mibps" => Ok(Unit::Mibps),
+            _ => Err(format!("Unknown unit '{}', use mbps or mibps.", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn converts_bytes_per_second() {
+        assert_eq!(8.0, Unit::Mbps.from_bytes_per_second(1_000_000.0));
+        assert_eq!(8.0, Unit::Mibps.from_bytes_per_second(1_048_576.0));
+    }
+
+    #[test]
+    fn converts_between_units() {
+        assert_eq!(104.8576, Unit::Mibps.convert(100.0, Unit::Mbps));
+        assert_eq!(100.0, Unit::Mbps.convert(104.8576, Unit::Mibps));
+        assert_eq!(100.0, Unit::Mbps.convert(100.0, Unit::Mbps));
+    }
+
+    #[test]
+    fn parses_and_displays_units() {
+        for unit in [Unit::Mbps, Unit::Mibps] {
+            assert_eq!(Ok(unit), unit.to_string().parse());
+        }
+        assert_eq!(Ok(Unit::Mbps), "Mbps".parse());
+        assert!("kbps".parse::<Unit>().is_err());
+    }
+}